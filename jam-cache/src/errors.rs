@@ -14,9 +14,7 @@ impl JamCacheError {
 
 impl Display for JamCacheError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        match *self {
-            _ => write!(f, "{}", self.message),
-        }
+        write!(f, "{}", self.message)
     }
 }
 
@@ -25,3 +23,9 @@ impl From<io::Error> for JamCacheError {
         JamCacheError::new(error.to_string())
     }
 }
+
+impl From<reqwest::Error> for JamCacheError {
+    fn from(error: reqwest::Error) -> Self {
+        JamCacheError::new(error.to_string())
+    }
+}
@@ -0,0 +1,309 @@
+use crate::errors::JamCacheError;
+use crate::{Cache, CacheLimits, CacheLock, CacheStats, CacheWriter, CachedValue};
+use async_trait::async_trait;
+use filetime::FileTime;
+use jam_common::lock::FileLock;
+use jam_common::{decode_key, encode_key, sanitize_package_name};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const LOCKS_DIR_NAME: &str = ".locks";
+const PARTIAL_FILE_SUFFIX: &str = ".partial";
+
+// Limits are enforced again once this share of the max size was written since the last check
+const EVICTION_WRITE_RATIO: u64 = 10;
+
+static NEXT_WRITER_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+pub struct FsCache {
+    cache_dir: PathBuf,
+    limits: CacheLimits,
+    unchecked_bytes: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    // None for files that don't hold a value, like legacy entries and uncommitted writes
+    pub key: Option<String>,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub partial: bool,
+}
+
+pub struct FsCacheWriter {
+    cache: FsCache,
+    file: File,
+    size: u64,
+    temp_path: PathBuf,
+    key_path: PathBuf,
+    committed: bool,
+}
+
+impl FsCache {
+    pub fn new(cache_dir: PathBuf, limits: CacheLimits) -> Result<FsCache, JamCacheError> {
+        fs::create_dir_all(&cache_dir)?;
+
+        let cache = FsCache {
+            cache_dir,
+            limits,
+            unchecked_bytes: Arc::new(AtomicU64::new(0)),
+        };
+
+        if cache.limits != CacheLimits::default() {
+            cache.evict()?;
+        }
+
+        Ok(cache)
+    }
+
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        let key_path = self.key_path(key);
+
+        let found = if key_path.exists() {
+            Some(key_path)
+        } else {
+            self.migrate_legacy_entry(key, key_path)
+        };
+
+        // The modification time doubles as the last access time for eviction, access times
+        // are often not kept by the filesystem
+        if let Some(path) = &found {
+            let _ = filetime::set_file_mtime(path, FileTime::now());
+        }
+
+        found
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<PathBuf, JamCacheError> {
+        let mut writer = self.writer(key)?;
+
+        writer.write_all(value)?;
+
+        writer.commit()
+    }
+
+    // Streams a value into a temporary file that only becomes visible to `get` once committed
+    pub fn writer(&self, key: &str) -> Result<FsCacheWriter, JamCacheError> {
+        let key_name = encode_key(key);
+        let temp_path = self.cache_dir.join(format!(
+            ".{}.{}.{}{}",
+            key_name,
+            process::id(),
+            NEXT_WRITER_ID.fetch_add(1, Ordering::Relaxed),
+            PARTIAL_FILE_SUFFIX
+        ));
+
+        Ok(FsCacheWriter {
+            cache: self.clone(),
+            file: File::create(&temp_path)?,
+            size: 0,
+            temp_path,
+            key_path: self.cache_dir.join(key_name),
+            committed: false,
+        })
+    }
+
+    // Blocks until no other process (or caller in this process) holds the lock for the key
+    pub fn lock(&self, key: &str) -> Result<FileLock, JamCacheError> {
+        let lock_path = self
+            .cache_dir
+            .join(LOCKS_DIR_NAME)
+            .join(format!("{}.lock", encode_key(key)));
+
+        Ok(FileLock::acquire(&lock_path)?)
+    }
+
+    // Lists every file in the cache, lock files excluded
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, JamCacheError> {
+        let mut entries = vec![];
+
+        for entry in fs::read_dir(&self.cache_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let partial = file_name.starts_with('.') && file_name.ends_with(PARTIAL_FILE_SUFFIX);
+
+            entries.push(CacheEntry {
+                key: if partial {
+                    None
+                } else {
+                    decode_key(&file_name)
+                },
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+                partial,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    // Removes entries older than the max age, then the least recently used ones until
+    // the cache fits in its max size
+    pub fn evict(&self) -> Result<CacheStats, JamCacheError> {
+        self.unchecked_bytes.store(0, Ordering::Relaxed);
+
+        let mut entries: Vec<CacheEntry> = self
+            .entries()?
+            .into_iter()
+            .filter(|entry| !entry.partial)
+            .collect();
+        entries.sort_by_key(|entry| entry.modified);
+
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let expires_at = self
+            .limits
+            .max_age
+            .and_then(|max_age| SystemTime::now().checked_sub(max_age));
+        let mut stats = CacheStats::default();
+
+        let last_index = entries.len().saturating_sub(1);
+        for (index, entry) in entries.into_iter().enumerate() {
+            let expired = expires_at.is_some_and(|expires_at| entry.modified < expires_at);
+            // The most recent entry is kept even when it's larger than the cache on its own,
+            // it was most likely just written and is about to be used
+            let oversized =
+                index < last_index && self.limits.max_size.is_some_and(|max_size| size > max_size);
+
+            if !expired && !oversized {
+                break;
+            }
+
+            remove_entry(&entry, &mut stats)?;
+            size -= entry.size;
+        }
+
+        Ok(stats)
+    }
+
+    // Removes every committed entry, writes in progress are left alone
+    pub fn clear(&self) -> Result<CacheStats, JamCacheError> {
+        let mut stats = CacheStats::default();
+
+        for entry in self.entries()? {
+            if !entry.partial {
+                remove_entry(&entry, &mut stats)?;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn record_write(&self, size: u64) -> Result<(), JamCacheError> {
+        if let Some(max_size) = self.limits.max_size {
+            let unchecked_bytes = self.unchecked_bytes.fetch_add(size, Ordering::Relaxed) + size;
+
+            if unchecked_bytes >= max_size / EVICTION_WRITE_RATIO {
+                self.evict()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn key_path(&self, key: &str) -> PathBuf {
+        self.cache_dir.join(encode_key(key))
+    }
+
+    // Entries written before keys were encoded are moved to their encoded path on first access
+    fn migrate_legacy_entry(&self, key: &str, key_path: PathBuf) -> Option<PathBuf> {
+        let legacy_path = self.cache_dir.join(sanitize_package_name(key));
+
+        if legacy_path != key_path
+            && legacy_path.is_file()
+            && fs::rename(&legacy_path, &key_path).is_ok()
+        {
+            Some(key_path)
+        } else {
+            None
+        }
+    }
+}
+
+// Another process may have removed the entry already
+fn remove_entry(entry: &CacheEntry, stats: &mut CacheStats) -> Result<(), JamCacheError> {
+    match fs::remove_file(&entry.path) {
+        Ok(()) => {
+            stats.files += 1;
+            stats.bytes += entry.size;
+
+            Ok(())
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl FsCacheWriter {
+    pub fn commit(mut self) -> Result<PathBuf, JamCacheError> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, &self.key_path)?;
+        self.committed = true;
+
+        self.cache.record_write(self.size)?;
+
+        Ok(self.key_path.clone())
+    }
+}
+
+impl Write for FsCacheWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for FsCacheWriter {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+#[async_trait]
+impl Cache for FsCache {
+    async fn get(&self, key: &str) -> Result<Option<CachedValue>, JamCacheError> {
+        Ok(FsCache::get(self, key).map(CachedValue::File))
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), JamCacheError> {
+        FsCache::set(self, key, value).map(|_| ())
+    }
+
+    fn writer(&self, key: &str) -> Result<Box<dyn CacheWriter>, JamCacheError> {
+        Ok(Box::new(FsCache::writer(self, key)?))
+    }
+
+    fn lock(&self, key: &str) -> Result<CacheLock, JamCacheError> {
+        Ok(Box::new(FsCache::lock(self, key)?))
+    }
+}
+
+#[async_trait]
+impl CacheWriter for FsCacheWriter {
+    async fn commit(self: Box<Self>) -> Result<(), JamCacheError> {
+        FsCacheWriter::commit(*self).map(|_| ())
+    }
+}
@@ -1,50 +1,140 @@
 pub mod errors;
 
+mod fs_cache;
+mod memory_cache;
+mod remote_cache;
+
+pub use fs_cache::{CacheEntry, FsCache, FsCacheWriter};
+pub use memory_cache::MemoryCache;
+pub use remote_cache::RemoteCache;
+
+use async_trait::async_trait;
 use errors::JamCacheError;
-use jam_common::sanitize_package_name;
-use std::fs;
+use reqwest::Client;
+use std::collections::HashMap;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-pub struct CacheFactory {
-    cache_dir: PathBuf,
+// Held until the caller is done populating the key
+pub type CacheLock = Box<dyn Send>;
+
+#[async_trait]
+pub trait Cache: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<CachedValue>, JamCacheError>;
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), JamCacheError>;
+
+    // Values written through the writer only become visible to `get` once committed
+    fn writer(&self, key: &str) -> Result<Box<dyn CacheWriter>, JamCacheError>;
+
+    // Blocks until no other caller holds the lock for the key
+    fn lock(&self, key: &str) -> Result<CacheLock, JamCacheError>;
 }
 
-pub struct Cache {
-    cache_dir: PathBuf,
+#[async_trait]
+pub trait CacheWriter: Write + Send {
+    async fn commit(self: Box<Self>) -> Result<(), JamCacheError>;
 }
 
-impl CacheFactory {
-    pub fn new(cache_dir: PathBuf) -> CacheFactory {
-        CacheFactory { cache_dir }
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachedValue {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl CachedValue {
+    // Files are streamed instead of being read into memory
+    pub fn reader(&self) -> io::Result<Box<dyn Read + Send + '_>> {
+        match self {
+            CachedValue::File(path) => Ok(Box::new(File::open(path)?)),
+            CachedValue::Bytes(bytes) => Ok(Box::new(bytes.as_slice())),
+        }
     }
+}
 
-    pub fn create_cache(&self, cache_name: &str) -> Result<Cache, JamCacheError> {
-        let cache_dir = self.cache_dir.join(cache_name);
-        fs::create_dir_all(&cache_dir)?;
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum CacheBackend {
+    #[default]
+    Filesystem,
+    Memory,
+    // Values are read and written with GET and PUT requests under `<url>/<cache name>/`
+    Remote(String),
+}
 
-        Ok(Cache { cache_dir })
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CacheLimits {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
 }
 
-impl Cache {
-    pub fn get(&self, key: &str) -> Option<PathBuf> {
-        let key_path = self.cache_dir.join(sanitize_package_name(key));
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CacheStats {
+    pub files: u64,
+    pub bytes: u64,
+}
 
-        if key_path.exists() {
-            Some(key_path)
-        } else {
-            None
-        }
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CacheOptions {
+    pub backend: CacheBackend,
+    // Keyed by cache name, caches without limits grow unbounded. Only the filesystem backend
+    // enforces them.
+    pub limits: HashMap<String, CacheLimits>,
+}
+
+pub struct CacheFactory {
+    cache_dir: PathBuf,
+    options: CacheOptions,
+    client: Client,
+    memory_caches: Mutex<HashMap<String, MemoryCache>>,
+}
+
+impl CacheFactory {
+    pub fn new(cache_dir: PathBuf) -> CacheFactory {
+        CacheFactory::with_options(cache_dir, CacheOptions::default(), Client::new())
     }
 
-    pub fn set(&self, key: &str, value: &[u8]) -> Result<PathBuf, JamCacheError> {
-        let key_path = self.cache_dir.join(sanitize_package_name(key));
-        let mut file = File::create(&key_path)?;
+    // `client` is used by the remote backend
+    pub fn with_options(cache_dir: PathBuf, options: CacheOptions, client: Client) -> CacheFactory {
+        CacheFactory {
+            cache_dir,
+            options,
+            client,
+            memory_caches: Mutex::new(HashMap::new()),
+        }
+    }
 
-        file.write_all(value)?;
+    pub fn create_cache(&self, cache_name: &str) -> Result<Arc<dyn Cache>, JamCacheError> {
+        match &self.options.backend {
+            CacheBackend::Filesystem => Ok(Arc::new(self.create_fs_cache(cache_name)?)),
+            // Caches of the same name share their values, like they do on disk
+            CacheBackend::Memory => Ok(Arc::new(
+                self.memory_caches
+                    .lock()
+                    .unwrap()
+                    .entry(cache_name.to_string())
+                    .or_default()
+                    .clone(),
+            )),
+            CacheBackend::Remote(url) => Ok(Arc::new(RemoteCache::new(
+                &format!("{}/{}", url.trim_end_matches('/'), cache_name),
+                self.client.clone(),
+            ))),
+        }
+    }
 
-        Ok(key_path)
+    // The local cache directory, regardless of the configured backend
+    pub fn create_fs_cache(&self, cache_name: &str) -> Result<FsCache, JamCacheError> {
+        FsCache::new(
+            self.cache_dir.join(cache_name),
+            self.options
+                .limits
+                .get(cache_name)
+                .copied()
+                .unwrap_or_default(),
+        )
     }
 }
@@ -0,0 +1,106 @@
+use crate::errors::JamCacheError;
+use crate::{Cache, CacheLock, CacheWriter, CachedValue};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::prelude::*;
+use std::sync::{Arc, Condvar, Mutex};
+
+// Keeps values in memory, so tests and throwaway installs don't touch the disk
+#[derive(Clone, Default)]
+pub struct MemoryCache {
+    values: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    locks: Arc<KeyLocks>,
+}
+
+#[derive(Default)]
+struct KeyLocks {
+    locked: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+struct MemoryCacheLock {
+    locks: Arc<KeyLocks>,
+    key: String,
+}
+
+pub struct MemoryCacheWriter {
+    values: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    key: String,
+    value: Vec<u8>,
+}
+
+impl MemoryCache {
+    pub fn new() -> MemoryCache {
+        MemoryCache::default()
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn get(&self, key: &str) -> Result<Option<CachedValue>, JamCacheError> {
+        Ok(self
+            .values
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|value| CachedValue::Bytes(value.clone())))
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), JamCacheError> {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_vec());
+
+        Ok(())
+    }
+
+    fn writer(&self, key: &str) -> Result<Box<dyn CacheWriter>, JamCacheError> {
+        Ok(Box::new(MemoryCacheWriter {
+            values: self.values.clone(),
+            key: key.to_string(),
+            value: vec![],
+        }))
+    }
+
+    fn lock(&self, key: &str) -> Result<CacheLock, JamCacheError> {
+        let mut locked = self.locks.locked.lock().unwrap();
+
+        while locked.contains(key) {
+            locked = self.locks.released.wait(locked).unwrap();
+        }
+        locked.insert(key.to_string());
+
+        Ok(Box::new(MemoryCacheLock {
+            locks: self.locks.clone(),
+            key: key.to_string(),
+        }))
+    }
+}
+
+impl Drop for MemoryCacheLock {
+    fn drop(&mut self) {
+        self.locks.locked.lock().unwrap().remove(&self.key);
+        self.locks.released.notify_all();
+    }
+}
+
+#[async_trait]
+impl CacheWriter for MemoryCacheWriter {
+    async fn commit(self: Box<Self>) -> Result<(), JamCacheError> {
+        self.values.lock().unwrap().insert(self.key, self.value);
+
+        Ok(())
+    }
+}
+
+impl Write for MemoryCacheWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.value.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::errors::JamCacheError;
+use crate::{Cache, CacheLock, CacheWriter, CachedValue};
+use async_trait::async_trait;
+use jam_common::encode_key;
+use reqwest::{Client, StatusCode};
+use std::io;
+use std::io::prelude::*;
+
+// Shares values between machines through a plain HTTP server, `GET` returns 404 for missing
+// keys and `PUT` stores the request body
+#[derive(Clone)]
+pub struct RemoteCache {
+    client: Client,
+    url: String,
+}
+
+pub struct RemoteCacheWriter {
+    cache: RemoteCache,
+    key: String,
+    value: Vec<u8>,
+}
+
+impl RemoteCache {
+    // The client carries the proxy and certificate settings of the registry requests
+    pub fn new(url: &str, client: Client) -> RemoteCache {
+        RemoteCache {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn key_url(&self, key: &str) -> String {
+        format!("{}/{}", self.url, encode_key(key))
+    }
+}
+
+#[async_trait]
+impl Cache for RemoteCache {
+    async fn get(&self, key: &str) -> Result<Option<CachedValue>, JamCacheError> {
+        let response = self.client.get(self.key_url(key)).send().await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let value = response.error_for_status()?.bytes().await?;
+
+        Ok(Some(CachedValue::Bytes(value.to_vec())))
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<(), JamCacheError> {
+        self.client
+            .put(self.key_url(key))
+            .body(value.to_vec())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    // Values are buffered and uploaded in a single request on commit
+    fn writer(&self, key: &str) -> Result<Box<dyn CacheWriter>, JamCacheError> {
+        Ok(Box::new(RemoteCacheWriter {
+            cache: self.clone(),
+            key: key.to_string(),
+            value: vec![],
+        }))
+    }
+
+    // Writes of the same key store the same value, so concurrent writers only waste a request
+    fn lock(&self, _key: &str) -> Result<CacheLock, JamCacheError> {
+        Ok(Box::new(()))
+    }
+}
+
+#[async_trait]
+impl CacheWriter for RemoteCacheWriter {
+    async fn commit(self: Box<Self>) -> Result<(), JamCacheError> {
+        self.cache.set(&self.key, &self.value).await
+    }
+}
+
+impl Write for RemoteCacheWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.value.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use httpmock::Method::{GET, PUT};
+use httpmock::MockServer;
+use jam_cache::{
+    Cache, CacheBackend, CacheFactory, CacheOptions, CachedValue, MemoryCache, RemoteCache,
+};
+use reqwest::Client;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn memory_cache_factory() -> CacheFactory {
+    CacheFactory::with_options(
+        PathBuf::from("/nonexistent"),
+        CacheOptions {
+            backend: CacheBackend::Memory,
+            ..CacheOptions::default()
+        },
+        Client::new(),
+    )
+}
+
+#[tokio::test]
+async fn test_memory_cache_set_and_get() {
+    let cache = MemoryCache::new();
+
+    assert_eq!(cache.get("key").await.unwrap(), None);
+
+    cache.set("key", b"something").await.unwrap();
+
+    assert_eq!(
+        cache.get("key").await.unwrap(),
+        Some(CachedValue::Bytes(b"something".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn test_memory_cache_writer_is_visible_after_commit() {
+    let cache = MemoryCache::new();
+
+    let mut writer = cache.writer("key").unwrap();
+    writer.write_all(b"some").unwrap();
+    writer.write_all(b"thing").unwrap();
+
+    assert_eq!(cache.get("key").await.unwrap(), None);
+
+    writer.commit().await.unwrap();
+
+    assert_eq!(
+        cache.get("key").await.unwrap(),
+        Some(CachedValue::Bytes(b"something".to_vec()))
+    );
+}
+
+#[test]
+fn test_memory_cache_lock_waits_for_release() {
+    let cache = MemoryCache::new();
+    let released = Arc::new(AtomicBool::new(false));
+
+    let lock = cache.lock("key").unwrap();
+
+    let waiter = {
+        let cache = cache.clone();
+        let released = released.clone();
+
+        thread::spawn(move || {
+            let _lock = cache.lock("key").unwrap();
+
+            assert!(released.load(Ordering::SeqCst));
+        })
+    };
+
+    // Other keys are not blocked
+    drop(cache.lock("other").unwrap());
+
+    thread::sleep(Duration::from_millis(50));
+    released.store(true, Ordering::SeqCst);
+    drop(lock);
+
+    waiter.join().unwrap();
+}
+
+#[tokio::test]
+async fn test_memory_caches_of_the_same_name_share_values() {
+    let cache_factory = memory_cache_factory();
+
+    cache_factory
+        .create_cache("tarballs")
+        .unwrap()
+        .set("key", b"something")
+        .await
+        .unwrap();
+
+    assert!(cache_factory
+        .create_cache("tarballs")
+        .unwrap()
+        .get("key")
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        cache_factory
+            .create_cache("metadata")
+            .unwrap()
+            .get("key")
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_remote_cache_returns_none_for_missing_keys() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET).path("/tarballs/p1@1.0.0");
+        then.status(404);
+    });
+
+    let cache = CacheFactory::with_options(
+        PathBuf::from("/nonexistent"),
+        CacheOptions {
+            backend: CacheBackend::Remote(server.base_url()),
+            ..CacheOptions::default()
+        },
+        Client::new(),
+    )
+    .create_cache("tarballs")
+    .unwrap();
+
+    assert_eq!(cache.get("p1@1.0.0").await.unwrap(), None);
+    mock.assert();
+}
+
+#[tokio::test]
+async fn test_remote_cache_gets_values() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(GET).path("/tarballs/p1@1.0.0");
+        then.status(200).body("something");
+    });
+
+    let cache = RemoteCache::new(&format!("{}/tarballs/", server.base_url()), Client::new());
+
+    assert_eq!(
+        cache.get("p1@1.0.0").await.unwrap(),
+        Some(CachedValue::Bytes(b"something".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn test_remote_cache_uploads_committed_values() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(PUT)
+            .path("/tarballs/p1@1.0.0")
+            .body("something");
+        then.status(201);
+    });
+
+    let cache = RemoteCache::new(&format!("{}/tarballs", server.base_url()), Client::new());
+
+    let mut writer = cache.writer("p1@1.0.0").unwrap();
+    writer.write_all(b"something").unwrap();
+    writer.commit().await.unwrap();
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn test_remote_cache_fails_on_server_errors() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(GET).path("/tarballs/p1@1.0.0");
+        then.status(500);
+    });
+
+    let cache = RemoteCache::new(&format!("{}/tarballs", server.base_url()), Client::new());
+
+    assert!(cache.get("p1@1.0.0").await.is_err());
+}
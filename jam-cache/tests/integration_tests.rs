@@ -1,10 +1,14 @@
-use jam_cache::{Cache, CacheFactory};
+use jam_cache::{CacheFactory, CacheLimits, CacheOptions, CacheStats, FsCache};
 use jam_test_utils::sync_helpers::with_tmp_dir;
+use reqwest::Client;
+use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
-fn create_cache(dir: PathBuf) -> Cache {
+fn create_cache(dir: PathBuf) -> FsCache {
     let cache_factory = CacheFactory::new(dir);
-    cache_factory.create_cache("unit_tests").unwrap()
+    cache_factory.create_fs_cache("unit_tests").unwrap()
 }
 
 #[test]
@@ -36,6 +40,204 @@ fn test_cache_key_sanitization() {
 
         let path = cache.get("@scope/a").unwrap();
 
-        assert!(path.to_str().unwrap().contains("@scope_a"));
+        assert!(path.to_str().unwrap().ends_with("@scope%2Fa"));
+    })
+}
+
+#[test]
+fn test_cache_keys_do_not_collide() {
+    with_tmp_dir(|path| {
+        let cache = create_cache(path);
+
+        cache.set("@scope/a", "scoped".as_bytes()).unwrap();
+        cache.set("@scope_a", "unscoped".as_bytes()).unwrap();
+        cache.set("Package", "upper".as_bytes()).unwrap();
+        cache.set("package", "lower".as_bytes()).unwrap();
+
+        let read = |key: &str| fs::read_to_string(cache.get(key).unwrap()).unwrap();
+
+        assert_eq!(read("@scope/a"), "scoped");
+        assert_eq!(read("@scope_a"), "unscoped");
+        assert_eq!(read("Package"), "upper");
+        assert_eq!(read("package"), "lower");
+    })
+}
+
+#[test]
+fn test_cache_migrates_legacy_entries() {
+    with_tmp_dir(|path| {
+        let cache = create_cache(path.clone());
+        let legacy_path = path.join("unit_tests").join("@scope_a@1.0.0");
+        fs::write(&legacy_path, "something").unwrap();
+
+        let key_path = cache.get("@scope/a@1.0.0").unwrap();
+
+        assert!(key_path.ends_with("@scope%2Fa@1.0.0"));
+        assert_eq!(fs::read_to_string(key_path).unwrap(), "something");
+        assert!(!legacy_path.exists());
+    })
+}
+
+#[test]
+fn test_cache_writer_commit() {
+    with_tmp_dir(|path| {
+        let cache = create_cache(path);
+
+        let mut writer = cache.writer("package").unwrap();
+        writer.write_all("some".as_bytes()).unwrap();
+        writer.write_all("thing".as_bytes()).unwrap();
+
+        assert_eq!(cache.get("package"), None);
+
+        let key_path = writer.commit().unwrap();
+
+        assert_eq!(cache.get("package"), Some(key_path.clone()));
+        assert_eq!(fs::read_to_string(key_path).unwrap(), "something");
+    })
+}
+
+#[test]
+fn test_cache_writer_discarded_without_commit() {
+    with_tmp_dir(|path| {
+        let cache = create_cache(path.clone());
+
+        let mut writer = cache.writer("package").unwrap();
+        writer.write_all("something".as_bytes()).unwrap();
+        drop(writer);
+
+        assert_eq!(cache.get("package"), None);
+        assert_eq!(fs::read_dir(path.join("unit_tests")).unwrap().count(), 0);
+    })
+}
+
+#[test]
+fn test_cache_entries() {
+    with_tmp_dir(|path| {
+        let cache = create_cache(path);
+
+        cache.set("@scope/a", "scoped".as_bytes()).unwrap();
+        let _lock = cache.lock("@scope/a").unwrap();
+        let mut writer = cache.writer("b").unwrap();
+        writer.write_all("partial".as_bytes()).unwrap();
+
+        let mut entries = cache.entries().unwrap();
+        entries.sort_by_key(|entry| entry.partial);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, Some("@scope/a".to_string()));
+        assert_eq!(entries[0].size, 6);
+        assert!(!entries[0].partial);
+        assert_eq!(entries[1].key, None);
+        assert!(entries[1].partial);
+    })
+}
+
+fn create_limited_cache(dir: PathBuf, limits: CacheLimits) -> FsCache {
+    let cache_factory = CacheFactory::with_options(
+        dir,
+        CacheOptions {
+            limits: vec![("unit_tests".to_string(), limits)]
+                .into_iter()
+                .collect(),
+            ..CacheOptions::default()
+        },
+        Client::new(),
+    );
+    cache_factory.create_fs_cache("unit_tests").unwrap()
+}
+
+fn set_modified(cache: &FsCache, key: &str, seconds_ago: u64) {
+    let modified = SystemTime::now() - Duration::from_secs(seconds_ago);
+
+    filetime::set_file_mtime(
+        cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .find(|entry| entry.key.as_deref() == Some(key))
+            .unwrap()
+            .path,
+        filetime::FileTime::from_system_time(modified),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_cache_evicts_least_recently_used_entries() {
+    with_tmp_dir(|path| {
+        let cache = create_limited_cache(
+            path,
+            CacheLimits {
+                max_size: Some(20),
+                max_age: None,
+            },
+        );
+
+        cache.set("a", &[0; 8]).unwrap();
+        cache.set("b", &[0; 8]).unwrap();
+        set_modified(&cache, "a", 20);
+        set_modified(&cache, "b", 10);
+        cache.get("a").unwrap();
+
+        cache.set("c", &[0; 8]).unwrap();
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    })
+}
+
+#[test]
+fn test_cache_keeps_the_latest_entry_when_it_exceeds_the_max_size() {
+    with_tmp_dir(|path| {
+        let cache = create_limited_cache(
+            path,
+            CacheLimits {
+                max_size: Some(4),
+                max_age: None,
+            },
+        );
+
+        cache.set("a", &[0; 8]).unwrap();
+        set_modified(&cache, "a", 10);
+        cache.set("b", &[0; 8]).unwrap();
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+    })
+}
+
+#[test]
+fn test_cache_evicts_expired_entries_on_creation() {
+    with_tmp_dir(|path| {
+        let cache = create_cache(path.clone());
+        cache.set("old", "old".as_bytes()).unwrap();
+        cache.set("new", "new".as_bytes()).unwrap();
+        set_modified(&cache, "old", 60 * 60);
+
+        let cache = create_limited_cache(
+            path,
+            CacheLimits {
+                max_size: None,
+                max_age: Some(Duration::from_secs(60)),
+            },
+        );
+
+        assert!(cache.get("old").is_none());
+        assert!(cache.get("new").is_some());
+    })
+}
+
+#[test]
+fn test_cache_clear() {
+    with_tmp_dir(|path| {
+        let cache = create_cache(path);
+        cache.set("a", "abc".as_bytes()).unwrap();
+        cache.set("b", "de".as_bytes()).unwrap();
+
+        let stats = cache.clear().unwrap();
+
+        assert_eq!(stats, CacheStats { files: 2, bytes: 5 });
+        assert_eq!(cache.entries().unwrap(), vec![]);
     })
 }
@@ -0,0 +1,77 @@
+use jam_cache::CacheFactory;
+use jam_test_utils::sync_helpers::with_tmp_dir;
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+const CHILD_CACHE_DIR_ENV: &str = "JAM_CACHE_LOCKING_TESTS_CHILD_DIR";
+const PROCESSES: usize = 4;
+
+// Runs in every child process: populate the entry unless another process already did
+fn populate_cache_entry(dir: &Path) {
+    let cache = CacheFactory::new(dir.to_path_buf())
+        .create_fs_cache("locking_tests")
+        .unwrap();
+
+    let _lock = cache.lock("@scope/package@1.0.0").unwrap();
+
+    if cache.get("@scope/package@1.0.0").is_none() {
+        let mut writes = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("writes.log"))
+            .unwrap();
+        writeln!(writes, "{}", std::process::id()).unwrap();
+
+        // Gives the other processes time to race on the entry if locking is broken
+        thread::sleep(Duration::from_millis(200));
+
+        cache
+            .set("@scope/package@1.0.0", "something".as_bytes())
+            .unwrap();
+    }
+}
+
+#[test]
+fn concurrent_processes_populate_an_entry_once() {
+    if let Ok(dir) = env::var(CHILD_CACHE_DIR_ENV) {
+        populate_cache_entry(&PathBuf::from(dir));
+        return;
+    }
+
+    with_tmp_dir(|path| {
+        let children: Vec<_> = (0..PROCESSES)
+            .map(|_| {
+                Command::new(env::current_exe().unwrap())
+                    .args([
+                        "--exact",
+                        "concurrent_processes_populate_an_entry_once",
+                        "--test-threads=1",
+                    ])
+                    .env(CHILD_CACHE_DIR_ENV, &path)
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+
+        let writes = fs::read_to_string(path.join("writes.log")).unwrap();
+        let cache = CacheFactory::new(path)
+            .create_fs_cache("locking_tests")
+            .unwrap();
+
+        assert_eq!(writes.lines().count(), 1);
+        assert_eq!(
+            fs::read_to_string(cache.get("@scope/package@1.0.0").unwrap()).unwrap(),
+            "something"
+        );
+    })
+}
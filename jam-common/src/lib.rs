@@ -1,10 +1,56 @@
+pub mod lock;
+
 use jam_npm_metadata::NpmBinMetadata;
 use std::collections::HashMap;
 
+// Layout used before keys were encoded, only kept to recognize old cache and store entries
 pub fn sanitize_package_name(package_name: &str) -> String {
     package_name.replace("/", "_")
 }
 
+// Keys are used as file names, so anything but lowercase letters, digits and `-._@` is escaped as `%XX`.
+// Escaping upper case letters keeps keys that only differ in case apart on case-insensitive filesystems,
+// and a leading `.` is escaped so encoded keys never clash with hidden or temporary files.
+pub fn encode_key(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+
+    for (index, byte) in key.bytes().enumerate() {
+        match byte {
+            b'.' if index == 0 => encoded.push_str("%2E"),
+            b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'@' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+pub fn decode_key(encoded: &str) -> Option<String> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = encoded.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    let key = String::from_utf8(decoded).ok()?;
+
+    // Only the canonical form is accepted, so every file name maps to at most one key
+    if encode_key(&key) == encoded {
+        Some(key)
+    } else {
+        None
+    }
+}
+
 pub fn extract_binaries(
     package_name: &str,
     bin: &Option<NpmBinMetadata>,
@@ -38,12 +84,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn encodes_keys_reversibly() {
+        let keys = vec![
+            "lodash",
+            "lodash@4.17.21",
+            "@scope/name@1.0.0-RC.1+build",
+            "JSONStream",
+            "string_decoder",
+            ".hidden",
+            "100%",
+        ];
+
+        for key in keys {
+            assert_eq!(decode_key(&encode_key(key)), Some(key.to_string()));
+        }
+    }
+
+    #[test]
+    fn encodes_keys_without_collisions() {
+        assert_eq!(encode_key("@a/b"), "@a%2Fb");
+        assert_ne!(encode_key("@a/b"), encode_key("@a_b"));
+        assert_ne!(
+            encode_key("JSONStream").to_lowercase(),
+            encode_key("jsonstream").to_lowercase()
+        );
+    }
+
+    #[test]
+    fn keeps_simple_keys_readable() {
+        assert_eq!(encode_key("lodash@4.17.21"), "lodash@4.17.21");
+    }
+
+    #[test]
+    fn encoded_keys_never_start_with_a_dot() {
+        assert_eq!(encode_key(".bin"), "%2Ebin");
+        assert_eq!(encode_key(".."), "%2E.");
+    }
+
+    #[test]
+    fn rejects_non_canonical_file_names() {
+        assert_eq!(decode_key("@scope%2fname"), None);
+        assert_eq!(decode_key("Lodash"), None);
+        assert_eq!(decode_key("broken%2"), None);
+        assert_eq!(decode_key(".partial"), None);
+    }
+
     #[test]
     fn extract_binaries_none() {
         let package_name = "name";
         let bin = None;
 
-        assert_eq!(extract_binaries(&package_name, &bin), hashmap! {});
+        assert_eq!(extract_binaries(package_name, &bin), hashmap! {});
     }
 
     #[test]
@@ -52,7 +144,7 @@ mod tests {
         let bin = Some(NpmBinMetadata::String("./a.js".to_string()));
 
         assert_eq!(
-            extract_binaries(&package_name, &bin),
+            extract_binaries(package_name, &bin),
             hashmap! {
                 package_name.to_string() => "./a.js".to_string(),
             }
@@ -67,6 +159,6 @@ mod tests {
         };
         let bin = Some(NpmBinMetadata::Object(bin_object.clone()));
 
-        assert_eq!(extract_binaries(&package_name, &bin), bin_object);
+        assert_eq!(extract_binaries(package_name, &bin), bin_object);
     }
 }
@@ -0,0 +1,75 @@
+use fs2::FileExt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+// An exclusive advisory lock shared between processes, released on drop or when the holding process exits
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    pub fn acquire(path: &Path) -> io::Result<FileLock> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Lock files are never removed, deleting them would let two processes lock different inodes
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        file.lock_exclusive()?;
+
+        Ok(FileLock { file })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    #[test]
+    fn serializes_lock_holders() {
+        let tmp_dir = TempDir::new("jam-lock").unwrap();
+        let lock_path = tmp_dir.path().join("locks").join("key.lock");
+        let holders = Arc::new(AtomicUsize::new(0));
+        let max_holders = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let lock_path = lock_path.clone();
+                let holders = holders.clone();
+                let max_holders = max_holders.clone();
+
+                thread::spawn(move || {
+                    let _lock = FileLock::acquire(&lock_path).unwrap();
+                    let current = holders.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_holders.fetch_max(current, Ordering::SeqCst);
+
+                    thread::sleep(Duration::from_millis(20));
+                    holders.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(max_holders.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,69 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use jam_core::metadata_format::{decode_metadata, encode_metadata};
+use jam_core::npm::{PackageMetadata, VersionMetadata};
+use std::collections::HashMap;
+
+// Roughly the shape of `@types/node`: well over a thousand versions with a few dependencies each
+const VERSIONS: usize = 1500;
+
+fn create_large_metadata() -> PackageMetadata {
+    let versions = (0..VERSIONS)
+        .map(|index| {
+            let version = format!("{}.{}.{}", index / 100, (index / 10) % 10, index % 10);
+            let dependencies = (0..3)
+                .map(|dependency| {
+                    (
+                        format!("dependency-{}", dependency),
+                        format!("^{}.0.0", dependency),
+                    )
+                })
+                .collect();
+
+            (
+                version.clone(),
+                VersionMetadata {
+                    shasum: format!("{:040x}", index),
+                    tarball: format!(
+                        "https://registry.npmjs.org/@types/node/-/node-{}.tgz",
+                        version
+                    ),
+                    dependencies,
+                    binaries: HashMap::new(),
+                },
+            )
+        })
+        .collect();
+
+    PackageMetadata {
+        package_name: "@types/node".to_string(),
+        dist_tags: vec![("latest".to_string(), "14.9.9".to_string())]
+            .into_iter()
+            .collect(),
+        versions,
+    }
+}
+
+fn load_large_metadata(c: &mut Criterion) {
+    let metadata = create_large_metadata();
+    let json = serde_json::to_vec(&metadata).unwrap();
+    let encoded = encode_metadata(&metadata).unwrap();
+
+    println!(
+        "@types/node sized metadata: {} bytes as JSON, {} bytes encoded",
+        json.len(),
+        encoded.len()
+    );
+
+    c.bench_function("load json metadata", |b| {
+        b.iter(|| serde_json::from_slice::<PackageMetadata>(&json).unwrap())
+    });
+    c.bench_function("load encoded metadata", |b| {
+        b.iter(|| decode_metadata(&mut encoded.as_slice()).unwrap())
+    });
+    c.bench_function("encode metadata", |b| {
+        b.iter(|| encode_metadata(&metadata).unwrap())
+    });
+}
+
+criterion_group!(benches, load_large_metadata);
+criterion_main!(benches);
@@ -9,7 +9,7 @@ impl Collector {
         Collector {}
     }
 
-    pub fn collect(&self, packages: &Vec<Package>) -> HashMap<Dependency, Vec<Package>> {
+    pub fn collect(&self, packages: &[Package]) -> HashMap<Dependency, Vec<Package>> {
         packages.iter().fold(
             HashMap::new(),
             |mut acc: HashMap<Dependency, Vec<Package>>, package| {
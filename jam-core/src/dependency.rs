@@ -9,7 +9,7 @@ pub struct Dependency {
 
 impl Dependency {
     pub fn from_entry(key: &str, value: &str) -> Dependency {
-        match VersionReq::parse_compat(&value, Compat::Npm) {
+        match VersionReq::parse_compat(value, Compat::Npm) {
             Ok(_) => Dependency {
                 name: key.to_string(),
                 real_name: key.to_string(),
@@ -1,22 +1,108 @@
 use jam_cache::errors::JamCacheError;
 use std::fmt::{Display, Error, Formatter};
 use std::io;
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct JamCoreError {
-    pub message: String,
+pub enum JamCoreError {
+    Message(String),
+    PackageNotFound {
+        package_name: String,
+    },
+    Unauthorized {
+        package_name: String,
+    },
+    RateLimited {
+        package_name: String,
+        retry_after: Option<Duration>,
+    },
+    ServerError {
+        package_name: String,
+        status: u16,
+    },
+    UnexpectedStatus {
+        package_name: String,
+        status: u16,
+    },
+    MalformedResponse {
+        package_name: String,
+        reason: String,
+    },
+    Network {
+        package_name: String,
+        reason: String,
+    },
 }
 
 impl JamCoreError {
     pub fn new(message: String) -> JamCoreError {
-        JamCoreError { message }
+        JamCoreError::Message(message)
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            JamCoreError::RateLimited { .. }
+                | JamCoreError::ServerError { .. }
+                | JamCoreError::Network { .. }
+        )
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            JamCoreError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
     }
 }
 
 impl Display for JamCoreError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        match *self {
-            _ => write!(f, "{}", self.message),
+        match self {
+            JamCoreError::Message(message) => write!(f, "{}", message),
+            JamCoreError::PackageNotFound { package_name } => {
+                write!(f, "{}: Package not found in registry", package_name)
+            }
+            JamCoreError::Unauthorized { package_name } => write!(
+                f,
+                "{}: Unauthorized, please check your registry credentials",
+                package_name
+            ),
+            JamCoreError::RateLimited { package_name, .. } => {
+                write!(f, "{}: Rate limited by registry", package_name)
+            }
+            JamCoreError::ServerError {
+                package_name,
+                status,
+            } => write!(
+                f,
+                "{}: Registry failed with status {}",
+                package_name, status
+            ),
+            JamCoreError::UnexpectedStatus {
+                package_name,
+                status,
+            } => write!(
+                f,
+                "{}: Unexpected registry response status {}",
+                package_name, status
+            ),
+            JamCoreError::MalformedResponse {
+                package_name,
+                reason,
+            } => write!(
+                f,
+                "{}: Unexpected package metadata response ({})",
+                package_name, reason
+            ),
+            JamCoreError::Network {
+                package_name,
+                reason,
+            } => write!(
+                f,
+                "{}: Failed to fetch package metadata ({})",
+                package_name, reason
+            ),
         }
     }
 }
@@ -0,0 +1,205 @@
+use crate::errors::JamCoreError;
+use reqwest::{Certificate, Client, ClientBuilder, Proxy, Url};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientOptions {
+    pub proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub no_proxy: Vec<String>,
+    pub ca_file: Option<PathBuf>,
+    pub strict_ssl: bool,
+}
+
+impl Default for ClientOptions {
+    fn default() -> ClientOptions {
+        ClientOptions {
+            proxy: None,
+            https_proxy: None,
+            no_proxy: vec![],
+            ca_file: None,
+            strict_ssl: true,
+        }
+    }
+}
+
+pub struct ClientFactory {
+    options: ClientOptions,
+}
+
+impl ClientFactory {
+    pub fn new(options: ClientOptions) -> ClientFactory {
+        ClientFactory { options }
+    }
+
+    pub fn create_client(&self) -> Result<Client, JamCoreError> {
+        self.build(Client::builder())
+    }
+
+    // For clients sending every request through the same settings, like the remote cache
+    pub fn create_client_with_timeout(&self, timeout: Duration) -> Result<Client, JamCoreError> {
+        self.build(Client::builder().timeout(timeout))
+    }
+
+    fn build(&self, builder: ClientBuilder) -> Result<Client, JamCoreError> {
+        let mut builder = builder
+            .no_proxy()
+            .danger_accept_invalid_certs(!self.options.strict_ssl);
+
+        if self.options.proxy.is_some() || self.options.https_proxy.is_some() {
+            let options = self.options.clone();
+
+            builder = builder.proxy(Proxy::custom(move |url| proxy_for(&options, url)));
+        }
+
+        if let Some(ca_file) = &self.options.ca_file {
+            for certificate in read_certificates(ca_file)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        builder
+            .build()
+            .map_err(|err| JamCoreError::new(format!("Failed to create http client: {}", err)))
+    }
+}
+
+fn proxy_for(options: &ClientOptions, url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+
+    if options
+        .no_proxy
+        .iter()
+        .any(|pattern| host_matches(host, pattern))
+    {
+        return None;
+    }
+
+    match url.scheme() {
+        "https" => options
+            .https_proxy
+            .clone()
+            .or_else(|| options.proxy.clone()),
+        _ => options.proxy.clone(),
+    }
+}
+
+// Follows the npm `noproxy` semantics: a domain matches itself and all of its subdomains
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim();
+    if pattern == "*" {
+        return true;
+    }
+
+    let pattern = pattern.trim_start_matches('*').trim_start_matches('.');
+
+    !pattern.is_empty() && (host == pattern || host.ends_with(&format!(".{}", pattern)))
+}
+
+fn read_certificates(ca_file: &Path) -> Result<Vec<Certificate>, JamCoreError> {
+    let content = fs::read_to_string(ca_file).map_err(|err| {
+        JamCoreError::new(format!("Failed to read cafile {:?}: {}", ca_file, err))
+    })?;
+
+    content
+        .split_inclusive(PEM_CERTIFICATE_END)
+        .filter(|pem| pem.contains(PEM_CERTIFICATE_END))
+        .map(|pem| {
+            Certificate::from_pem(pem.trim().as_bytes()).map_err(|err| {
+                JamCoreError::new(format!("Invalid certificate in {:?}: {}", ca_file, err))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options_with_proxies() -> ClientOptions {
+        ClientOptions {
+            proxy: Some("http://proxy:8080".to_string()),
+            https_proxy: Some("http://secure-proxy:8443".to_string()),
+            no_proxy: vec!["internal.corp".to_string()],
+            ..ClientOptions::default()
+        }
+    }
+
+    #[test]
+    fn uses_https_proxy_for_https_urls() {
+        let url = Url::parse("https://registry.npmjs.org/lodash").unwrap();
+
+        assert_eq!(
+            proxy_for(&options_with_proxies(), &url),
+            Some("http://secure-proxy:8443".to_string())
+        );
+    }
+
+    #[test]
+    fn falls_back_to_proxy_when_https_proxy_is_missing() {
+        let url = Url::parse("https://registry.npmjs.org/lodash").unwrap();
+        let options = ClientOptions {
+            https_proxy: None,
+            ..options_with_proxies()
+        };
+
+        assert_eq!(
+            proxy_for(&options, &url),
+            Some("http://proxy:8080".to_string())
+        );
+    }
+
+    #[test]
+    fn uses_proxy_for_http_urls() {
+        let url = Url::parse("http://registry.npmjs.org/lodash").unwrap();
+
+        assert_eq!(
+            proxy_for(&options_with_proxies(), &url),
+            Some("http://proxy:8080".to_string())
+        );
+    }
+
+    #[test]
+    fn skips_proxy_for_no_proxy_hosts_and_their_subdomains() {
+        let options = options_with_proxies();
+
+        let url = Url::parse("https://internal.corp/lodash").unwrap();
+        let sub_domain_url = Url::parse("https://npm.internal.corp/lodash").unwrap();
+        let other_url = Url::parse("https://notinternal.corp/lodash").unwrap();
+
+        assert_eq!(proxy_for(&options, &url), None);
+        assert_eq!(proxy_for(&options, &sub_domain_url), None);
+        assert_ne!(proxy_for(&options, &other_url), None);
+    }
+
+    #[test]
+    fn skips_proxy_for_every_host_with_a_wildcard_no_proxy() {
+        let url = Url::parse("https://registry.npmjs.org/lodash").unwrap();
+
+        let options = ClientOptions {
+            no_proxy: vec!["*".to_string()],
+            ..options_with_proxies()
+        };
+        assert_eq!(proxy_for(&options, &url), None);
+
+        let options = ClientOptions {
+            no_proxy: vec!["".to_string(), " ".to_string()],
+            ..options_with_proxies()
+        };
+        assert_ne!(proxy_for(&options, &url), None);
+    }
+
+    #[test]
+    fn fails_on_missing_ca_file() {
+        let factory = ClientFactory::new(ClientOptions {
+            ca_file: Some(PathBuf::from("/does/not/exist.pem")),
+            ..ClientOptions::default()
+        });
+
+        assert!(factory.create_client().is_err());
+    }
+}
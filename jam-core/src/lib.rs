@@ -2,6 +2,9 @@ mod collector;
 
 pub mod dependency;
 pub mod errors;
+pub mod http;
+pub mod limiter;
+pub mod metadata_format;
 pub mod npm;
 pub mod package;
 pub mod resolver;
@@ -12,12 +15,9 @@ use crate::dependency::Dependency;
 use crate::errors::JamCoreError;
 use crate::package::Package;
 use crate::resolver::PackageResolver;
-use futures::StreamExt;
 use petgraph::graph::{Graph, NodeIndex};
 use std::collections::HashMap;
 
-const CONCURRENCY: usize = 50;
-
 pub async fn build_graph(
     base: Vec<Package>,
     resolver: &dyn PackageResolver,
@@ -32,20 +32,19 @@ pub async fn build_graph(
     list.iter().for_each(|package| {
         let node = graph.add_node(package.clone());
 
-        starting_nodes.push(node.clone());
+        starting_nodes.push(node);
         seen.insert(package.clone(), node);
     });
 
     while !list.is_empty() {
         let dependencies_map = collector.collect(&list);
 
-        let dependencies_packages = futures::stream::iter(
+        // Network requests are bounded by the fetcher's limiter, cache hits should not wait on them
+        let dependencies_packages = futures::future::join_all(
             dependencies_map
                 .iter()
                 .map(|(dependency, packages)| resolver.get(packages[0].name(), dependency)),
         )
-        .buffer_unordered(CONCURRENCY)
-        .collect::<Vec<Result<_, _>>>()
         .await
         .into_iter()
         .collect::<Result<Vec<(Package, &Dependency)>, JamCoreError>>()?;
@@ -0,0 +1,201 @@
+use crate::errors::JamCoreError;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep_until, Instant};
+
+const DEFAULT_NETWORK_CONCURRENCY: usize = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimiterOptions {
+    pub concurrency: usize,
+    pub requests_per_second: Option<u32>,
+}
+
+impl Default for LimiterOptions {
+    fn default() -> LimiterOptions {
+        LimiterOptions {
+            concurrency: DEFAULT_NETWORK_CONCURRENCY,
+            requests_per_second: None,
+        }
+    }
+}
+
+// Shared by every component talking to the network, so the limits hold across metadata and tarball requests
+pub struct RequestLimiter {
+    semaphore: Arc<Semaphore>,
+    interval: Option<Duration>,
+    next_slots: Mutex<HashMap<String, Instant>>,
+}
+
+pub struct RequestPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl RequestLimiter {
+    pub fn new(options: LimiterOptions) -> Result<RequestLimiter, JamCoreError> {
+        if options.concurrency == 0 {
+            return Err(JamCoreError::new(String::from(
+                "Network concurrency must be greater than 0",
+            )));
+        }
+
+        if options.requests_per_second == Some(0) {
+            return Err(JamCoreError::new(String::from(
+                "Requests per second must be greater than 0",
+            )));
+        }
+
+        Ok(RequestLimiter {
+            semaphore: Arc::new(Semaphore::new(options.concurrency)),
+            interval: options
+                .requests_per_second
+                .map(|requests_per_second| Duration::from_secs(1) / requests_per_second),
+            next_slots: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // The permit must be held for as long as the response is being read
+    pub async fn acquire(&self, url: &str) -> Result<RequestPermit, JamCoreError> {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|err| {
+                JamCoreError::new(format!("Failed to acquire request permit: {}", err))
+            })?;
+
+        if let Some(interval) = self.interval {
+            sleep_until(self.reserve_slot(host_of(url), interval)).await;
+        }
+
+        Ok(RequestPermit { _permit: permit })
+    }
+
+    // Requests to the same host are spaced evenly instead of bursting at the start of every second
+    fn reserve_slot(&self, host: String, interval: Duration) -> Instant {
+        let mut next_slots = self.next_slots.lock().unwrap();
+        let now = Instant::now();
+
+        let slot = match next_slots.get(&host) {
+            Some(next_slot) if *next_slot > now => *next_slot,
+            _ => now,
+        };
+        next_slots.insert(host, slot + interval);
+
+        slot
+    }
+}
+
+fn host_of(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        ),
+        Err(_) => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn fails_on_zero_concurrency() {
+        let result = RequestLimiter::new(LimiterOptions {
+            concurrency: 0,
+            requests_per_second: None,
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fails_on_zero_requests_per_second() {
+        let result = RequestLimiter::new(LimiterOptions {
+            concurrency: 1,
+            requests_per_second: Some(0),
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn limits_concurrent_requests() {
+        let limiter = Arc::new(
+            RequestLimiter::new(LimiterOptions {
+                concurrency: 2,
+                requests_per_second: None,
+            })
+            .unwrap(),
+        );
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..6).map(|_| {
+            let limiter = limiter.clone();
+            let running = running.clone();
+            let max_running = max_running.clone();
+
+            tokio::spawn(async move {
+                let _permit = limiter.acquire("http://registry/p1").await.unwrap();
+                let current = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(current, Ordering::SeqCst);
+
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+
+        futures::future::join_all(tasks).await;
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn spaces_requests_to_the_same_host() {
+        let limiter = RequestLimiter::new(LimiterOptions {
+            concurrency: 10,
+            requests_per_second: Some(20),
+        })
+        .unwrap();
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            limiter.acquire("http://registry/p1").await.unwrap();
+        }
+
+        assert!(now.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn does_not_space_requests_to_different_hosts() {
+        let limiter = RequestLimiter::new(LimiterOptions {
+            concurrency: 10,
+            requests_per_second: Some(1),
+        })
+        .unwrap();
+        let now = Instant::now();
+
+        limiter.acquire("http://registry-1/p1").await.unwrap();
+        limiter.acquire("http://registry-2/p1").await.unwrap();
+        limiter.acquire("https://registry-1/p1").await.unwrap();
+
+        assert!(now.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn keys_hosts_by_name_and_port() {
+        assert_eq!(
+            host_of("https://registry.npmjs.org/p1"),
+            "registry.npmjs.org:443"
+        );
+        assert_eq!(host_of("http://localhost:4873/p1"), "localhost:4873");
+    }
+}
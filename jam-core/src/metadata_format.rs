@@ -0,0 +1,127 @@
+use crate::errors::JamCoreError;
+use crate::npm::PackageMetadata;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::prelude::*;
+
+const MAGIC: &[u8; 4] = b"JAMM";
+
+// Bump whenever `PackageMetadata` changes, entries written by other versions are refetched
+pub const METADATA_FORMAT_VERSION: u32 = 1;
+
+// Cached metadata is a magic, a little endian format version and the deflated bincode of the metadata
+pub fn encode_metadata(metadata: &PackageMetadata) -> Result<Vec<u8>, JamCoreError> {
+    let mut buffer = MAGIC.to_vec();
+    buffer.extend_from_slice(&METADATA_FORMAT_VERSION.to_le_bytes());
+
+    let mut encoder = DeflateEncoder::new(buffer, Compression::fast());
+
+    bincode::serialize_into(&mut encoder, metadata).map_err(|err| {
+        JamCoreError::new(format!(
+            "Failed to encode metadata of {}: {}",
+            metadata.package_name, err
+        ))
+    })?;
+
+    Ok(encoder.finish()?)
+}
+
+pub fn decode_metadata(reader: &mut dyn Read) -> Result<PackageMetadata, JamCoreError> {
+    let mut header = [0; 8];
+
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| JamCoreError::new(String::from("Metadata entry is truncated")))?;
+
+    if &header[..4] != MAGIC {
+        return Err(JamCoreError::new(String::from(
+            "Metadata entry has an unknown format",
+        )));
+    }
+
+    let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    if version != METADATA_FORMAT_VERSION {
+        return Err(JamCoreError::new(format!(
+            "Metadata entry has format version {}, expected {}",
+            version, METADATA_FORMAT_VERSION
+        )));
+    }
+
+    // Inflating up front is much faster than letting bincode issue small reads against the decoder
+    let mut content = vec![];
+
+    DeflateDecoder::new(reader)
+        .read_to_end(&mut content)
+        .map_err(|err| JamCoreError::new(format!("Metadata entry is corrupt: {}", err)))?;
+
+    bincode::deserialize(&content)
+        .map_err(|err| JamCoreError::new(format!("Metadata entry is corrupt: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npm::VersionMetadata;
+    use maplit::hashmap;
+
+    fn create_metadata() -> PackageMetadata {
+        PackageMetadata {
+            package_name: "lodash".to_string(),
+            dist_tags: hashmap! { "latest".to_string() => "1.0.0".to_string() },
+            versions: hashmap! {
+                "1.0.0".to_string() => VersionMetadata {
+                    shasum: "some-shasum".to_string(),
+                    tarball: "some-tarball".to_string(),
+                    dependencies: hashmap! { "dep1".to_string() => "^1.0.0".to_string() },
+                    binaries: hashmap! {},
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn decodes_encoded_metadata() {
+        let metadata = create_metadata();
+
+        let encoded = encode_metadata(&metadata).unwrap();
+
+        assert_eq!(decode_metadata(&mut encoded.as_slice()), Ok(metadata));
+    }
+
+    #[test]
+    fn fails_on_plain_json_entries() {
+        let content = serde_json::to_vec(&create_metadata()).unwrap();
+
+        assert_eq!(
+            decode_metadata(&mut content.as_slice()),
+            Err(JamCoreError::new(String::from(
+                "Metadata entry has an unknown format"
+            )))
+        );
+    }
+
+    #[test]
+    fn fails_on_other_format_versions() {
+        let mut encoded = encode_metadata(&create_metadata()).unwrap();
+        encoded[4..8].copy_from_slice(&(METADATA_FORMAT_VERSION + 1).to_le_bytes());
+
+        assert_eq!(
+            decode_metadata(&mut encoded.as_slice()),
+            Err(JamCoreError::new(format!(
+                "Metadata entry has format version {}, expected {}",
+                METADATA_FORMAT_VERSION + 1,
+                METADATA_FORMAT_VERSION
+            )))
+        );
+    }
+
+    #[test]
+    fn fails_on_truncated_entries() {
+        let encoded = encode_metadata(&create_metadata()).unwrap();
+
+        assert!(decode_metadata(&mut &encoded[..encoded.len() / 2]).is_err());
+        assert!(decode_metadata(&mut &encoded[..3]).is_err());
+    }
+}
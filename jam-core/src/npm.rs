@@ -1,23 +1,29 @@
 use crate::errors::JamCoreError;
-use again::RetryPolicy;
-use jam_cache::{Cache, CacheFactory};
+use crate::http::ClientFactory;
+use crate::limiter::RequestLimiter;
+use crate::metadata_format::{decode_metadata, encode_metadata};
+use jam_cache::{Cache, CacheFactory, CacheLock, CachedValue};
 use jam_common::extract_binaries;
 use jam_npm_metadata::NpmPackageMetadata;
-use log::debug;
+use log::{debug, info, warn};
+use rand::Rng;
 use reqwest::header;
-use reqwest::Client;
+use reqwest::{Client, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::time::sleep;
 use urlencoding::encode;
 
-const NPM_ABBREVIATED_METADATA_ACCEPT_HEADER_VALUE: &'static str =
+const NPM_ABBREVIATED_METADATA_ACCEPT_HEADER_VALUE: &str =
     "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8, */*";
 
 const FETCH_METADATA_EXPONENTIAL_BACK_OFF_MILLIS: u64 = 100;
 const FETCH_METADATA_MAX_RETRIES: usize = 3;
+const FETCH_METADATA_TIMEOUT_MILLIS: u64 = 60_000;
+// Longest `Retry-After` honored, so a registry can't stall the install
+const FETCH_METADATA_MAX_RETRY_AFTER_SECS: u64 = 60;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VersionMetadata {
@@ -34,23 +40,54 @@ pub struct PackageMetadata {
     pub versions: HashMap<String, VersionMetadata>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetcherOptions {
+    pub max_retries: usize,
+    pub retry_back_off: Duration,
+    pub timeout: Duration,
+    pub rewrite_tarball_urls: bool,
+}
+
+impl Default for FetcherOptions {
+    fn default() -> FetcherOptions {
+        FetcherOptions {
+            max_retries: FETCH_METADATA_MAX_RETRIES,
+            retry_back_off: Duration::from_millis(FETCH_METADATA_EXPONENTIAL_BACK_OFF_MILLIS),
+            timeout: Duration::from_millis(FETCH_METADATA_TIMEOUT_MILLIS),
+            rewrite_tarball_urls: false,
+        }
+    }
+}
+
 pub struct Fetcher<'a> {
-    cache: Cache,
-    registry: &'a str,
+    cache: Arc<dyn Cache>,
+    registries: &'a [String],
     client: Client,
+    limiter: Arc<RequestLimiter>,
+    options: FetcherOptions,
 }
 
 impl<'a> Fetcher<'a> {
     pub fn new(
         cache_factory: &CacheFactory,
-        registry: &'a str,
+        client_factory: &ClientFactory,
+        limiter: Arc<RequestLimiter>,
+        registries: &'a [String],
+        options: FetcherOptions,
     ) -> Result<Fetcher<'a>, JamCoreError> {
+        if registries.is_empty() {
+            return Err(JamCoreError::new(String::from("No registries configured")));
+        }
+
         let cache = cache_factory.create_cache("metadata")?;
+        let client = client_factory.create_client()?;
 
         Ok(Fetcher {
             cache,
-            registry,
-            client: Client::new(),
+            registries,
+            client,
+            limiter,
+            options,
         })
     }
 
@@ -58,99 +95,788 @@ impl<'a> Fetcher<'a> {
         &self,
         package_name: &str,
     ) -> Result<PackageMetadata, JamCoreError> {
-        match self.cache.get(package_name) {
-            Some(file_path) => {
+        if let Some(metadata) = self.get_cached(package_name).await {
+            return Ok(metadata);
+        }
+
+        let _lock = self.lock(package_name).await?;
+
+        // Another process may have fetched the metadata while we were waiting for the lock
+        if let Some(metadata) = self.get_cached(package_name).await {
+            return Ok(metadata);
+        }
+
+        let metadata = self
+            .get_package_metadata_from_registries(package_name)
+            .await?;
+
+        self.cache
+            .set(package_name, &encode_metadata(&metadata)?)
+            .await?;
+
+        Ok(metadata)
+    }
+
+    // A cache that can't be read is treated like a miss, the registries are the source of truth.
+    // Entries of another format version or corrupt ones are overwritten by the refetched metadata.
+    async fn get_cached(&self, package_name: &str) -> Option<PackageMetadata> {
+        let value = match self.cache.get(package_name).await {
+            Ok(value) => value?,
+            Err(err) => {
+                warn!("Failed to read {} from the cache: {}", package_name, err);
+
+                return None;
+            }
+        };
+
+        match read_cached_metadata(&value) {
+            Ok(metadata) => {
                 debug!("Got metadata for {} from cache", package_name);
 
-                let file = File::open(file_path)?;
-                let reader = BufReader::new(file);
+                Some(metadata)
+            }
+            Err(err) => {
+                debug!("Discarding cached metadata of {}: {}", package_name, err);
 
-                match serde_json::from_reader(reader) {
-                    Ok(package_metadata) => Ok(package_metadata),
-                    Err(_) => Err(JamCoreError::new(String::from(
-                        "Failed to read package metadata from cache",
-                    ))),
-                }
+                None
             }
-            None => {
-                let metadata = self.get_package_metadata_from_npm(package_name).await?;
+        }
+    }
+
+    async fn lock(&self, package_name: &str) -> Result<CacheLock, JamCoreError> {
+        let cache = self.cache.clone();
+        let key = package_name.to_string();
+
+        tokio::task::spawn_blocking(move || cache.lock(&key))
+            .await
+            .map_err(|err| JamCoreError::new(format!("Failed to lock {}: {}", package_name, err)))?
+            .map_err(JamCoreError::from)
+    }
 
-                self.cache.set(
-                    package_name,
-                    serde_json::to_string(&metadata).unwrap().as_bytes(),
-                )?;
+    // Registries are tried in order, falling back to the next one when a package is missing or a registry is unhealthy
+    async fn get_package_metadata_from_registries(
+        &self,
+        package_name: &str,
+    ) -> Result<PackageMetadata, JamCoreError> {
+        let mut last_error = None;
+
+        for registry in self.registries {
+            match self
+                .get_package_metadata_from_registry(registry, package_name)
+                .await
+            {
+                Ok(metadata) => {
+                    info!("Got {} metadata from {}", package_name, registry);
 
-                Ok(metadata)
+                    return Ok(metadata);
+                }
+                Err(err) if should_fall_back(&err) => {
+                    warn!("{} from {}", err, registry);
+                    last_error = Some(err);
+                }
+                Err(err) => return Err(err),
             }
         }
+
+        Err(last_error.unwrap())
     }
 
-    async fn get_package_metadata_from_npm(
+    async fn get_package_metadata_from_registry(
         &self,
+        registry: &str,
         package_name: &str,
-    ) -> Result<PackageMetadata, String> {
+    ) -> Result<PackageMetadata, JamCoreError> {
         let now = Instant::now();
-        let url = format!("{}/{}", self.registry, encode(&package_name));
-
-        debug!("Getting {} metadata", package_name);
-        let retry_policy = RetryPolicy::exponential(Duration::from_millis(
-            FETCH_METADATA_EXPONENTIAL_BACK_OFF_MILLIS,
-        ))
-        .with_max_retries(1)
-        .with_jitter(true);
-        match retry_policy
-            .retry(|| {
-                self.client
-                    .get(&url)
-                    .header(header::ACCEPT, NPM_ABBREVIATED_METADATA_ACCEPT_HEADER_VALUE)
-                    .send()
-            })
+        let url = format!("{}/{}", registry, encode(package_name));
+        let mut back_off = self.options.retry_back_off;
+        let mut retries = 0;
+
+        loop {
+            debug!("Getting {} metadata", package_name);
+
+            match self
+                .fetch_package_metadata(registry, package_name, &url)
+                .await
+            {
+                Ok(metadata) => {
+                    debug!(
+                        "Got {} package metadata in {} milliseconds",
+                        package_name,
+                        now.elapsed().as_millis()
+                    );
+
+                    return Ok(metadata);
+                }
+                Err(err) if err.is_retryable() && retries < self.options.max_retries => {
+                    let delay = retry_delay(err.retry_after(), back_off);
+                    retries += 1;
+
+                    debug!(
+                        "{} (retry {}/{} in {} milliseconds)",
+                        err,
+                        retries,
+                        self.options.max_retries,
+                        delay.as_millis()
+                    );
+
+                    sleep(delay).await;
+                    back_off *= 2;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn fetch_package_metadata(
+        &self,
+        registry: &str,
+        package_name: &str,
+        url: &str,
+    ) -> Result<PackageMetadata, JamCoreError> {
+        let _permit = self.limiter.acquire(url).await?;
+        let response = self
+            .client
+            .get(url)
+            .header(header::ACCEPT, NPM_ABBREVIATED_METADATA_ACCEPT_HEADER_VALUE)
+            .timeout(self.options.timeout)
+            .send()
             .await
-        {
-            Ok(response) if response.status().is_success() => {
-                debug!(
-                    "Got {} package metadata in {} milliseconds",
-                    package_name,
-                    now.elapsed().as_millis()
-                );
-
-                let npm_metadata: NpmPackageMetadata = response.json().await.expect(&format!(
-                    "{}: Unexpected package metadata response",
-                    package_name
-                ));
-
-                let metadata = PackageMetadata {
+            .map_err(|err| JamCoreError::Network {
+                package_name: package_name.to_string(),
+                reason: err.to_string(),
+            })?;
+
+        check_response_status(package_name, &response)?;
+
+        let npm_metadata: NpmPackageMetadata =
+            response
+                .json()
+                .await
+                .map_err(|err| JamCoreError::MalformedResponse {
                     package_name: package_name.to_string(),
-                    dist_tags: npm_metadata.dist_tags.unwrap_or(HashMap::new()),
-                    versions: npm_metadata
-                        .versions
-                        .iter()
-                        .map(|(version, npm_version_metadata)| {
-                            (
-                                version.clone(),
-                                VersionMetadata {
-                                    binaries: extract_binaries(
-                                        &package_name,
-                                        &npm_version_metadata.bin,
-                                    ),
-                                    shasum: npm_version_metadata.dist.shasum.clone(),
-                                    tarball: npm_version_metadata.dist.tarball.clone(),
-                                    dependencies: npm_version_metadata
-                                        .dependencies
-                                        .clone()
-                                        .unwrap_or(HashMap::new()),
-                                },
-                            )
-                        })
-                        .collect(),
-                };
-
-                Ok(metadata)
-            }
-            _ => Err(format!(
-                "{}: Failed to fetch package metadata with {} retries",
-                package_name, FETCH_METADATA_MAX_RETRIES
-            )),
+                    reason: err.to_string(),
+                })?;
+
+        Ok(PackageMetadata {
+            package_name: package_name.to_string(),
+            dist_tags: npm_metadata.dist_tags.unwrap_or_default(),
+            versions: npm_metadata
+                .versions
+                .iter()
+                .map(|(version, npm_version_metadata)| {
+                    (
+                        version.clone(),
+                        VersionMetadata {
+                            binaries: extract_binaries(package_name, &npm_version_metadata.bin),
+                            shasum: npm_version_metadata.dist.shasum.clone(),
+                            tarball: if self.options.rewrite_tarball_urls {
+                                rewrite_tarball_url(
+                                    &npm_version_metadata.dist.tarball,
+                                    registry,
+                                    package_name,
+                                )
+                            } else {
+                                npm_version_metadata.dist.tarball.clone()
+                            },
+                            dependencies: npm_version_metadata
+                                .dependencies
+                                .clone()
+                                .unwrap_or_default(),
+                        },
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+fn read_cached_metadata(value: &CachedValue) -> Result<PackageMetadata, JamCoreError> {
+    decode_metadata(&mut value.reader()?)
+}
+
+fn should_fall_back(err: &JamCoreError) -> bool {
+    matches!(err, JamCoreError::PackageNotFound { .. }) || err.is_retryable()
+}
+
+// Mirrors often serve metadata pointing at the upstream registry, npm tarball urls have a `/<name>/-/` segment
+fn rewrite_tarball_url(tarball_url: &str, registry: &str, package_name: &str) -> String {
+    let marker = format!("/{}/-/", package_name);
+
+    match tarball_url.find(&marker) {
+        Some(index) => format!("{}{}", registry, &tarball_url[index..]),
+        None => tarball_url.to_string(),
+    }
+}
+
+fn check_response_status(package_name: &str, response: &Response) -> Result<(), JamCoreError> {
+    let status = response.status();
+    let package_name = package_name.to_string();
+
+    match status {
+        status if status.is_success() => Ok(()),
+        StatusCode::NOT_FOUND => Err(JamCoreError::PackageNotFound { package_name }),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            Err(JamCoreError::Unauthorized { package_name })
+        }
+        StatusCode::TOO_MANY_REQUESTS => Err(JamCoreError::RateLimited {
+            package_name,
+            retry_after: response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after),
+        }),
+        status if status.is_server_error() => Err(JamCoreError::ServerError {
+            package_name,
+            status: status.as_u16(),
+        }),
+        status => Err(JamCoreError::UnexpectedStatus {
+            package_name,
+            status: status.as_u16(),
+        }),
+    }
+}
+
+// Back-offs are jittered so concurrent fetches of a busy registry don't retry in lock step
+fn retry_delay(retry_after: Option<Duration>, back_off: Duration) -> Duration {
+    match retry_after {
+        Some(retry_after) => {
+            retry_after.min(Duration::from_secs(FETCH_METADATA_MAX_RETRY_AFTER_SECS))
         }
+        None => back_off / 2 + back_off.mul_f64(rand::thread_rng().gen_range(0.0..0.5)),
+    }
+}
+
+// Only the delta-seconds form is supported, HTTP dates fall back to the regular back off
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::ClientOptions;
+    use crate::limiter::LimiterOptions;
+    use jam_cache::{CacheBackend, CacheOptions};
+    use jam_npm_metadata::{NpmDistMetadata, NpmVersionMetadata};
+    use jam_test_utils::common::create_tmp_dir;
+    use jam_test_utils::npm_mock_server::NpmMockServer;
+    use maplit::hashmap;
+    use std::path::PathBuf;
+
+    fn fast_options(max_retries: usize) -> FetcherOptions {
+        FetcherOptions {
+            max_retries,
+            retry_back_off: Duration::from_millis(1),
+            timeout: Duration::from_secs(5),
+            rewrite_tarball_urls: false,
+        }
+    }
+
+    async fn fetch(
+        server: &NpmMockServer,
+        package_name: &str,
+        options: FetcherOptions,
+    ) -> Result<PackageMetadata, JamCoreError> {
+        let registries = vec![server.url()];
+
+        fetch_from(&registries, package_name, ClientOptions::default(), options).await
+    }
+
+    async fn fetch_from(
+        registries: &[String],
+        package_name: &str,
+        client_options: ClientOptions,
+        options: FetcherOptions,
+    ) -> Result<PackageMetadata, JamCoreError> {
+        fetch_with_limiter(
+            registries,
+            package_name,
+            client_options,
+            LimiterOptions::default(),
+            options,
+        )
+        .await
+    }
+
+    async fn fetch_with_limiter(
+        registries: &[String],
+        package_name: &str,
+        client_options: ClientOptions,
+        limiter_options: LimiterOptions,
+        options: FetcherOptions,
+    ) -> Result<PackageMetadata, JamCoreError> {
+        let tmp_dir = create_tmp_dir();
+        let cache_factory = CacheFactory::new(tmp_dir.path().to_path_buf());
+        let client_factory = ClientFactory::new(client_options);
+        let limiter = Arc::new(RequestLimiter::new(limiter_options).unwrap());
+        let fetcher = Fetcher::new(
+            &cache_factory,
+            &client_factory,
+            limiter,
+            registries,
+            options,
+        )
+        .unwrap();
+
+        fetcher.get_package_metadata(package_name).await
+    }
+
+    #[tokio::test]
+    async fn fetches_package_metadata() {
+        let mut server = NpmMockServer::new();
+
+        server.with_metadata(
+            "lodash",
+            &NpmPackageMetadata {
+                dist_tags: None,
+                versions: hashmap! {
+                    "1.0.0".to_string() => NpmVersionMetadata {
+                        bin: None,
+                        dist: NpmDistMetadata {
+                            shasum: String::from("some-shasum"),
+                            tarball: String::from("some-tarball"),
+                        },
+                        dependencies: None,
+                    },
+                },
+            },
+        );
+
+        let result = fetch(&server, "lodash", fast_options(0)).await.unwrap();
+
+        assert_eq!(result.package_name, "lodash");
+        assert_eq!(result.versions["1.0.0"].shasum, "some-shasum");
+    }
+
+    async fn fetch_with_cached_entry(
+        server: &NpmMockServer,
+        package_name: &str,
+        cached_entry: &[u8],
+    ) -> (PackageMetadata, Arc<dyn Cache>) {
+        let cache_factory = CacheFactory::with_options(
+            PathBuf::new(),
+            CacheOptions {
+                backend: CacheBackend::Memory,
+                ..CacheOptions::default()
+            },
+            Client::new(),
+        );
+        let cache = cache_factory.create_cache("metadata").unwrap();
+        cache.set(package_name, cached_entry).await.unwrap();
+
+        let registries = vec![server.url()];
+        let client_factory = ClientFactory::new(ClientOptions::default());
+        let limiter = Arc::new(RequestLimiter::new(LimiterOptions::default()).unwrap());
+        let fetcher = Fetcher::new(
+            &cache_factory,
+            &client_factory,
+            limiter,
+            &registries,
+            fast_options(0),
+        )
+        .unwrap();
+
+        let metadata = fetcher.get_package_metadata(package_name).await.unwrap();
+
+        (metadata, cache)
+    }
+
+    fn raw_metadata(shasum: &str) -> String {
+        serde_json::to_string(&NpmPackageMetadata {
+            dist_tags: None,
+            versions: hashmap! {
+                "1.0.0".to_string() => NpmVersionMetadata {
+                    bin: None,
+                    dist: NpmDistMetadata {
+                        shasum: shasum.to_string(),
+                        tarball: String::from("some-tarball"),
+                    },
+                    dependencies: None,
+                },
+            },
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_package_metadata_from_cache() {
+        let server = NpmMockServer::new();
+        let mock = server.with_raw_metadata("lodash", &raw_metadata("fetched-shasum"));
+        let mut cached = fetch(&server, "lodash", fast_options(0)).await.unwrap();
+        cached.versions.get_mut("1.0.0").unwrap().shasum = "cached-shasum".to_string();
+
+        let (result, _) =
+            fetch_with_cached_entry(&server, "lodash", &encode_metadata(&cached).unwrap()).await;
+
+        assert_eq!(result.versions["1.0.0"].shasum, "cached-shasum");
+        assert_eq!(mock.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn refetches_and_replaces_incompatible_cached_metadata() {
+        let server = NpmMockServer::new();
+        let mock = server.with_raw_metadata("lodash", &raw_metadata("some-shasum"));
+
+        // Entries written before the format was versioned are plain JSON
+        let (result, cache) =
+            fetch_with_cached_entry(&server, "lodash", raw_metadata("old-shasum").as_bytes()).await;
+
+        assert_eq!(result.versions["1.0.0"].shasum, "some-shasum");
+        assert_eq!(mock.hits(), 1);
+        assert_eq!(
+            read_cached_metadata(&cache.get("lodash").await.unwrap().unwrap()),
+            Ok(result)
+        );
+    }
+
+    #[tokio::test]
+    async fn refetches_corrupt_cached_metadata() {
+        let server = NpmMockServer::new();
+        let mock = server.with_raw_metadata("lodash", &raw_metadata("some-shasum"));
+        let metadata = fetch(&server, "lodash", fast_options(0)).await.unwrap();
+        let encoded = encode_metadata(&metadata).unwrap();
+
+        let (result, _) =
+            fetch_with_cached_entry(&server, "lodash", &encoded[..encoded.len() - 4]).await;
+
+        assert_eq!(result, metadata);
+        assert_eq!(mock.hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_with_not_found_without_retrying() {
+        let server = NpmMockServer::new();
+        let mock = server.with_metadata_status("lodash", 404);
+
+        let result = fetch(&server, "lodash", fast_options(3)).await;
+
+        assert_eq!(
+            result,
+            Err(JamCoreError::PackageNotFound {
+                package_name: "lodash".to_string()
+            })
+        );
+        assert_eq!(mock.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_with_unauthorized_without_retrying() {
+        let server = NpmMockServer::new();
+        let mock = server.with_metadata_status("@private/lib", 401);
+
+        let result = fetch(&server, "@private/lib", fast_options(3)).await;
+
+        assert_eq!(
+            result,
+            Err(JamCoreError::Unauthorized {
+                package_name: "@private/lib".to_string()
+            })
+        );
+        assert_eq!(mock.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_on_server_errors() {
+        let server = NpmMockServer::new();
+        let mock = server.with_metadata_status("lodash", 503);
+
+        let result = fetch(&server, "lodash", fast_options(2)).await;
+
+        assert_eq!(
+            result,
+            Err(JamCoreError::ServerError {
+                package_name: "lodash".to_string(),
+                status: 503,
+            })
+        );
+        assert_eq!(mock.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_every_request_attempt() {
+        let server = NpmMockServer::new();
+        let mock = server.with_metadata_status("lodash", 503);
+        let limiter_options = LimiterOptions {
+            concurrency: 1,
+            requests_per_second: Some(10),
+        };
+        let now = Instant::now();
+
+        let result = fetch_with_limiter(
+            &[server.url()],
+            "lodash",
+            ClientOptions::default(),
+            limiter_options,
+            fast_options(2),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(mock.hits(), 3);
+        assert!(now.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn retries_when_rate_limited_honoring_retry_after() {
+        let server = NpmMockServer::new();
+        let mock = server.with_rate_limited_metadata("lodash", "1");
+        let now = Instant::now();
+
+        let result = fetch(&server, "lodash", fast_options(1)).await;
+
+        assert_eq!(
+            result,
+            Err(JamCoreError::RateLimited {
+                package_name: "lodash".to_string(),
+                retry_after: Some(Duration::from_secs(1)),
+            })
+        );
+        assert_eq!(mock.hits(), 2);
+        assert!(now.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn fails_on_malformed_response_without_retrying() {
+        let server = NpmMockServer::new();
+        let mock = server.with_raw_metadata("lodash", "not a json");
+
+        let result = fetch(&server, "lodash", fast_options(3)).await;
+
+        assert!(matches!(
+            result,
+            Err(JamCoreError::MalformedResponse { .. })
+        ));
+        assert_eq!(mock.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_with_network_error_on_timeout() {
+        let server = NpmMockServer::new();
+        let metadata = NpmPackageMetadata {
+            dist_tags: None,
+            versions: hashmap! {},
+        };
+        let mock = server.with_delayed_metadata("lodash", &metadata, Duration::from_millis(500));
+        let options = FetcherOptions {
+            timeout: Duration::from_millis(50),
+            ..fast_options(1)
+        };
+
+        let result = fetch(&server, "lodash", options).await;
+
+        assert!(matches!(result, Err(JamCoreError::Network { .. })));
+        assert_eq!(mock.hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn fetches_package_metadata_through_proxy() {
+        let mut proxy = NpmMockServer::new();
+        let metadata = NpmPackageMetadata {
+            dist_tags: None,
+            versions: hashmap! {},
+        };
+
+        proxy.with_metadata("lodash", &metadata);
+
+        let client_options = ClientOptions {
+            proxy: Some(proxy.url()),
+            ..ClientOptions::default()
+        };
+
+        let result = fetch_from(
+            &["http://registry.jam.invalid".to_string()],
+            "lodash",
+            client_options,
+            fast_options(0),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn bypasses_proxy_for_no_proxy_hosts() {
+        let mut proxy = NpmMockServer::new();
+        let metadata = NpmPackageMetadata {
+            dist_tags: None,
+            versions: hashmap! {},
+        };
+
+        proxy.with_metadata("lodash", &metadata);
+
+        let client_options = ClientOptions {
+            proxy: Some(proxy.url()),
+            no_proxy: vec!["jam.invalid".to_string()],
+            ..ClientOptions::default()
+        };
+
+        let result = fetch_from(
+            &["http://registry.jam.invalid".to_string()],
+            "lodash",
+            client_options,
+            fast_options(0),
+        )
+        .await;
+
+        assert!(matches!(result, Err(JamCoreError::Network { .. })));
+    }
+
+    fn metadata_with_tarball(tarball: &str) -> NpmPackageMetadata {
+        NpmPackageMetadata {
+            dist_tags: None,
+            versions: hashmap! {
+                "1.0.0".to_string() => NpmVersionMetadata {
+                    bin: None,
+                    dist: NpmDistMetadata {
+                        shasum: String::from("some-shasum"),
+                        tarball: String::from(tarball),
+                    },
+                    dependencies: None,
+                },
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_registry_when_package_is_missing() {
+        let mirror = NpmMockServer::new();
+        let mut upstream = NpmMockServer::new();
+
+        let mirror_mock = mirror.with_metadata_status("lodash", 404);
+        upstream.with_metadata("lodash", &metadata_with_tarball("some-tarball"));
+
+        let result = fetch_from(
+            &[mirror.url(), upstream.url()],
+            "lodash",
+            ClientOptions::default(),
+            fast_options(3),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(mirror_mock.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_registry_when_registry_is_unhealthy() {
+        let mirror = NpmMockServer::new();
+        let mut upstream = NpmMockServer::new();
+
+        let mirror_mock = mirror.with_metadata_status("lodash", 502);
+        upstream.with_metadata("lodash", &metadata_with_tarball("some-tarball"));
+
+        let result = fetch_from(
+            &[mirror.url(), upstream.url()],
+            "lodash",
+            ClientOptions::default(),
+            fast_options(1),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(mirror_mock.hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_fall_back_when_unauthorized() {
+        let mirror = NpmMockServer::new();
+        let mut upstream = NpmMockServer::new();
+
+        mirror.with_metadata_status("lodash", 401);
+        upstream.with_metadata("lodash", &metadata_with_tarball("some-tarball"));
+
+        let result = fetch_from(
+            &[mirror.url(), upstream.url()],
+            "lodash",
+            ClientOptions::default(),
+            fast_options(0),
+        )
+        .await;
+
+        assert_eq!(
+            result,
+            Err(JamCoreError::Unauthorized {
+                package_name: "lodash".to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_last_error_when_all_registries_fail() {
+        let mirror = NpmMockServer::new();
+        let upstream = NpmMockServer::new();
+
+        mirror.with_metadata_status("lodash", 503);
+        upstream.with_metadata_status("lodash", 404);
+
+        let result = fetch_from(
+            &[mirror.url(), upstream.url()],
+            "lodash",
+            ClientOptions::default(),
+            fast_options(0),
+        )
+        .await;
+
+        assert_eq!(
+            result,
+            Err(JamCoreError::PackageNotFound {
+                package_name: "lodash".to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rewrites_tarball_urls_to_the_serving_registry() {
+        let mut mirror = NpmMockServer::new();
+
+        mirror.with_metadata(
+            "@types/lodash",
+            &metadata_with_tarball("https://registry.npmjs.org/@types/lodash/-/lodash-1.0.0.tgz"),
+        );
+
+        let options = FetcherOptions {
+            rewrite_tarball_urls: true,
+            ..fast_options(0)
+        };
+
+        let result = fetch(&mirror, "@types/lodash", options).await.unwrap();
+
+        assert_eq!(
+            result.versions["1.0.0"].tarball,
+            format!("{}/@types/lodash/-/lodash-1.0.0.tgz", mirror.url())
+        );
+    }
+
+    #[test]
+    fn keeps_tarball_urls_without_the_npm_layout() {
+        assert_eq!(
+            rewrite_tarball_url("https://cdn/lodash.tgz", "http://mirror", "lodash"),
+            "https://cdn/lodash.tgz"
+        );
+    }
+
+    #[test]
+    fn caps_retry_after_and_jitters_back_offs() {
+        assert_eq!(
+            retry_delay(Some(Duration::from_secs(2)), Duration::from_millis(100)),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            retry_delay(Some(Duration::from_secs(3600)), Duration::from_millis(100)),
+            Duration::from_secs(FETCH_METADATA_MAX_RETRY_AFTER_SECS)
+        );
+
+        for _ in 0..100 {
+            let delay = retry_delay(None, Duration::from_millis(100));
+
+            assert!(delay >= Duration::from_millis(50) && delay < Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
     }
 }
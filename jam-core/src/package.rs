@@ -100,7 +100,7 @@ impl WorkspacePackage {
 }
 
 fn to_dependencies_list(dependencies: Option<HashMap<String, String>>) -> Vec<Dependency> {
-    let dependencies = dependencies.unwrap_or(HashMap::new());
+    let dependencies = dependencies.unwrap_or_default();
 
     dependencies
         .iter()
@@ -23,12 +23,12 @@ pub fn with_package_json_file_content(
 ) -> String {
     let dependencies = serde_json::to_string_pretty(&dependencies).unwrap();
 
-    String::from(format!(
+    format!(
         r#"{{
         "name": "{}",
         "version": "{}",
         "dependencies": {}
     }}"#,
         name, version, dependencies
-    ))
+    )
 }
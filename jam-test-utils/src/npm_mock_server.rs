@@ -1,13 +1,14 @@
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use httpmock::Method::GET;
-use httpmock::MockServer;
+use httpmock::{MockRef, MockServer};
 use jam_npm_metadata::NpmPackageMetadata;
+use sha1::{Digest, Sha1};
 use std::collections::HashMap;
-use std::env;
 use std::fs;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempdir::TempDir;
 use urlencoding::encode;
 
@@ -15,6 +16,12 @@ pub struct NpmMockServer {
     server: MockServer,
 }
 
+impl Default for NpmMockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NpmMockServer {
     pub fn new() -> NpmMockServer {
         let server = MockServer::start();
@@ -36,12 +43,62 @@ impl NpmMockServer {
         });
     }
 
-    pub fn with_tarball_data(&mut self, package_name: &str, files: HashMap<String, String>) {
+    pub fn with_raw_metadata(&self, package_name: &str, body: &str) -> MockRef<'_> {
+        let expected_path = format!("/{}", encode(package_name));
+
+        self.server.mock(|when, then| {
+            when.method(GET).path(expected_path);
+            then.status(200).body(body);
+        })
+    }
+
+    pub fn with_metadata_status(&self, package_name: &str, status: u16) -> MockRef<'_> {
+        let expected_path = format!("/{}", encode(package_name));
+
+        self.server.mock(|when, then| {
+            when.method(GET).path(expected_path);
+            then.status(status);
+        })
+    }
+
+    pub fn with_rate_limited_metadata(&self, package_name: &str, retry_after: &str) -> MockRef<'_> {
+        let expected_path = format!("/{}", encode(package_name));
+
+        self.server.mock(|when, then| {
+            when.method(GET).path(expected_path);
+            then.status(429).header("retry-after", retry_after);
+        })
+    }
+
+    pub fn with_delayed_metadata(
+        &self,
+        package_name: &str,
+        package_metadata: &NpmPackageMetadata,
+        delay: Duration,
+    ) -> MockRef<'_> {
+        let expected_path = format!("/{}", encode(package_name));
+
+        self.server.mock(|when, then| {
+            when.method(GET).path(expected_path);
+            then.status(200)
+                .body(serde_json::to_string(package_metadata).unwrap())
+                .delay(delay);
+        })
+    }
+
+    pub fn with_tarball_data(
+        &mut self,
+        package_name: &str,
+        files: HashMap<String, String>,
+    ) -> String {
         let tmp_dir = TempDir::new("jam-tarballs").unwrap();
+        let files_path = tmp_dir.path().join("files");
+        fs::create_dir_all(&files_path).unwrap();
 
-        self.write_files(&files, tmp_dir.path().to_path_buf());
+        self.write_files(&files, files_path.clone());
 
-        let tar_gz_path = self.write_tarball(package_name, tmp_dir.path().to_str().unwrap());
+        let tarball = self.write_tarball(&files_path, &tmp_dir.path().join("package.tgz"));
+        let shasum = hex::encode(Sha1::digest(&tarball));
 
         let expected_path = format!("/tarball/{}", encode(package_name));
 
@@ -49,8 +106,10 @@ impl NpmMockServer {
             when.method(GET).path(expected_path);
             then.status(200)
                 .header("content-encoding", "gzip")
-                .body_from_file(tar_gz_path.to_str().unwrap());
+                .body(tarball);
         });
+
+        shasum
     }
 
     fn write_files(&self, files: &HashMap<String, String>, to: PathBuf) {
@@ -59,17 +118,15 @@ impl NpmMockServer {
         }
     }
 
-    fn write_tarball(&self, package_name: &str, files_path: &str) -> PathBuf {
-        let tar_gz_path = env::temp_dir().join(package_name);
-        fs::create_dir_all(tar_gz_path.parent().unwrap()).unwrap();
-
-        let tar_gz = File::create(&tar_gz_path).unwrap();
+    fn write_tarball(&self, files_path: &Path, tar_gz_path: &Path) -> Vec<u8> {
+        let tar_gz = File::create(tar_gz_path).unwrap();
         let enc = GzEncoder::new(tar_gz, Compression::default());
         let mut tar = tar::Builder::new(enc);
 
         tar.append_dir_all("package", files_path).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
 
-        tar_gz_path
+        fs::read(tar_gz_path).unwrap()
     }
 }
 
@@ -151,16 +208,17 @@ mod tests {
           "file1".to_string() => "hello".to_string()
         };
 
-        server.with_tarball_data("some-lib", files);
+        let shasum = server.with_tarball_data("some-lib", files);
 
         let url = format!("{}/tarball/{}", server.url(), "some-lib");
         let response = client.get(&url).send().unwrap();
 
         let status = response.status();
-        let body = response.text().unwrap();
+        let body = response.bytes().unwrap();
 
         assert_eq!(status, 200);
-        assert!(body.len() > 0);
+        assert!(!body.is_empty());
+        assert_eq!(shasum, hex::encode(Sha1::digest(&body)));
     }
 
     #[test]
@@ -181,6 +239,6 @@ mod tests {
         let body = response.text().unwrap();
 
         assert_eq!(status, 200);
-        assert!(body.len() > 0);
+        assert!(!body.is_empty());
     }
 }
@@ -2,17 +2,24 @@ use crate::errors::JamError;
 use flate2::read::GzDecoder;
 use std::fs;
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
 use tar::{Archive, EntryType};
 
 // Default npm pack directory
-const NPM_PACK_PATH_PREFIX: &'static str = "package";
+const NPM_PACK_PATH_PREFIX: &str = "package";
 // @types/node pack directory
-const TYPES_NODE_PATH_PREFIX: &'static str = "node";
+const TYPES_NODE_PATH_PREFIX: &str = "node";
 
 pub trait Archiver: Send + Sync {
-    fn extract_to(&self, tarball_path: &Path, target_path: &Path) -> Result<(), JamError>;
+    fn extract_from(&self, tar_gz: &mut dyn Read, target_path: &Path) -> Result<(), JamError>;
+
+    fn extract_to(&self, tarball_path: &Path, target_path: &Path) -> Result<(), JamError> {
+        let mut tar_gz = File::open(tarball_path)?;
+
+        self.extract_from(&mut tar_gz, target_path)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -37,8 +44,7 @@ impl DefaultArchiver {
 }
 
 impl Archiver for DefaultArchiver {
-    fn extract_to(&self, archive_path: &Path, target_path: &Path) -> Result<(), JamError> {
-        let tar_gz = File::open(archive_path)?;
+    fn extract_from(&self, tar_gz: &mut dyn Read, target_path: &Path) -> Result<(), JamError> {
         let tar = GzDecoder::new(tar_gz);
         let mut archive = Archive::new(tar);
 
@@ -51,7 +57,7 @@ impl Archiver for DefaultArchiver {
             let file_inner_path = self.strip_known_prefixes(&entry_path);
 
             let file_path = target_path.join(&file_inner_path);
-            fs::create_dir_all(&file_path.parent().unwrap())?;
+            fs::create_dir_all(file_path.parent().unwrap())?;
 
             entry.unpack(file_path)?;
         }
@@ -0,0 +1,300 @@
+use crate::errors::JamError;
+use crate::file_store::list_files;
+use jam_core::package::{BinaryScript, Package};
+use log::{debug, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Deserialize)]
+struct PackageDirectories {
+    bin: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PackageManifest {
+    directories: Option<PackageDirectories>,
+}
+
+// A package installed at `path` whose binaries are linked into a `.bin` directory
+pub struct BinaryProvider<'a> {
+    pub package: &'a Package,
+    pub path: PathBuf,
+    // Declared by the package owning the `.bin` directory, as opposed to a hoisted package
+    pub direct: bool,
+}
+
+impl<'a> BinaryProvider<'a> {
+    pub fn new(package: &'a Package, path: PathBuf, direct: bool) -> BinaryProvider<'a> {
+        BinaryProvider {
+            package,
+            path,
+            direct,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryLink {
+    pub name: String,
+    pub original: PathBuf,
+}
+
+// Picks a single binary for every name: the package configured in `bin_overrides`, then direct
+// dependencies over hoisted ones, then the first package by name. Conflicts are reported since
+// only one of the packages gets to run under that name.
+pub fn select_binaries(
+    providers: &[BinaryProvider],
+    bin_overrides: &HashMap<String, String>,
+) -> Result<Vec<BinaryLink>, JamError> {
+    let mut candidates: BTreeMap<String, Vec<(&BinaryProvider, PathBuf)>> = BTreeMap::new();
+
+    for provider in providers {
+        for binary in package_binaries(provider.package, &provider.path)? {
+            candidates
+                .entry(binary.name)
+                .or_default()
+                .push((provider, provider.path.join(binary.path)));
+        }
+    }
+
+    let mut links = vec![];
+    for (name, mut candidates) in candidates {
+        let preferred = bin_overrides.get(&name);
+        candidates.sort_by_key(|(provider, _)| {
+            (
+                Some(provider.package.name()) != preferred.map(String::as_str),
+                !provider.direct,
+                provider.package.name(),
+                provider.package.version(),
+            )
+        });
+
+        let (selected, original) = candidates.remove(0);
+        let others: HashSet<&str> = candidates
+            .iter()
+            .map(|(provider, _)| provider.package.name())
+            .filter(|package_name| *package_name != selected.package.name())
+            .collect();
+
+        if !others.is_empty() {
+            let mut others: Vec<&str> = others.into_iter().collect();
+            others.sort_unstable();
+            warn!(
+                "Binary '{}' is provided by {} and {}, linking the one of {}",
+                name,
+                selected.package.name(),
+                others.join(", "),
+                selected.package.name()
+            );
+        }
+
+        links.push(BinaryLink { name, original });
+    }
+
+    Ok(links)
+}
+
+// Packages declaring no `bin` expose every file under their `directories.bin`. A string `bin` is
+// named after the package, it is linked without the scope so the link lands directly in `.bin`.
+pub fn package_binaries(
+    package: &Package,
+    package_path: &Path,
+) -> Result<Vec<BinaryScript>, JamError> {
+    if !package.binaries().is_empty() {
+        return Ok(package
+            .binaries()
+            .iter()
+            .map(|binary| {
+                let name = binary.name.rsplit('/').next().unwrap_or(&binary.name);
+
+                BinaryScript::new(name.to_string(), binary.path.clone())
+            })
+            .collect());
+    }
+
+    let bin_directory = match read_bin_directory(package_path) {
+        Some(bin_directory) => bin_directory,
+        None => return Ok(vec![]),
+    };
+
+    // The directory comes from the package, links must not point outside of it
+    if !bin_directory
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        warn!(
+            "Ignoring binaries directory {:?} of {} outside of the package",
+            bin_directory,
+            package.name()
+        );
+        return Ok(vec![]);
+    }
+
+    let bin_path = package_path.join(&bin_directory);
+    if !bin_path.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut names = HashSet::new();
+    let mut binaries = vec![];
+    for file in list_files(&bin_path)? {
+        let name = file.file_name().unwrap().to_string_lossy().to_string();
+
+        if names.insert(name.clone()) {
+            binaries.push(BinaryScript::new(name, bin_directory.join(file)));
+        }
+    }
+
+    Ok(binaries)
+}
+
+fn read_bin_directory(package_path: &Path) -> Option<PathBuf> {
+    let manifest_path = package_path.join("package.json");
+    let content = fs::read(&manifest_path).ok()?;
+
+    match serde_json::from_slice::<PackageManifest>(&content) {
+        Ok(manifest) => manifest
+            .directories
+            .and_then(|directories| directories.bin)
+            .map(PathBuf::from),
+        Err(err) => {
+            debug!("Failed to read directories of {:?}: {}", manifest_path, err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jam_core::package::NpmPackage;
+    use maplit::hashmap;
+    use tempdir::TempDir;
+
+    fn npm_package(name: &str, binaries: Vec<(&str, &str)>) -> Package {
+        Package::NpmPackage(NpmPackage::new(
+            name.to_string(),
+            "1.0.0".to_string(),
+            None,
+            "shasum".to_string(),
+            "tarball".to_string(),
+            binaries
+                .into_iter()
+                .map(|(name, path)| BinaryScript::new(name.to_string(), PathBuf::from(path)))
+                .collect(),
+        ))
+    }
+
+    fn selected_packages(links: &[BinaryLink]) -> Vec<(String, PathBuf)> {
+        links
+            .iter()
+            .map(|link| (link.name.clone(), link.original.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn prefers_overrides_then_direct_dependencies_then_package_names() {
+        let a = npm_package("a", vec![("cli", "a.js"), ("only-a", "a.js")]);
+        let b = npm_package("b", vec![("cli", "b.js")]);
+        let c = npm_package("c", vec![("cli", "c.js"), ("tool", "c.js")]);
+        let d = npm_package("d", vec![("tool", "d.js")]);
+        let providers = vec![
+            BinaryProvider::new(&c, PathBuf::from("/c"), true),
+            BinaryProvider::new(&b, PathBuf::from("/b"), true),
+            BinaryProvider::new(&a, PathBuf::from("/a"), false),
+            BinaryProvider::new(&d, PathBuf::from("/d"), false),
+        ];
+
+        let links = select_binaries(&providers, &HashMap::new()).unwrap();
+
+        assert_eq!(
+            selected_packages(&links),
+            vec![
+                (String::from("cli"), PathBuf::from("/b/b.js")),
+                (String::from("only-a"), PathBuf::from("/a/a.js")),
+                (String::from("tool"), PathBuf::from("/c/c.js")),
+            ]
+        );
+
+        let bin_overrides = hashmap! {
+            String::from("cli") => String::from("a"),
+            String::from("tool") => String::from("d"),
+        };
+        let links = select_binaries(&providers, &bin_overrides).unwrap();
+
+        assert_eq!(
+            selected_packages(&links),
+            vec![
+                (String::from("cli"), PathBuf::from("/a/a.js")),
+                (String::from("only-a"), PathBuf::from("/a/a.js")),
+                (String::from("tool"), PathBuf::from("/d/d.js")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_binaries_from_the_bin_directory() {
+        let tmp_dir = TempDir::new("jam-binaries").unwrap();
+        let package_path = tmp_dir.path();
+        fs::create_dir_all(package_path.join("bin").join("nested")).unwrap();
+        fs::write(
+            package_path.join("package.json"),
+            r#"{ "name": "p1", "directories": { "bin": "./bin" } }"#,
+        )
+        .unwrap();
+        fs::write(package_path.join("bin").join("p1"), "").unwrap();
+        fs::write(package_path.join("bin").join("nested").join("p1-tool"), "").unwrap();
+
+        let package = npm_package("p1", vec![]);
+
+        assert_eq!(
+            package_binaries(&package, package_path).unwrap(),
+            vec![
+                BinaryScript::new(
+                    String::from("p1-tool"),
+                    PathBuf::from("./bin/nested/p1-tool")
+                ),
+                BinaryScript::new(String::from("p1"), PathBuf::from("./bin/p1")),
+            ]
+        );
+
+        // A declared bin takes precedence over the directory
+        let package = npm_package("p1", vec![("p1", "index.js")]);
+
+        assert_eq!(
+            package_binaries(&package, package_path).unwrap(),
+            vec![BinaryScript::new(
+                String::from("p1"),
+                PathBuf::from("index.js")
+            )]
+        );
+
+        let package = npm_package("@scope/p1", vec![("@scope/p1", "index.js")]);
+
+        assert_eq!(
+            package_binaries(&package, package_path).unwrap(),
+            vec![BinaryScript::new(
+                String::from("p1"),
+                PathBuf::from("index.js")
+            )]
+        );
+    }
+
+    #[test]
+    fn ignores_bin_directories_outside_of_the_package() {
+        let tmp_dir = TempDir::new("jam-binaries").unwrap();
+        let package_path = tmp_dir.path().join("p1");
+        fs::create_dir_all(&package_path).unwrap();
+        fs::write(
+            package_path.join("package.json"),
+            r#"{ "name": "p1", "directories": { "bin": "../" } }"#,
+        )
+        .unwrap();
+
+        let package = npm_package("p1", vec![]);
+
+        assert_eq!(package_binaries(&package, &package_path).unwrap(), vec![]);
+    }
+}
@@ -1,15 +1,22 @@
 use clap::Clap;
 use std::fmt::{Display, Error, Formatter};
+use std::path::PathBuf;
 
 #[derive(Clap)]
 #[clap(version = "0.0")]
 pub struct CliOptions {
+    #[clap(long, about = "NPM registry, overrides the manifest file registries")]
+    pub registry: Option<String>,
     #[clap(
         long,
-        default_value = "https://registry.npmjs.org",
-        about = "NPM registry"
+        about = "Maximum number of concurrent network requests, overrides the manifest file"
     )]
-    pub registry: String,
+    pub network_concurrency: Option<usize>,
+    #[clap(
+        long,
+        about = "Maximum number of requests per second to each host, overrides the manifest file"
+    )]
+    pub requests_per_second: Option<u32>,
     #[clap(subcommand)]
     pub command: Command,
     #[clap(short, long, about = "Turn on debug mode")]
@@ -24,15 +31,136 @@ pub enum Command {
     I(Install),
     #[clap(version = "0.0", author = "Idan A.")]
     Install(Install),
+    #[clap(
+        version = "0.0",
+        author = "Idan A.",
+        about = "Copy a workspace package and its production dependencies into a standalone directory"
+    )]
+    Deploy(Deploy),
+    #[clap(
+        version = "0.0",
+        author = "Idan A.",
+        about = "Manage the package store"
+    )]
+    Store(Store),
+    #[clap(
+        version = "0.0",
+        author = "Idan A.",
+        about = "Manage the metadata and tarball caches"
+    )]
+    Cache(Cache),
 }
 
 impl Display for Command {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        match *self {
-            _ => write!(f, "install"),
+        match self {
+            Command::I(_) | Command::Install(_) => write!(f, "install"),
+            Command::Deploy(_) => write!(f, "deploy"),
+            Command::Store(store) => write!(f, "store {}", store.command),
+            Command::Cache(cache) => write!(f, "cache {}", cache.command),
         }
     }
 }
 
+#[derive(Debug, Default, Clap)]
+pub struct Install {
+    #[clap(
+        long,
+        conflicts_with = "dev",
+        about = "Only install dependencies, skipping devDependencies of workspace packages"
+    )]
+    pub prod: bool,
+    #[clap(long, about = "Only install devDependencies of workspace packages")]
+    pub dev: bool,
+}
+
+#[derive(Debug, Clap)]
+pub struct Deploy {
+    #[clap(about = "Name of the workspace package to deploy")]
+    pub workspace: String,
+    #[clap(about = "Directory to deploy to, must be empty or missing")]
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clap)]
+pub struct Store {
+    #[clap(subcommand)]
+    pub command: StoreCommand,
+}
+
 #[derive(Debug, Clap)]
-pub struct Install {}
+pub enum StoreCommand {
+    #[clap(about = "Remove packages and cache files no installed project references")]
+    Prune(Prune),
+    #[clap(
+        about = "Check store packages for changed files, and against their tarball when it is cached"
+    )]
+    Verify(Verify),
+    #[clap(about = "Show the size of the store and the cache")]
+    Status(Status),
+}
+
+impl Display for StoreCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            StoreCommand::Prune(_) => write!(f, "prune"),
+            StoreCommand::Verify(_) => write!(f, "verify"),
+            StoreCommand::Status(_) => write!(f, "status"),
+        }
+    }
+}
+
+#[derive(Debug, Clap)]
+pub struct Prune {
+    #[clap(long, about = "Only report what would be removed")]
+    pub dry_run: bool,
+    #[clap(
+        long,
+        about = "Also remove packages installed before projects were tracked, which may still be in use"
+    )]
+    pub force: bool,
+}
+
+#[derive(Debug, Clap)]
+pub struct Verify {
+    #[clap(long, about = "Extract changed packages again from the tarball cache")]
+    pub repair: bool,
+}
+
+#[derive(Debug, Clap)]
+pub struct Status {}
+
+#[derive(Debug, Clap)]
+pub struct Cache {
+    #[clap(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Debug, Clap)]
+pub enum CacheCommand {
+    #[clap(about = "Show the size and age of the caches")]
+    Stats(Stats),
+    #[clap(about = "Remove every cached file")]
+    Clean(Clean),
+}
+
+impl Display for CacheCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            CacheCommand::Stats(_) => write!(f, "stats"),
+            CacheCommand::Clean(_) => write!(f, "clean"),
+        }
+    }
+}
+
+#[derive(Debug, Clap)]
+pub struct Stats {}
+
+#[derive(Debug, Clap)]
+pub struct Clean {
+    #[clap(
+        possible_values = &["metadata", "tarballs"],
+        about = "Only clean the given cache"
+    )]
+    pub cache_name: Option<String>,
+}
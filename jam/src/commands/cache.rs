@@ -0,0 +1,60 @@
+use crate::common::CACHE_NAMES;
+use crate::file_store::format_size;
+use crate::JamError;
+use directories::ProjectDirs;
+use jam_cache::{CacheFactory, CacheStats};
+use log::info;
+use std::time::SystemTime;
+
+pub fn stats(project_dirs: &ProjectDirs) -> Result<(), JamError> {
+    let cache_factory = CacheFactory::new(project_dirs.cache_dir().to_path_buf());
+
+    for cache_name in CACHE_NAMES.iter() {
+        let entries = cache_factory.create_fs_cache(cache_name)?.entries()?;
+        let size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let least_recently_used = entries
+            .iter()
+            .filter(|entry| !entry.partial)
+            .map(|entry| entry.modified)
+            .min();
+
+        match least_recently_used {
+            Some(modified) => info!(
+                "{}: {} files taking {}, least recently used {} days ago",
+                cache_name,
+                entries.len(),
+                format_size(size),
+                SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+                    .as_secs()
+                    / (24 * 60 * 60)
+            ),
+            None => info!("{}: empty", cache_name),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn clean(project_dirs: &ProjectDirs, cache_name: Option<&str>) -> Result<(), JamError> {
+    let cache_factory = CacheFactory::new(project_dirs.cache_dir().to_path_buf());
+    let mut stats = CacheStats::default();
+
+    for name in CACHE_NAMES.iter() {
+        if cache_name.is_none_or(|cache_name| cache_name == *name) {
+            let cache_stats = cache_factory.create_fs_cache(name)?.clear()?;
+
+            stats.files += cache_stats.files;
+            stats.bytes += cache_stats.bytes;
+        }
+    }
+
+    info!(
+        "Removed {} cached files, freeing {}",
+        stats.files,
+        format_size(stats.bytes)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use crate::archiver::DefaultArchiver;
+use crate::downloader::TarDownloader;
+use crate::linker::{create_linker, LinkStrategy};
+use crate::resolver::Resolver;
+use crate::store::Store;
+use crate::workspace::DependencyScope;
+use crate::Config;
+use crate::JamError;
+use crate::Workspace;
+use crate::Writer;
+use directories::ProjectDirs;
+use jam_cache::CacheFactory;
+use jam_core::build_graph;
+use jam_core::http::ClientFactory;
+use jam_core::limiter::RequestLimiter;
+use jam_core::npm::Fetcher;
+use jam_core::package::Package;
+use log::info;
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+// Resolves the workspace like a production install and copies the closure of `workspace_name`
+// into `deploy_path`
+pub async fn deploy(
+    config: &Config,
+    project_dirs: &ProjectDirs,
+    workspace_name: &str,
+    deploy_path: &Path,
+) -> Result<(), JamError> {
+    let workspace = Workspace::from_config(config)?.with_scope(DependencyScope::Production);
+
+    let workspace_package = workspace
+        .workspace_packages
+        .iter()
+        .find(|workspace_package| workspace_package.name == workspace_name)
+        .ok_or_else(|| JamError::new(format!("No workspace package named '{}'", workspace_name)))?;
+
+    let deploy_path = &resolve_path(deploy_path)?;
+    if deploy_path.starts_with(resolve_path(&workspace_package.base_path)?) {
+        return Err(JamError::new(format!(
+            "Can't deploy {} into its own directory {:?}",
+            workspace_name, deploy_path
+        )));
+    }
+    if fs::read_dir(deploy_path).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(JamError::new(format!(
+            "Deploy directory {:?} is not empty",
+            deploy_path
+        )));
+    }
+
+    let client_factory = ClientFactory::new(config.network.to_client_options());
+    let cache_factory = CacheFactory::with_options(
+        project_dirs.cache_dir().to_path_buf(),
+        config.cache_options.clone(),
+        client_factory.create_client_with_timeout(config.fetcher_options.timeout)?,
+    );
+    let limiter = Arc::new(RequestLimiter::new(config.limiter_options.clone())?);
+    let fetcher = Fetcher::new(
+        &cache_factory,
+        &client_factory,
+        limiter.clone(),
+        &config.registries,
+        config.fetcher_options.clone(),
+    )?;
+    let resolver = Resolver::new(fetcher, &workspace.workspace_packages);
+
+    // The whole workspace is resolved, so the deployed versions match the ones installed
+    let (starting_nodes, graph) = build_graph(workspace.packages(), &resolver).await?;
+    let workspace_node = starting_nodes
+        .into_iter()
+        .find(|node| match &graph[*node] {
+            Package::WorkspacePackage(package) => package.base_path == workspace_package.base_path,
+            Package::NpmPackage(_) => false,
+        })
+        .unwrap();
+
+    let downloader = TarDownloader::new(
+        &cache_factory,
+        &client_factory,
+        limiter,
+        Arc::new(DefaultArchiver::new()),
+    )?;
+    let store = Store::new(project_dirs.data_dir())?;
+    let writer = Writer::new(
+        &store,
+        &downloader,
+        create_linker(LinkStrategy::Copy),
+        config.bin_overrides.clone(),
+    );
+
+    writer.deploy(deploy_path, workspace_node, &graph).await?;
+    info!("Deployed {} to {:?}", workspace_name, deploy_path);
+
+    Ok(())
+}
+
+// Symlinks along the existing part of the path are resolved, the missing part can't contain any
+// and is normalized lexically
+fn resolve_path(path: &Path) -> Result<PathBuf, JamError> {
+    let mut existing_path = path.to_path_buf();
+    let mut missing_components: Vec<OsString> = vec![];
+
+    let mut resolved_path = loop {
+        match fs::canonicalize(&existing_path) {
+            Ok(resolved_path) => break resolved_path,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                match existing_path.components().next_back() {
+                    Some(component @ (Component::Normal(_) | Component::ParentDir)) => {
+                        missing_components.push(component.as_os_str().to_os_string());
+                        existing_path.pop();
+                    }
+                    _ => return Err(err.into()),
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+    };
+
+    for component in missing_components.into_iter().rev() {
+        if component == ".." {
+            resolved_path.pop();
+        } else {
+            resolved_path.push(component);
+        }
+    }
+
+    Ok(resolved_path)
+}
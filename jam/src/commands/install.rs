@@ -1,7 +1,14 @@
 use crate::archiver::DefaultArchiver;
+use crate::config::NodeLinker;
 use crate::downloader::TarDownloader;
+use crate::install_state::{
+    collect_packages, hash_inputs, hash_manifest, InstallState, WorkspaceState,
+};
+use crate::linker::create_linker;
+use crate::projects::ProjectRegistry;
 use crate::resolver::Resolver;
 use crate::store::Store;
+use crate::workspace::DependencyScope;
 use crate::Config;
 use crate::JamError;
 use crate::Workspace;
@@ -9,22 +16,150 @@ use crate::Writer;
 use directories::ProjectDirs;
 use jam_cache::CacheFactory;
 use jam_core::build_graph;
+use jam_core::http::ClientFactory;
+use jam_core::limiter::RequestLimiter;
 use jam_core::npm::Fetcher;
+use jam_core::package::{Package, WorkspacePackage};
+use log::{debug, info};
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-pub async fn install(config: &Config, project_dirs: &ProjectDirs) -> Result<(), JamError> {
-    let workspace = Workspace::from_config(config)?;
-    let cache_factory = CacheFactory::new(project_dirs.cache_dir().to_path_buf());
-    let fetcher = Fetcher::new(&cache_factory, &config.registry)?;
+pub async fn install(
+    config: &Config,
+    project_dirs: &ProjectDirs,
+    scope: DependencyScope,
+) -> Result<(), JamError> {
+    let workspace = Workspace::from_config(config)?.with_scope(scope);
+    let inputs_hash = hash_inputs(config, scope, &workspace.workspace_packages)?;
+    let manifest_hashes = workspace
+        .workspace_packages
+        .iter()
+        .map(hash_manifest)
+        .collect::<Result<Vec<String>, JamError>>()?;
+
+    let previous_state = InstallState::load(&config.root_path)
+        .filter(|previous_state| previous_state.inputs_hash == inputs_hash);
+    let outdated_packages: Vec<&WorkspacePackage> = match &previous_state {
+        Some(previous_state) => {
+            previous_state.outdated_packages(&workspace.workspace_packages, &manifest_hashes)
+        }
+        None => workspace.workspace_packages.iter().collect(),
+    };
+
+    if outdated_packages.is_empty() {
+        info!("Already up to date");
+        return Ok(());
+    }
+
+    // Hoisting places packages across workspaces, only isolated installs can skip unchanged ones
+    let previous_state = previous_state
+        .filter(|_| config.node_linker == NodeLinker::Isolated && config.hoist_patterns.is_empty());
+    let installed_packages: Vec<Package> = match &previous_state {
+        Some(_) => outdated_packages
+            .into_iter()
+            .map(|workspace_package| Package::WorkspacePackage(workspace_package.clone()))
+            .collect(),
+        None => workspace.packages(),
+    };
+    debug!(
+        "Installing {} of {} workspace packages",
+        installed_packages.len(),
+        workspace.workspace_packages.len()
+    );
+
+    let client_factory = ClientFactory::new(config.network.to_client_options());
+    let cache_factory = CacheFactory::with_options(
+        project_dirs.cache_dir().to_path_buf(),
+        config.cache_options.clone(),
+        client_factory.create_client_with_timeout(config.fetcher_options.timeout)?,
+    );
+    let limiter = Arc::new(RequestLimiter::new(config.limiter_options.clone())?);
+    let fetcher = Fetcher::new(
+        &cache_factory,
+        &client_factory,
+        limiter.clone(),
+        &config.registries,
+        config.fetcher_options.clone(),
+    )?;
     let resolver = Resolver::new(fetcher, &workspace.workspace_packages);
 
-    let (starting_nodes, graph) = build_graph(workspace.packages(), &resolver).await?;
+    let (starting_nodes, graph) = build_graph(installed_packages, &resolver).await?;
 
-    let archiver = DefaultArchiver::new();
-    let downloader = TarDownloader::new(&cache_factory, &archiver)?;
+    let downloader = TarDownloader::new(
+        &cache_factory,
+        &client_factory,
+        limiter,
+        Arc::new(DefaultArchiver::new()),
+    )?;
     let store = Store::new(project_dirs.data_dir())?;
-    let writer = Writer::new(&store, &downloader);
+    // Created before the store is written to, so a new store has no packages predating it
+    let registry = ProjectRegistry::new(project_dirs.data_dir())?;
+    let writer = Writer::new(
+        &store,
+        &downloader,
+        create_linker(config.link_strategy),
+        config.bin_overrides.clone(),
+    );
+
+    match config.node_linker {
+        NodeLinker::Isolated => {
+            writer.write(starting_nodes.clone(), &graph).await?;
+
+            // Partial installs have no hoist patterns, and the graph lacks the unchanged workspaces
+            if previous_state.is_none() {
+                writer.link_hoisted_packages(
+                    &config.root_path,
+                    &starting_nodes,
+                    &graph,
+                    &config.hoist_patterns,
+                )?;
+            }
+        }
+        NodeLinker::Hoisted => {
+            writer
+                .write_hoisted(&config.root_path, starting_nodes.clone(), &graph)
+                .await?
+        }
+    }
+
+    let installed_nodes: HashMap<PathBuf, NodeIndex> = starting_nodes
+        .iter()
+        .filter_map(|node| match &graph[*node] {
+            Package::WorkspacePackage(workspace_package) => {
+                Some((workspace_package.base_path.clone(), *node))
+            }
+            Package::NpmPackage(_) => None,
+        })
+        .collect();
+
+    let mut state = InstallState::new(inputs_hash);
+    for (workspace_package, manifest_hash) in
+        workspace.workspace_packages.iter().zip(manifest_hashes)
+    {
+        let packages = match installed_nodes.get(&workspace_package.base_path) {
+            Some(node) => collect_packages(*node, &graph),
+            None => previous_state
+                .as_ref()
+                .and_then(|previous_state| {
+                    previous_state.workspaces.get(&workspace_package.base_path)
+                })
+                .map(|workspace_state| workspace_state.packages.clone())
+                .unwrap_or_default(),
+        };
+
+        state.workspaces.insert(
+            workspace_package.base_path.clone(),
+            WorkspaceState {
+                manifest_hash,
+                packages,
+            },
+        );
+    }
 
-    writer.write(starting_nodes, &graph).await?;
+    registry.register(&config.root_path, state.packages())?;
+    state.save(&config.root_path)?;
 
     Ok(())
 }
@@ -1,3 +1,4 @@
+pub mod cache;
+pub mod deploy;
 pub mod install;
-
-pub use self::install::*;
+pub mod store;
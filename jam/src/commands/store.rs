@@ -0,0 +1,272 @@
+use crate::archiver::DefaultArchiver;
+use crate::common::CACHE_NAMES;
+use crate::file_store::format_size;
+use crate::projects::ProjectRegistry;
+use crate::store::{PruneOptions, PruneStats, Store, StoreEntry, Verification};
+use crate::JamError;
+use directories::ProjectDirs;
+use jam_cache::{CacheFactory, FsCache};
+use log::{debug, info, warn};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Anything changed this recently may belong to an install that hasn't registered its project yet
+const PRUNE_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+pub fn prune(project_dirs: &ProjectDirs, dry_run: bool, force: bool) -> Result<(), JamError> {
+    let options = PruneOptions {
+        dry_run,
+        grace_period: PRUNE_GRACE_PERIOD,
+    };
+    let registry = ProjectRegistry::new(project_dirs.data_dir())?;
+    let mut referenced = HashSet::new();
+
+    for project in registry.projects()? {
+        if project.exists() {
+            referenced.extend(project.packages);
+        } else {
+            info!("Forgetting removed project {:?}", project.root_path);
+
+            if !dry_run {
+                registry.unregister(&project.root_path)?;
+            }
+        }
+    }
+
+    let store = Store::new(project_dirs.data_dir())?;
+
+    // Projects installed before the registry existed were never registered, so their packages
+    // look unreferenced
+    let tracked_since = registry.tracked_since()?;
+    let mut untracked = 0;
+    for entry in store.entries()? {
+        if !referenced.contains(&entry.key()) && store.entry_changed_at(&entry)? < tracked_since {
+            untracked += 1;
+        }
+    }
+
+    if untracked > 0 {
+        if !force && !dry_run {
+            return Err(JamError::new(format!(
+                "{} store packages were installed before projects were tracked and may still be in use, reinstall the projects using the store or prune with --force",
+                untracked
+            )));
+        }
+
+        warn!(
+            "{} store packages were installed before projects were tracked, projects installed back then may need to be reinstalled",
+            untracked
+        );
+    }
+
+    let store_stats = store.prune(&referenced, &options)?;
+    let cache_stats = prune_caches(
+        &CacheFactory::new(project_dirs.cache_dir().to_path_buf()),
+        &referenced,
+        &options,
+    )?;
+
+    info!(
+        "{} {} packages and {} files from the store and {} files from the cache, freeing {}",
+        if dry_run { "Would remove" } else { "Removed" },
+        store_stats.packages,
+        store_stats.files,
+        cache_stats.files,
+        format_size(store_stats.bytes + cache_stats.bytes)
+    );
+
+    Ok(())
+}
+
+// Tarballs are cached by `name@version` and metadata by package name
+fn prune_caches(
+    cache_factory: &CacheFactory,
+    referenced: &HashSet<String>,
+    options: &PruneOptions,
+) -> Result<PruneStats, JamError> {
+    let referenced_names: HashSet<&str> = referenced
+        .iter()
+        .filter_map(|key| key.rfind('@').map(|index| &key[..index]))
+        .collect();
+    let mut stats = PruneStats::default();
+
+    for (cache_name, is_referenced) in [
+        (
+            "tarballs",
+            Box::new(|key: &str| referenced.contains(key)) as Box<dyn Fn(&str) -> bool>,
+        ),
+        (
+            "metadata",
+            Box::new(|key: &str| referenced_names.contains(key)),
+        ),
+    ]
+    .iter()
+    {
+        for entry in cache_factory.create_fs_cache(cache_name)?.entries()? {
+            let metadata = fs::metadata(&entry.path)?;
+            let keep = match &entry.key {
+                Some(key) => is_referenced(key),
+                None => false,
+            };
+
+            if !keep && !options.is_recent(&metadata) {
+                options.remove_file(&entry.path, &metadata, &mut stats)?;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+pub fn verify(project_dirs: &ProjectDirs, repair: bool) -> Result<(), JamError> {
+    let store = Store::new(project_dirs.data_dir())?;
+    let tarballs =
+        CacheFactory::new(project_dirs.cache_dir().to_path_buf()).create_fs_cache("tarballs")?;
+    let entries = store.entries()?;
+    let mut invalid = 0;
+    let mut repaired = 0;
+
+    for entry in &entries {
+        let key = entry.key();
+
+        // Incomplete entries are rebuilt by the next install using them
+        if !entry.complete {
+            info!("{}: incomplete", key);
+            continue;
+        }
+
+        let verification = match store.verify_entry(entry)? {
+            Verification::Valid => match verified_tarball(&store, &tarballs, entry) {
+                Ok(tarball_path) => {
+                    store.verify_entry_tarball(entry, &tarball_path, &DefaultArchiver::new())?
+                }
+                Err(err) => {
+                    debug!("{}: only checked against its file index, {}", key, err);
+                    Verification::Valid
+                }
+            },
+            verification => verification,
+        };
+
+        match verification {
+            Verification::Valid => continue,
+            Verification::Unindexed => {
+                info!("{}: no file index, cannot be verified", key);
+
+                if !repair {
+                    continue;
+                }
+            }
+            Verification::Invalid(problems) => {
+                for problem in problems {
+                    info!("{}: {}", key, problem);
+                }
+
+                invalid += 1;
+            }
+        }
+
+        if repair {
+            match repair_entry(&store, &tarballs, entry) {
+                Ok(()) => {
+                    info!("{}: repaired", key);
+                    repaired += 1;
+                }
+                Err(err) => info!("{}: failed to repair, {}", key, err),
+            }
+        }
+    }
+
+    info!(
+        "Verified {} packages, {} changed, {} repaired",
+        entries.len(),
+        invalid,
+        repaired
+    );
+
+    if invalid > repaired {
+        return Err(JamError::new(format!(
+            "{} store packages failed verification",
+            invalid - repaired
+        )));
+    }
+
+    Ok(())
+}
+
+fn repair_entry(store: &Store, tarballs: &FsCache, entry: &StoreEntry) -> Result<(), JamError> {
+    let tarball_path = verified_tarball(store, tarballs, entry)?;
+
+    store.repair_entry(entry, &tarball_path, &DefaultArchiver::new())
+}
+
+// Only tarballs matching the shasum recorded when the entry was installed are trusted
+fn verified_tarball(
+    store: &Store,
+    tarballs: &FsCache,
+    entry: &StoreEntry,
+) -> Result<PathBuf, JamError> {
+    let tarball_path = tarballs.get(&entry.key()).ok_or_else(|| {
+        JamError::new(String::from(
+            "its tarball is not cached, reinstall a project using it",
+        ))
+    })?;
+    let recorded_shasum = store
+        .recorded_shasum(entry)?
+        .ok_or_else(|| JamError::new(String::from("no shasum was recorded")))?;
+
+    let mut hasher = Sha1::new();
+    io::copy(&mut fs::File::open(&tarball_path)?, &mut hasher)?;
+    let shasum = hex::encode(hasher.finalize());
+
+    if shasum != recorded_shasum {
+        return Err(JamError::new(format!(
+            "the cached tarball shasum {} doesn't match the recorded {}",
+            shasum, recorded_shasum
+        )));
+    }
+
+    Ok(tarball_path)
+}
+
+pub fn status(project_dirs: &ProjectDirs) -> Result<(), JamError> {
+    let store = Store::new(project_dirs.data_dir())?;
+    let entries = store.entries()?;
+    let incomplete = entries.iter().filter(|entry| !entry.complete).count();
+    let (stored_files, stored_bytes) = store.files_size()?;
+
+    info!(
+        "Store: {} packages ({} incomplete), {} stored files taking {}",
+        entries.len(),
+        incomplete,
+        stored_files,
+        format_size(stored_bytes)
+    );
+
+    let projects = ProjectRegistry::new(project_dirs.data_dir())?.projects()?;
+    let removed_projects = projects.iter().filter(|project| !project.exists()).count();
+
+    info!(
+        "Projects: {} registered ({} removed since)",
+        projects.len(),
+        removed_projects
+    );
+
+    let cache_factory = CacheFactory::new(project_dirs.cache_dir().to_path_buf());
+    for cache_name in CACHE_NAMES.iter() {
+        let entries = cache_factory.create_fs_cache(cache_name)?.entries()?;
+
+        info!(
+            "Cache {}: {} files taking {}",
+            cache_name,
+            entries.len(),
+            format_size(entries.iter().map(|entry| entry.size).sum())
+        );
+    }
+
+    Ok(())
+}
@@ -2,7 +2,10 @@ use crate::errors::JamError;
 use std::fs;
 use std::path::PathBuf;
 
-pub fn read_manifest_file<'a>(manifest_file_path: PathBuf) -> Result<String, JamError> {
+// Named caches created under the cache directory, by the fetcher and the downloader
+pub const CACHE_NAMES: [&str; 2] = ["metadata", "tarballs"];
+
+pub fn read_manifest_file(manifest_file_path: PathBuf) -> Result<String, JamError> {
     let content = fs::read_to_string(&manifest_file_path)?;
 
     Ok(content)
@@ -1,38 +1,224 @@
+use crate::common::CACHE_NAMES;
 use crate::errors::JamError;
+use crate::hoisting::HoistPatterns;
+use crate::linker::LinkStrategy;
+use crate::network::NetworkSettings;
+use jam_cache::{CacheBackend, CacheLimits, CacheOptions};
+use jam_core::limiter::LimiterOptions;
+use jam_core::npm::FetcherOptions;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_REGISTRY: &str = "https://registry.npmjs.org";
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RegistrySetting {
+    Single(String),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct CacheLimitsSetting {
+    #[serde(alias = "maxSizeMb")]
+    max_size_mb: Option<u64>,
+    #[serde(alias = "maxAgeDays")]
+    max_age_days: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum CacheBackendSetting {
+    Filesystem,
+    Memory,
+    Remote { url: String },
+}
+
+// How packages are laid out in node_modules
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeLinker {
+    // Each package only sees its declared dependencies, linked from the store
+    #[default]
+    Isolated,
+    // Packages are copied into a flat tree at the root, nested only on version conflicts
+    Hoisted,
+}
 
 #[derive(Debug, Deserialize)]
 struct Manifest {
     workspaces: Vec<String>,
+    registry: Option<RegistrySetting>,
+    #[serde(alias = "rewriteTarballUrls")]
+    rewrite_tarball_urls: Option<bool>,
+    #[serde(alias = "fetchRetries")]
+    fetch_retries: Option<usize>,
+    #[serde(alias = "fetchTimeout")]
+    fetch_timeout: Option<u64>,
+    #[serde(alias = "networkConcurrency")]
+    network_concurrency: Option<usize>,
+    #[serde(alias = "requestsPerSecond")]
+    requests_per_second: Option<u32>,
+    cache: Option<HashMap<String, CacheLimitsSetting>>,
+    #[serde(alias = "cacheBackend")]
+    cache_backend: Option<CacheBackendSetting>,
+    #[serde(alias = "nodeLinker")]
+    node_linker: Option<NodeLinker>,
+    #[serde(alias = "linkStrategy")]
+    link_strategy: Option<LinkStrategy>,
+    #[serde(alias = "publicHoistPattern")]
+    public_hoist_pattern: Option<Vec<String>>,
+    #[serde(alias = "hoistPattern")]
+    hoist_pattern: Option<Vec<String>>,
+    #[serde(alias = "binOverrides")]
+    bin_overrides: Option<HashMap<String, String>>,
+    #[serde(flatten)]
+    network: NetworkSettings,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Config {
     pub root_path: PathBuf,
     pub patterns: Vec<String>,
-    pub registry: String,
+    pub registries: Vec<String>,
+    pub fetcher_options: FetcherOptions,
+    pub limiter_options: LimiterOptions,
+    pub cache_options: CacheOptions,
+    pub node_linker: NodeLinker,
+    pub link_strategy: LinkStrategy,
+    pub hoist_patterns: HoistPatterns,
+    // Binary name to the package whose binary is linked when several provide it
+    pub bin_overrides: HashMap<String, String>,
+    pub network: NetworkSettings,
 }
 
 impl Config {
     pub fn new(
         root_path: PathBuf,
         manifest_file_content: &str,
-        registry: &str,
+        registry: Option<&str>,
     ) -> Result<Config, JamError> {
-        match serde_json::from_str::<Manifest>(&manifest_file_content) {
-        Ok(manifest) => Ok(Config { root_path, patterns: manifest.workspaces, registry: String::from(registry) }),
-        Err(_) => Err(JamError::new(String::from(
-          "Failed to parse manifest file, please make sure it is a valid JSON and 'workspaces' array exists",
-        ) ))
-      }
+        match serde_json::from_str::<Manifest>(manifest_file_content) {
+            Ok(manifest) => Ok(Config {
+                root_path,
+                registries: to_registries(registry, &manifest.registry)?,
+                fetcher_options: to_fetcher_options(&manifest),
+                limiter_options: to_limiter_options(&manifest),
+                cache_options: CacheOptions {
+                    backend: to_cache_backend(&manifest.cache_backend),
+                    limits: to_cache_limits(&manifest.cache)?,
+                },
+                node_linker: manifest.node_linker.unwrap_or_default(),
+                link_strategy: manifest.link_strategy.unwrap_or_default(),
+                hoist_patterns: HoistPatterns::new(
+                    manifest.public_hoist_pattern.clone().unwrap_or_default(),
+                    manifest.hoist_pattern.clone().unwrap_or_default(),
+                )?,
+                bin_overrides: manifest.bin_overrides.clone().unwrap_or_default(),
+                patterns: manifest.workspaces,
+                network: manifest.network,
+            }),
+            Err(_) => Err(JamError::new(String::from(
+                "Failed to parse manifest file, please make sure it is a valid JSON and 'workspaces' array exists",
+            ))),
+        }
+    }
+}
+
+// A registry given on the command line overrides the ones configured in the manifest file
+fn to_registries(
+    registry: Option<&str>,
+    manifest_registry: &Option<RegistrySetting>,
+) -> Result<Vec<String>, JamError> {
+    let registries = match (registry, manifest_registry) {
+        (Some(registry), _) => vec![registry.to_string()],
+        (None, Some(RegistrySetting::Single(registry))) => vec![registry.clone()],
+        (None, Some(RegistrySetting::List(registries))) => registries.clone(),
+        (None, None) => vec![DEFAULT_REGISTRY.to_string()],
+    };
+
+    if registries.is_empty() {
+        return Err(JamError::new(String::from(
+            "'registry' must contain at least one registry url",
+        )));
+    }
+
+    Ok(registries
+        .iter()
+        .map(|registry| registry.trim_end_matches('/').to_string())
+        .collect())
+}
+
+fn to_fetcher_options(manifest: &Manifest) -> FetcherOptions {
+    let defaults = FetcherOptions::default();
+
+    FetcherOptions {
+        max_retries: manifest.fetch_retries.unwrap_or(defaults.max_retries),
+        timeout: manifest
+            .fetch_timeout
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.timeout),
+        rewrite_tarball_urls: manifest
+            .rewrite_tarball_urls
+            .unwrap_or(defaults.rewrite_tarball_urls),
+        ..defaults
+    }
+}
+
+fn to_limiter_options(manifest: &Manifest) -> LimiterOptions {
+    let defaults = LimiterOptions::default();
+
+    LimiterOptions {
+        concurrency: manifest.network_concurrency.unwrap_or(defaults.concurrency),
+        requests_per_second: manifest.requests_per_second,
+    }
+}
+
+fn to_cache_backend(cache_backend: &Option<CacheBackendSetting>) -> CacheBackend {
+    match cache_backend {
+        None | Some(CacheBackendSetting::Filesystem) => CacheBackend::Filesystem,
+        Some(CacheBackendSetting::Memory) => CacheBackend::Memory,
+        Some(CacheBackendSetting::Remote { url }) => CacheBackend::Remote(url.clone()),
     }
 }
 
+fn to_cache_limits(
+    cache: &Option<HashMap<String, CacheLimitsSetting>>,
+) -> Result<HashMap<String, CacheLimits>, JamError> {
+    let mut cache_limits = HashMap::new();
+
+    for (cache_name, setting) in cache.iter().flatten() {
+        if !CACHE_NAMES.contains(&cache_name.as_str()) {
+            return Err(JamError::new(format!(
+                "Unknown cache '{}' in 'cache', expected one of {}",
+                cache_name,
+                CACHE_NAMES.join(", ")
+            )));
+        }
+
+        cache_limits.insert(
+            cache_name.clone(),
+            CacheLimits {
+                max_size: setting
+                    .max_size_mb
+                    .map(|max_size_mb| max_size_mb * 1024 * 1024),
+                max_age: setting
+                    .max_age_days
+                    .map(|max_age_days| Duration::from_secs(max_age_days * 24 * 60 * 60)),
+            },
+        );
+    }
+
+    Ok(cache_limits)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use jam_test_utils::common::with_manifest_file_content;
+    use maplit::hashmap;
 
     #[test]
     fn fails_on_invalid_manifest_content() {
@@ -40,9 +226,9 @@ mod tests {
         let content = "{}";
         let registry = "http://some/url";
 
-        let result = Config::new(root_path, content, registry);
+        let result = Config::new(root_path, content, Some(registry));
 
-        assert_eq!(result, Err(JamError::new(String::from("Failed to parse manifest file, please make sure it is a valid JSON and 'workspaces' array exists".to_string() ))));
+        assert_eq!(result, Err(JamError::new("Failed to parse manifest file, please make sure it is a valid JSON and 'workspaces' array exists".to_string())));
     }
 
     #[test]
@@ -51,15 +237,242 @@ mod tests {
         let content = with_manifest_file_content(vec!["packages/**", "not-in-packages/foo"]);
         let registry = String::from("http://some/url");
 
-        let result = Config::new(root_path.clone(), &content, &registry);
+        let result = Config::new(root_path.clone(), &content, Some(&registry));
 
         assert_eq!(
             result,
             Ok(Config {
                 root_path,
                 patterns: vec!["packages/**".to_string(), "not-in-packages/foo".to_string()],
-                registry
+                registries: vec![registry],
+                fetcher_options: FetcherOptions::default(),
+                limiter_options: LimiterOptions::default(),
+                cache_options: CacheOptions::default(),
+                node_linker: NodeLinker::Isolated,
+                link_strategy: LinkStrategy::Symlink,
+                hoist_patterns: HoistPatterns::default(),
+                bin_overrides: HashMap::new(),
+                network: NetworkSettings::default(),
             })
         )
     }
+
+    #[test]
+    fn reads_fetch_options_from_manifest_file() {
+        let root_path = PathBuf::new();
+        let content = r#"{ "workspaces": [], "fetchRetries": 5, "fetchTimeout": 1000 }"#;
+        let registry = "http://some/url";
+
+        let result = Config::new(root_path, content, Some(registry)).unwrap();
+
+        assert_eq!(result.fetcher_options.max_retries, 5);
+        assert_eq!(result.fetcher_options.timeout, Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn reads_limiter_options_from_manifest_file() {
+        let content = r#"{ "workspaces": [], "networkConcurrency": 8, "requestsPerSecond": 20 }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(
+            result.limiter_options,
+            LimiterOptions {
+                concurrency: 8,
+                requests_per_second: Some(20),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_network_settings_from_manifest_file() {
+        let root_path = PathBuf::new();
+        let content = r#"{
+            "workspaces": [],
+            "httpsProxy": "http://proxy:8443",
+            "noProxy": "localhost",
+            "cafile": "/etc/ssl/corp.pem",
+            "strictSsl": false
+        }"#;
+        let registry = "http://some/url";
+
+        let result = Config::new(root_path, content, Some(registry)).unwrap();
+
+        assert_eq!(
+            result.network,
+            NetworkSettings {
+                proxy: None,
+                https_proxy: Some("http://proxy:8443".to_string()),
+                no_proxy: Some("localhost".to_string()),
+                cafile: Some(PathBuf::from("/etc/ssl/corp.pem")),
+                strict_ssl: Some(false),
+            }
+        );
+    }
+
+    #[test]
+    fn uses_default_registry_when_none_is_configured() {
+        let content = with_manifest_file_content(vec!["packages/**"]);
+
+        let result = Config::new(PathBuf::new(), &content, None).unwrap();
+
+        assert_eq!(
+            result.registries,
+            vec!["https://registry.npmjs.org".to_string()]
+        );
+    }
+
+    #[test]
+    fn reads_registries_list_from_manifest_file() {
+        let content = r#"{
+            "workspaces": [],
+            "registry": ["http://localhost:4873/", "https://registry.npmjs.org"],
+            "rewriteTarballUrls": true
+        }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(
+            result.registries,
+            vec![
+                "http://localhost:4873".to_string(),
+                "https://registry.npmjs.org".to_string()
+            ]
+        );
+        assert!(result.fetcher_options.rewrite_tarball_urls);
+    }
+
+    #[test]
+    fn prefers_given_registry_over_manifest_file_registries() {
+        let content = r#"{ "workspaces": [], "registry": "http://localhost:4873" }"#;
+
+        let result = Config::new(PathBuf::new(), content, Some("http://some/url")).unwrap();
+
+        assert_eq!(result.registries, vec!["http://some/url".to_string()]);
+    }
+
+    #[test]
+    fn fails_on_empty_registries_list() {
+        let content = r#"{ "workspaces": [], "registry": [] }"#;
+
+        let result = Config::new(PathBuf::new(), content, None);
+
+        assert_eq!(
+            result,
+            Err(JamError::new(String::from(
+                "'registry' must contain at least one registry url"
+            )))
+        );
+    }
+
+    #[test]
+    fn reads_cache_limits_from_manifest_file() {
+        let content = r#"{
+            "workspaces": [],
+            "cache": {
+                "tarballs": { "maxSizeMb": 2, "maxAgeDays": 1 },
+                "metadata": { "maxAgeDays": 7 }
+            }
+        }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(
+            result.cache_options.limits,
+            hashmap! {
+                "tarballs".to_string() => CacheLimits {
+                    max_size: Some(2 * 1024 * 1024),
+                    max_age: Some(Duration::from_secs(24 * 60 * 60)),
+                },
+                "metadata".to_string() => CacheLimits {
+                    max_size: None,
+                    max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn fails_on_unknown_cache_limits() {
+        let content = r#"{ "workspaces": [], "cache": { "packages": { "maxSizeMb": 2 } } }"#;
+
+        let result = Config::new(PathBuf::new(), content, None);
+
+        assert_eq!(
+            result,
+            Err(JamError::new(String::from(
+                "Unknown cache 'packages' in 'cache', expected one of metadata, tarballs"
+            )))
+        );
+    }
+
+    #[test]
+    fn reads_cache_backend_from_manifest_file() {
+        let content = r#"{
+            "workspaces": [],
+            "cacheBackend": { "type": "remote", "url": "http://cache.local/jam" }
+        }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(
+            result.cache_options.backend,
+            CacheBackend::Remote("http://cache.local/jam".to_string())
+        );
+    }
+
+    #[test]
+    fn fails_on_unknown_cache_backend() {
+        let content = r#"{ "workspaces": [], "cacheBackend": { "type": "s3" } }"#;
+
+        let result = Config::new(PathBuf::new(), content, None);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reads_node_linker_from_manifest_file() {
+        let content = r#"{ "workspaces": [], "nodeLinker": "hoisted" }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(result.node_linker, NodeLinker::Hoisted);
+    }
+
+    #[test]
+    fn reads_link_strategy_from_manifest_file() {
+        let content = r#"{ "workspaces": [], "linkStrategy": "relative" }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(result.link_strategy, LinkStrategy::Relative);
+    }
+
+    #[test]
+    fn reads_bin_overrides_from_manifest_file() {
+        let content = r#"{ "workspaces": [], "binOverrides": { "tsc": "typescript" } }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(
+            result.bin_overrides,
+            hashmap! { String::from("tsc") => String::from("typescript") }
+        );
+    }
+
+    #[test]
+    fn reads_hoist_patterns_from_manifest_file() {
+        let content = r#"{
+            "workspaces": [],
+            "publicHoistPattern": ["*eslint*"],
+            "hoistPattern": ["*"]
+        }"#;
+
+        let result = Config::new(PathBuf::new(), content, None).unwrap();
+
+        assert_eq!(
+            result.hoist_patterns,
+            HoistPatterns::new(vec!["*eslint*".to_string()], vec!["*".to_string()]).unwrap()
+        );
+    }
 }
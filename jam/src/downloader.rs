@@ -1,79 +1,278 @@
 use crate::archiver::Archiver;
 use crate::errors::JamError;
 use async_trait::async_trait;
-use jam_cache::{Cache, CacheFactory};
+use bytes::{Buf, Bytes};
+use jam_cache::{Cache, CacheFactory, CacheLock, CachedValue};
+use jam_core::http::ClientFactory;
+use jam_core::limiter::RequestLimiter;
 use jam_core::package::NpmPackage;
-use log::{debug, info};
+use log::{debug, info, warn};
 use reqwest::Client;
+use sha1::{Digest, Sha1};
+use std::io::{self, Read, Write};
 use std::path::Path;
-use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
+use tokio::sync::mpsc;
+
+// Maximum number of response chunks waiting to be extracted, bounding memory per download
+const STREAM_BUFFER_CHUNKS: usize = 4;
 
 #[async_trait]
 pub trait Downloader {
     async fn download_to(&self, package: &NpmPackage, path: &Path) -> Result<(), JamError>;
 }
 
-pub struct TarDownloader<'a> {
+pub struct TarDownloader {
     client: Client,
-    cache: Cache,
-    archiver: &'a dyn Archiver,
+    cache: Arc<dyn Cache>,
+    limiter: Arc<RequestLimiter>,
+    archiver: Arc<dyn Archiver>,
+}
+
+#[derive(Debug, Default)]
+struct BufferGauge {
+    current: AtomicUsize,
+    max: AtomicUsize,
+}
+
+impl BufferGauge {
+    fn add(&self, size: usize) {
+        let current = self.current.fetch_add(size, Ordering::SeqCst) + size;
+        self.max.fetch_max(current, Ordering::SeqCst);
+    }
+
+    fn remove(&self, size: usize) {
+        self.current.fetch_sub(size, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug)]
+struct DownloadStats {
+    bytes: usize,
+    max_chunk_bytes: usize,
+    max_buffered_bytes: usize,
+}
+
+// Adapts the chunks received from the download task into a blocking reader for the archiver
+struct ChunkReader {
+    receiver: mpsc::Receiver<Bytes>,
+    chunk: Bytes,
+    gauge: Arc<BufferGauge>,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.chunk.has_remaining() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let size = buf.len().min(self.chunk.remaining());
+        self.chunk.copy_to_slice(&mut buf[..size]);
+        self.gauge.remove(size);
+
+        Ok(size)
+    }
 }
 
-impl<'a> TarDownloader<'a> {
+impl TarDownloader {
     pub fn new(
         cache_factory: &CacheFactory,
-        archiver: &'a dyn Archiver,
-    ) -> Result<TarDownloader<'a>, JamError> {
+        client_factory: &ClientFactory,
+        limiter: Arc<RequestLimiter>,
+        archiver: Arc<dyn Archiver>,
+    ) -> Result<TarDownloader, JamError> {
         let cache = cache_factory.create_cache("tarballs")?;
 
         Ok(TarDownloader {
-            client: Client::new(),
+            client: client_factory.create_client()?,
             cache,
+            limiter,
             archiver,
         })
     }
 
-    async fn download_tar(
+    fn extract_cached(
+        &self,
+        package: &NpmPackage,
+        value: &CachedValue,
+        path: &Path,
+    ) -> Result<(), JamError> {
+        debug!("tar of {} found in cache", package.name);
+
+        info!("Extracting {} to {:?}", package.name, path);
+        self.archiver.extract_from(&mut value.reader()?, path)
+    }
+
+    // A cache that can't be read or whose tarball doesn't match the shasum is treated like a
+    // miss, the tarball is downloaded again
+    async fn get_cached(&self, package: &NpmPackage, tarball_name: &str) -> Option<CachedValue> {
+        let value = match self.cache.get(tarball_name).await {
+            Ok(value) => value?,
+            Err(err) => {
+                warn!("Failed to read {} from the cache: {}", tarball_name, err);
+
+                return None;
+            }
+        };
+
+        match cached_shasum(&value) {
+            Ok(shasum) if shasum == package.shasum => Some(value),
+            Ok(shasum) => {
+                warn!(
+                    "Cached tarball of {} has shasum {} instead of {}, downloading it again",
+                    tarball_name, shasum, package.shasum
+                );
+
+                None
+            }
+            Err(err) => {
+                warn!("Failed to read {} from the cache: {}", tarball_name, err);
+
+                None
+            }
+        }
+    }
+
+    async fn lock(&self, tarball_name: &str) -> Result<CacheLock, JamError> {
+        let cache = self.cache.clone();
+        let key = tarball_name.to_string();
+
+        tokio::task::spawn_blocking(move || cache.lock(&key))
+            .await
+            .map_err(|err| JamError::new(format!("Failed to lock {}: {}", tarball_name, err)))?
+            .map_err(JamError::from)
+    }
+
+    // The response is hashed, written to the cache and extracted while it is being downloaded
+    async fn download_and_extract(
         &self,
         package: &NpmPackage,
         tarball_name: &str,
-    ) -> Result<PathBuf, JamError> {
-        let response = self.client.get(&package.tarball_url).send().await?;
-        let content = response.bytes().await?;
+        path: &Path,
+    ) -> Result<DownloadStats, JamError> {
+        let permit = self.limiter.acquire(&package.tarball_url).await?;
+        let mut response = self
+            .client
+            .get(&package.tarball_url)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let mut cache_writer = self.cache.writer(tarball_name)?;
+        let mut hasher = Sha1::new();
+        let mut bytes = 0;
+        let mut max_chunk_bytes = 0;
+
+        let gauge = Arc::new(BufferGauge::default());
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER_CHUNKS);
+        let mut reader = ChunkReader {
+            receiver,
+            chunk: Bytes::new(),
+            gauge: gauge.clone(),
+        };
+
+        let archiver = self.archiver.clone();
+        let target_path = path.to_path_buf();
+        let extraction =
+            tokio::task::spawn_blocking(move || archiver.extract_from(&mut reader, &target_path));
+
+        let mut sender = Some(sender);
+
+        while let Some(chunk) = response.chunk().await? {
+            hasher.update(&chunk);
+            cache_writer.write_all(&chunk)?;
+            bytes += chunk.len();
+            max_chunk_bytes = max_chunk_bytes.max(chunk.len());
 
-        let archive_path = self.cache.set(tarball_name, &content)?;
+            if let Some(chunk_sender) = &sender {
+                gauge.add(chunk.len());
 
-        Ok(archive_path)
+                // The archiver may stop reading before the end of the stream, the cache still needs the rest
+                if chunk_sender.send(chunk).await.is_err() {
+                    sender = None;
+                }
+            }
+        }
+
+        drop(sender);
+        drop(permit);
+
+        let extraction_result = extraction
+            .await
+            .map_err(|err| JamError::new(format!("Failed to extract {}: {}", package.name, err)))?;
+
+        let shasum = hex::encode(hasher.finalize());
+
+        if shasum != package.shasum {
+            return Err(JamError::new(format!(
+                "Integrity check failed for {}@{}: expected shasum {} but got {}",
+                package.name, package.version, package.shasum, shasum
+            )));
+        }
+
+        cache_writer.commit().await?;
+        extraction_result?;
+
+        Ok(DownloadStats {
+            bytes,
+            max_chunk_bytes,
+            max_buffered_bytes: gauge.max.load(Ordering::SeqCst),
+        })
     }
 }
 
+fn cached_shasum(value: &CachedValue) -> Result<String, JamError> {
+    let mut reader = value.reader()?;
+    let mut hasher = Sha1::new();
+    let mut buffer = [0; 8192];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
 #[async_trait]
-impl<'a> Downloader for TarDownloader<'a> {
+impl Downloader for TarDownloader {
     async fn download_to(&self, package: &NpmPackage, path: &Path) -> Result<(), JamError> {
         let tarball_name = format!("{}@{}", package.name, package.version);
 
-        let archive_path = match self.cache.get(&tarball_name) {
-            Some(file_path) => {
-                debug!("tar of {} found in cache", package.name);
-                file_path
-            }
-            None => {
-                let now = Instant::now();
-                let archive_path = self.download_tar(package, &tarball_name).await?;
-
-                debug!(
-                    "Downloaded {} package tar in {} milliseconds",
-                    package.name,
-                    now.elapsed().as_millis()
-                );
+        if let Some(value) = self.get_cached(package, &tarball_name).await {
+            return self.extract_cached(package, &value, path);
+        }
 
-                archive_path
-            }
-        };
+        let _lock = self.lock(&tarball_name).await?;
 
-        info!("Extracting {} to {:?}", package.name, path);
-        self.archiver.extract_to(&archive_path, path)?;
+        // Another process may have downloaded the tarball while we were waiting for the lock
+        if let Some(value) = self.get_cached(package, &tarball_name).await {
+            return self.extract_cached(package, &value, path);
+        }
+
+        let now = Instant::now();
+
+        info!("Downloading and extracting {} to {:?}", package.name, path);
+        let stats = self
+            .download_and_extract(package, &tarball_name, path)
+            .await?;
+
+        debug!(
+            "Downloaded {} package tar ({} bytes in chunks of up to {}, at most {} buffered) in {} milliseconds",
+            package.name,
+            stats.bytes,
+            stats.max_chunk_bytes,
+            stats.max_buffered_bytes,
+            now.elapsed().as_millis()
+        );
 
         Ok(())
     }
@@ -82,54 +281,94 @@ impl<'a> Downloader for TarDownloader<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::archiver::DefaultArchiver;
+    use jam_cache::{CacheBackend, CacheOptions};
+    use jam_core::http::ClientOptions;
+    use jam_core::limiter::LimiterOptions;
     use jam_test_utils::npm_mock_server::*;
     use maplit::hashmap;
+    use std::fs;
     use std::path::PathBuf;
-    use std::sync::{Arc, Mutex};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
     use tempdir::TempDir;
 
-    fn setup() -> (NpmMockServer, TempDir, CacheFactory) {
+    fn setup() -> (NpmMockServer, TempDir, CacheFactory, ClientFactory) {
         let npm_mock_server = NpmMockServer::new();
         let tmp_dir = TempDir::new("jam-downloader").unwrap();
 
         let cache_factory = CacheFactory::new(tmp_dir.path().to_path_buf());
+        let client_factory = ClientFactory::new(ClientOptions::default());
 
-        (npm_mock_server, tmp_dir, cache_factory)
+        (npm_mock_server, tmp_dir, cache_factory, client_factory)
+    }
+
+    fn cached_bytes(value: &CachedValue) -> Vec<u8> {
+        let mut bytes = vec![];
+        value.reader().unwrap().read_to_end(&mut bytes).unwrap();
+
+        bytes
+    }
+
+    fn limiter() -> Arc<RequestLimiter> {
+        Arc::new(RequestLimiter::new(LimiterOptions::default()).unwrap())
+    }
+
+    fn create_package(name: &str, shasum: String, tarball_url: String) -> NpmPackage {
+        NpmPackage::new(
+            name.to_string(),
+            "1.0.0".to_string(),
+            None,
+            shasum,
+            tarball_url,
+            vec![],
+        )
+    }
+
+    // Deterministic, poorly compressible content so the tarball stays large after gzip
+    fn generate_content(size: usize) -> String {
+        let alphabet = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let mut seed: u64 = 42;
+
+        (0..size)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                alphabet[(seed >> 33) as usize % alphabet.len()] as char
+            })
+            .collect()
     }
 
     #[tokio::test]
     async fn fails_when_archiver_fails() {
-        let (mut npm_mock_server, _, cache_factory) = setup();
+        let (mut npm_mock_server, _, cache_factory, client_factory) = setup();
 
         struct FailingArchiver {}
 
         impl Archiver for FailingArchiver {
-            fn extract_to(
-                &self,
-                _tarball_path: &Path,
-                _target_path: &Path,
-            ) -> Result<(), JamError> {
+            fn extract_from(&self, _: &mut dyn Read, _: &Path) -> Result<(), JamError> {
                 Err(JamError::new(String::from("Failing archiver")))
             }
         }
 
-        let package = NpmPackage::new(
-            "p1".to_string(),
-            "1.0.0".to_string(),
-            None,
-            "shasum".to_string(),
+        let shasum = npm_mock_server.with_tarball_data(
+            "p1",
+            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
+        );
+        let package = create_package(
+            "p1",
+            shasum,
             format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
-            vec![],
         );
         let path = PathBuf::new();
 
-        let archiver = FailingArchiver {};
-        let downloader = TarDownloader::new(&cache_factory, &archiver).unwrap();
-
-        npm_mock_server.with_tarball_data(
-            "p1",
-            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
-        );
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(FailingArchiver {}),
+        )
+        .unwrap();
 
         let result = downloader.download_to(&package, path.as_path()).await;
 
@@ -137,23 +376,15 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn calls_the_archiver_with_the_tar_path_and_target_path() {
-        let (mut npm_mock_server, tmp_dir, cache_factory) = setup();
+    async fn calls_the_archiver_with_the_target_path() {
+        let (mut npm_mock_server, tmp_dir, cache_factory, client_factory) = setup();
 
         struct MockArchiver {
-            pub called_with: Arc<Mutex<Vec<PathBuf>>>,
-        }
-
-        impl MockArchiver {
-            pub fn new() -> MockArchiver {
-                MockArchiver {
-                    called_with: Arc::new(Mutex::new(vec![])),
-                }
-            }
+            pub called_with: Mutex<Vec<PathBuf>>,
         }
 
         impl Archiver for MockArchiver {
-            fn extract_to(&self, _: &Path, target_path: &Path) -> Result<(), JamError> {
+            fn extract_from(&self, _: &mut dyn Read, target_path: &Path) -> Result<(), JamError> {
                 let mut lock = self.called_with.lock().unwrap();
 
                 (*lock).push(target_path.to_path_buf());
@@ -162,35 +393,33 @@ mod tests {
             }
         }
 
-        let package = NpmPackage::new(
-            "p1".to_string(),
-            "1.0.0".to_string(),
-            None,
-            "shasum".to_string(),
-            format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
-            vec![],
+        let shasum = npm_mock_server.with_tarball_data(
+            "p1",
+            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
         );
-        let scoped_package = NpmPackage::new(
-            "@scoped/p1".to_string(),
-            "2.0.0".to_string(),
-            None,
-            "shasum".to_string(),
-            format!("{}/tarball/{}", npm_mock_server.url(), "%40scoped%2Fp2"),
-            vec![],
+        let scoped_shasum = npm_mock_server.with_tarball_data(
+            "@scoped/p2",
+            hashmap! { "index.js".to_string() => "const x = 2".to_string() },
         );
 
-        let archiver = MockArchiver::new();
-        let downloader = TarDownloader::new(&cache_factory, &archiver).unwrap();
-
-        npm_mock_server.with_tarball_data(
+        let package = create_package(
             "p1",
-            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
+            shasum,
+            format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
         );
-        npm_mock_server.with_tarball_data(
+        let scoped_package = create_package(
             "@scoped/p2",
-            hashmap! { "index.js".to_string() => "const x = 2".to_string() },
+            scoped_shasum,
+            format!("{}/tarball/{}", npm_mock_server.url(), "%40scoped%2Fp2"),
         );
 
+        let archiver = Arc::new(MockArchiver {
+            called_with: Mutex::new(vec![]),
+        });
+        let downloader =
+            TarDownloader::new(&cache_factory, &client_factory, limiter(), archiver.clone())
+                .unwrap();
+
         downloader
             .download_to(&package, tmp_dir.path().join("p1").as_path())
             .await
@@ -209,4 +438,272 @@ mod tests {
 
         assert_eq!(*called_with, expected_paths);
     }
+
+    #[tokio::test]
+    async fn extracts_and_caches_the_downloaded_tarball() {
+        let (mut npm_mock_server, tmp_dir, cache_factory, client_factory) = setup();
+
+        let shasum = npm_mock_server.with_tarball_data(
+            "p1",
+            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
+        );
+        let package = create_package(
+            "p1",
+            shasum.clone(),
+            format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
+        );
+
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(DefaultArchiver::new()),
+        )
+        .unwrap();
+
+        downloader
+            .download_to(&package, tmp_dir.path().join("p1").as_path())
+            .await
+            .unwrap();
+
+        let cached_tarball = downloader.cache.get("p1@1.0.0").await.unwrap().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join("p1").join("index.js")).unwrap(),
+            "const x = 1"
+        );
+        assert_eq!(
+            hex::encode(Sha1::digest(&cached_bytes(&cached_tarball))),
+            shasum
+        );
+    }
+
+    #[tokio::test]
+    async fn extracts_tarballs_from_the_memory_cache() {
+        let (mut npm_mock_server, tmp_dir, _, client_factory) = setup();
+        let cache_factory = CacheFactory::with_options(
+            tmp_dir.path().to_path_buf(),
+            CacheOptions {
+                backend: CacheBackend::Memory,
+                ..CacheOptions::default()
+            },
+            Client::new(),
+        );
+
+        let shasum = npm_mock_server.with_tarball_data(
+            "p1",
+            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
+        );
+        let package = create_package(
+            "p1",
+            shasum.clone(),
+            format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
+        );
+        // Only resolvable from the cache, the registry doesn't serve it
+        let cached_package = create_package(
+            "p1",
+            shasum.clone(),
+            format!("{}/tarball/{}", npm_mock_server.url(), "missing"),
+        );
+
+        TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(DefaultArchiver::new()),
+        )
+        .unwrap()
+        .download_to(&package, tmp_dir.path().join("p1").as_path())
+        .await
+        .unwrap();
+
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(DefaultArchiver::new()),
+        )
+        .unwrap();
+
+        downloader
+            .download_to(&cached_package, tmp_dir.path().join("cached").as_path())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join("cached").join("index.js")).unwrap(),
+            "const x = 1"
+        );
+        assert!(!tmp_dir.path().join("tarballs").exists());
+        assert!(matches!(
+            downloader.cache.get("p1@1.0.0").await.unwrap(),
+            Some(CachedValue::Bytes(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn downloads_again_when_the_cached_tarball_does_not_match_the_shasum() {
+        let (mut npm_mock_server, tmp_dir, cache_factory, client_factory) = setup();
+
+        let shasum = npm_mock_server.with_tarball_data(
+            "p1",
+            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
+        );
+        let package = create_package(
+            "p1",
+            shasum,
+            format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
+        );
+
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(DefaultArchiver::new()),
+        )
+        .unwrap();
+
+        let mut cache_writer = downloader.cache.writer("p1@1.0.0").unwrap();
+        cache_writer.write_all(b"tampered").unwrap();
+        cache_writer.commit().await.unwrap();
+
+        downloader
+            .download_to(&package, tmp_dir.path().join("p1").as_path())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join("p1").join("index.js")).unwrap(),
+            "const x = 1"
+        );
+        let cached_tarball = downloader.cache.get("p1@1.0.0").await.unwrap().unwrap();
+        assert_eq!(cached_shasum(&cached_tarball).unwrap(), package.shasum);
+    }
+
+    #[tokio::test]
+    async fn fails_and_does_not_cache_on_shasum_mismatch() {
+        let (mut npm_mock_server, tmp_dir, cache_factory, client_factory) = setup();
+
+        npm_mock_server.with_tarball_data(
+            "p1",
+            hashmap! { "index.js".to_string() => "const x = 1".to_string() },
+        );
+        let package = create_package(
+            "p1",
+            "bad-shasum".to_string(),
+            format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
+        );
+
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(DefaultArchiver::new()),
+        )
+        .unwrap();
+
+        let result = downloader
+            .download_to(&package, tmp_dir.path().join("p1").as_path())
+            .await;
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Integrity check failed for p1@1.0.0"));
+        assert_eq!(downloader.cache.get("p1@1.0.0").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_tarball_is_missing() {
+        let (npm_mock_server, tmp_dir, cache_factory, client_factory) = setup();
+
+        let package = create_package(
+            "p1",
+            "shasum".to_string(),
+            format!("{}/tarball/{}", npm_mock_server.url(), "p1"),
+        );
+
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(DefaultArchiver::new()),
+        )
+        .unwrap();
+
+        let result = downloader
+            .download_to(&package, tmp_dir.path().join("p1").as_path())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(downloader.cache.get("p1@1.0.0").await.unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn streams_large_tarballs_with_bounded_buffering() {
+        let (mut npm_mock_server, tmp_dir, cache_factory, client_factory) = setup();
+
+        // Extracts slower than the local server responds, so an unbounded pipeline would buffer the whole tarball
+        struct SlowArchiver {
+            archiver: DefaultArchiver,
+        }
+
+        struct SlowReader<'a> {
+            inner: &'a mut dyn Read,
+        }
+
+        impl<'a> Read for SlowReader<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                thread::sleep(Duration::from_millis(1));
+                self.inner.read(buf)
+            }
+        }
+
+        impl Archiver for SlowArchiver {
+            fn extract_from(
+                &self,
+                tar_gz: &mut dyn Read,
+                target_path: &Path,
+            ) -> Result<(), JamError> {
+                self.archiver
+                    .extract_from(&mut SlowReader { inner: tar_gz }, target_path)
+            }
+        }
+
+        let content = generate_content(16 * 1024 * 1024);
+        let shasum = npm_mock_server.with_tarball_data(
+            "large",
+            hashmap! { "large.txt".to_string() => content.clone() },
+        );
+        let package = create_package(
+            "large",
+            shasum,
+            format!("{}/tarball/{}", npm_mock_server.url(), "large"),
+        );
+
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            limiter(),
+            Arc::new(SlowArchiver {
+                archiver: DefaultArchiver::new(),
+            }),
+        )
+        .unwrap();
+
+        let target_path = tmp_dir.path().join("large");
+        let stats = downloader
+            .download_and_extract(&package, "large@1.0.0", &target_path)
+            .await
+            .unwrap();
+
+        // Chunks queued in the channel, plus the one being read and the one being sent
+        assert!(stats.max_buffered_bytes <= (STREAM_BUFFER_CHUNKS + 2) * stats.max_chunk_bytes);
+        assert!(stats.max_buffered_bytes < stats.bytes / 2);
+        assert_eq!(
+            fs::read_to_string(target_path.join("large.txt")).unwrap(),
+            content
+        );
+        assert_ne!(downloader.cache.get("large@1.0.0").await.unwrap(), None);
+    }
 }
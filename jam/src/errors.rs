@@ -18,9 +18,7 @@ impl JamError {
 
 impl Display for JamError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        match *self {
-            _ => write!(f, "{}", self.message),
-        }
+        write!(f, "{}", self.message)
     }
 }
 
@@ -50,7 +48,7 @@ impl From<String> for JamError {
 
 impl From<JamCoreError> for JamError {
     fn from(error: JamCoreError) -> Self {
-        JamError::new(error.message)
+        JamError::new(error.to_string())
     }
 }
 
@@ -0,0 +1,718 @@
+use crate::errors::JamError;
+use crate::store::{PruneOptions, PruneStats};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Error, Formatter};
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub const FILES_DIR_NAME: &str = "files";
+pub const INDEX_DIR_NAME: &str = "index";
+const EXECUTABLE_SUFFIX: &str = "-exec";
+const EMLINK: i32 = 31;
+const PARTIAL_FILE_SUFFIX: &str = ".partial";
+
+static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexedFile {
+    pub hash: String,
+    pub size: u64,
+    pub executable: bool,
+}
+
+// Keyed by the file path relative to the package directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PackageIndex {
+    pub files: BTreeMap<String, IndexedFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkStats {
+    pub files: u64,
+    pub bytes: u64,
+    pub saved_bytes: u64,
+}
+
+impl LinkStats {
+    pub fn add(&mut self, other: LinkStats) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.saved_bytes += other.saved_bytes;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileProblem {
+    Missing(String),
+    Modified(String),
+    Added(String),
+}
+
+impl Display for FileProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            FileProblem::Missing(path) => write!(f, "missing {}", path),
+            FileProblem::Modified(path) => write!(f, "modified {}", path),
+            FileProblem::Added(path) => write!(f, "added {}", path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LinkMethod {
+    Reflink,
+    Hardlink,
+    Copy,
+}
+
+// Every file is stored once by its content hash, package directories only link to them
+pub struct FileStore {
+    files_path: PathBuf,
+    index_path: PathBuf,
+    reflink_supported: AtomicBool,
+    hardlink_supported: AtomicBool,
+}
+
+impl FileStore {
+    pub fn new(store_path: &Path) -> Result<FileStore, JamError> {
+        let files_path = store_path.join(FILES_DIR_NAME);
+        let index_path = store_path.join(INDEX_DIR_NAME);
+
+        fs::create_dir_all(&files_path)?;
+        fs::create_dir_all(&index_path)?;
+
+        Ok(FileStore {
+            files_path,
+            index_path,
+            reflink_supported: AtomicBool::new(true),
+            hardlink_supported: AtomicBool::new(true),
+        })
+    }
+
+    pub fn read_index(&self, key: &str) -> Result<Option<PackageIndex>, JamError> {
+        let content = match fs::read(self.index_file_path(key)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|err| JamError::new(format!("Failed to read index of {}: {}", key, err)))
+    }
+
+    // Moves the extracted package files into the store and replaces them with links to the stored copies
+    pub fn import_package(&self, key: &str, package_path: &Path) -> Result<LinkStats, JamError> {
+        let mut index = PackageIndex::default();
+        let mut stats = LinkStats::default();
+
+        for relative_path in list_files(package_path)? {
+            let path = package_path.join(&relative_path);
+            let metadata = fs::metadata(&path)?;
+            let indexed_file = IndexedFile {
+                hash: hash_file(&path)?,
+                size: metadata.len(),
+                executable: metadata.permissions().mode() & 0o111 != 0,
+            };
+
+            let stored_path = self.stored_file_path(&indexed_file);
+            if !self.store_file(&path, &stored_path)? {
+                fs::remove_file(&path)?;
+                if self.link_file(&stored_path, &path)? != LinkMethod::Copy {
+                    stats.saved_bytes += indexed_file.size;
+                }
+            }
+
+            stats.files += 1;
+            stats.bytes += indexed_file.size;
+            index
+                .files
+                .insert(relative_path.to_string_lossy().to_string(), indexed_file);
+        }
+
+        self.write_index(key, &index)?;
+
+        Ok(stats)
+    }
+
+    // Rebuilds a package directory from its index, without downloading or extracting its tarball
+    pub fn materialize_package(
+        &self,
+        index: &PackageIndex,
+        target_path: &Path,
+    ) -> Result<LinkStats, JamError> {
+        let mut stats = LinkStats::default();
+
+        for (relative_path, indexed_file) in &index.files {
+            let stored_path = self.stored_file_path(indexed_file);
+            let path = target_path.join(relative_path);
+
+            if !stored_path.exists() {
+                return Err(JamError::new(format!(
+                    "File {} is missing from the store",
+                    indexed_file.hash
+                )));
+            }
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            if self.link_file(&stored_path, &path)? != LinkMethod::Copy {
+                stats.saved_bytes += indexed_file.size;
+            }
+            stats.files += 1;
+            stats.bytes += indexed_file.size;
+        }
+
+        Ok(stats)
+    }
+
+    // Compares a package directory with the files recorded when it was imported
+    pub fn verify_package(
+        &self,
+        index: &PackageIndex,
+        package_path: &Path,
+    ) -> Result<Vec<FileProblem>, JamError> {
+        let mut problems = vec![];
+
+        for (relative_path, indexed_file) in &index.files {
+            let path = package_path.join(relative_path);
+
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_file() => {
+                    if !is_intact(&path, &metadata, indexed_file)? {
+                        problems.push(FileProblem::Modified(relative_path.clone()));
+                    }
+                }
+                Ok(_) => problems.push(FileProblem::Modified(relative_path.clone())),
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    problems.push(FileProblem::Missing(relative_path.clone()))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if package_path.is_dir() {
+            for relative_path in list_files(package_path)? {
+                let relative_path = relative_path.to_string_lossy().to_string();
+
+                if !index.files.contains_key(&relative_path) {
+                    problems.push(FileProblem::Added(relative_path));
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    // A package file written to in place also changes the stored file it is hardlinked to,
+    // which has to go before the package is imported again
+    pub fn remove_corrupted_files(&self, index: &PackageIndex) -> Result<u64, JamError> {
+        let mut removed = 0;
+
+        for indexed_file in index.files.values() {
+            let stored_path = self.stored_file_path(indexed_file);
+
+            if let Ok(metadata) = fs::metadata(&stored_path) {
+                if !is_intact(&stored_path, &metadata, indexed_file)? {
+                    info!("Removing corrupted stored file {:?}", stored_path);
+
+                    fs::remove_file(&stored_path)?;
+                    removed += 1;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    // Number and total size of the stored files
+    pub fn size(&self) -> Result<(u64, u64), JamError> {
+        let mut files = 0;
+        let mut bytes = 0;
+
+        for relative_path in list_files(&self.files_path)? {
+            files += 1;
+            bytes += fs::metadata(self.files_path.join(relative_path))?.len();
+        }
+
+        Ok((files, bytes))
+    }
+
+    // Removes the indexes of packages that are not kept and every stored file none of the kept
+    // indexes point to. Files that changed within the grace period may belong to a running install.
+    pub fn prune<F: Fn(&str) -> bool>(
+        &self,
+        keep_index: F,
+        options: &PruneOptions,
+    ) -> Result<PruneStats, JamError> {
+        let mut stats = PruneStats::default();
+        let mut referenced_files = HashSet::new();
+
+        for entry in fs::read_dir(&self.index_path)? {
+            let entry = entry?;
+            let path = entry.path();
+            let metadata = entry.metadata()?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+
+            let key = match file_name.strip_suffix(".json") {
+                Some(key) if !file_name.starts_with('.') => key.to_string(),
+                _ => {
+                    if !options.is_recent(&metadata) {
+                        options.remove_file(&path, &metadata, &mut stats)?;
+                    }
+                    continue;
+                }
+            };
+
+            if !keep_index(&key) && !options.is_recent(&metadata) {
+                options.remove_file(&path, &metadata, &mut stats)?;
+                continue;
+            }
+
+            match self.read_index(&key) {
+                Ok(Some(index)) => referenced_files.extend(
+                    index
+                        .files
+                        .values()
+                        .map(|indexed_file| self.stored_file_path(indexed_file)),
+                ),
+                Ok(None) => {}
+                // The package directory doesn't depend on its index, it is only needed to rebuild it
+                Err(err) => {
+                    info!("Removing unreadable index {:?}: {}", path, err);
+
+                    options.remove_file(&path, &metadata, &mut stats)?;
+                }
+            }
+        }
+
+        for prefix_entry in fs::read_dir(&self.files_path)? {
+            let prefix_entry = prefix_entry?;
+            if !prefix_entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(prefix_entry.path())? {
+                let entry = entry?;
+                let path = entry.path();
+                let metadata = entry.metadata()?;
+
+                if !referenced_files.contains(&path) && !options.is_recent(&metadata) {
+                    options.remove_file(&path, &metadata, &mut stats)?;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn index_file_path(&self, key: &str) -> PathBuf {
+        self.index_path.join(format!("{}.json", key))
+    }
+
+    // The mode is part of the stored file, so identical content with different modes is kept twice
+    fn stored_file_path(&self, indexed_file: &IndexedFile) -> PathBuf {
+        let file_name = if indexed_file.executable {
+            format!("{}{}", &indexed_file.hash[2..], EXECUTABLE_SUFFIX)
+        } else {
+            indexed_file.hash[2..].to_string()
+        };
+
+        self.files_path
+            .join(&indexed_file.hash[..2])
+            .join(file_name)
+    }
+
+    // Returns false when the content was already stored
+    fn store_file(&self, path: &Path, stored_path: &Path) -> Result<bool, JamError> {
+        if stored_path.exists() {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(stored_path.parent().unwrap())?;
+
+        match fs::hard_link(path, stored_path) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(_) => {
+                // Stored files must never be seen half written, another install may link them
+                let temp_path = self.temp_path(stored_path);
+                fs::copy(path, &temp_path)?;
+                fs::rename(&temp_path, stored_path)?;
+
+                Ok(true)
+            }
+        }
+    }
+
+    // Unsupported methods are remembered, so a store on a filesystem without reflinks
+    // doesn't pay for a failed attempt on every file
+    fn link_file(&self, stored_path: &Path, path: &Path) -> Result<LinkMethod, JamError> {
+        if self.reflink_supported.load(Ordering::Relaxed) {
+            match reflink::reflink(stored_path, path) {
+                Ok(_) => {
+                    fs::set_permissions(path, fs::metadata(stored_path)?.permissions())?;
+
+                    return Ok(LinkMethod::Reflink);
+                }
+                Err(err) => self.on_link_error(&self.reflink_supported, err)?,
+            }
+        }
+
+        if self.hardlink_supported.load(Ordering::Relaxed) {
+            match fs::hard_link(stored_path, path) {
+                Ok(_) => return Ok(LinkMethod::Hardlink),
+                // Hitting the filesystem link count limit only affects this file
+                Err(err) if err.raw_os_error() == Some(EMLINK) => {}
+                Err(err) => self.on_link_error(&self.hardlink_supported, err)?,
+            }
+        }
+
+        fs::copy(stored_path, path)?;
+
+        Ok(LinkMethod::Copy)
+    }
+
+    fn on_link_error(&self, supported: &AtomicBool, err: io::Error) -> Result<(), JamError> {
+        match err.kind() {
+            ErrorKind::AlreadyExists | ErrorKind::NotFound => Err(err.into()),
+            _ => {
+                supported.store(false, Ordering::Relaxed);
+
+                Ok(())
+            }
+        }
+    }
+
+    fn write_index(&self, key: &str, index: &PackageIndex) -> Result<(), JamError> {
+        let index_file_path = self.index_file_path(key);
+        let temp_path = self.temp_path(&index_file_path);
+        let content = serde_json::to_vec(index)
+            .map_err(|err| JamError::new(format!("Failed to write index of {}: {}", key, err)))?;
+
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &index_file_path)?;
+
+        Ok(())
+    }
+
+    fn temp_path(&self, path: &Path) -> PathBuf {
+        path.with_file_name(format!(
+            ".{}.{}.{}{}",
+            path.file_name().unwrap().to_string_lossy(),
+            process::id(),
+            NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed),
+            PARTIAL_FILE_SUFFIX
+        ))
+    }
+}
+
+fn is_intact(
+    path: &Path,
+    metadata: &fs::Metadata,
+    indexed_file: &IndexedFile,
+) -> Result<bool, JamError> {
+    let executable = metadata.permissions().mode() & 0o111 != 0;
+
+    Ok(metadata.len() == indexed_file.size
+        && executable == indexed_file.executable
+        && hash_file(path)? == indexed_file.hash)
+}
+
+fn hash_file(path: &Path) -> Result<String, JamError> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Symlinks shipped inside tarballs are left in place, only regular files are stored
+pub fn list_files(path: &Path) -> Result<Vec<PathBuf>, JamError> {
+    let mut files = vec![];
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(path.join(&dir))? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let relative_path = dir.join(entry.file_name());
+
+            if file_type.is_dir() {
+                dirs.push(relative_path);
+            } else if file_type.is_file() {
+                files.push(relative_path);
+            }
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jam_test_utils::sync_helpers::with_tmp_dir;
+    use std::os::unix::fs::MetadataExt;
+
+    fn create_package(path: &Path, files: Vec<(&str, &str, u32)>) {
+        for (relative_path, content, mode) in files {
+            let file_path = path.join(relative_path);
+
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(&file_path, content).unwrap();
+            fs::set_permissions(&file_path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+    }
+
+    #[test]
+    fn shares_identical_files_between_packages() {
+        with_tmp_dir(|path| {
+            let file_store = FileStore::new(&path.join("store")).unwrap();
+            file_store.reflink_supported.store(false, Ordering::Relaxed);
+            create_package(
+                &path.join("p1"),
+                vec![("index.js", "const x = 1", 0o644), ("lib/a.js", "a", 0o644)],
+            );
+            create_package(
+                &path.join("p2"),
+                vec![("index.js", "const x = 1", 0o644), ("lib/a.js", "b", 0o644)],
+            );
+
+            let p1_stats = file_store
+                .import_package("p1@1.0.0", &path.join("p1"))
+                .unwrap();
+            let p2_stats = file_store
+                .import_package("p1@2.0.0", &path.join("p2"))
+                .unwrap();
+
+            let p1_index = fs::metadata(path.join("p1").join("index.js")).unwrap();
+            let p2_index = fs::metadata(path.join("p2").join("index.js")).unwrap();
+
+            assert_eq!(p1_stats.saved_bytes, 0);
+            assert_eq!(p2_stats.files, 2);
+            assert_eq!(p2_stats.bytes, 12);
+            assert_eq!(p2_stats.saved_bytes, 11);
+            assert_eq!(p1_index.ino(), p2_index.ino());
+            assert_ne!(
+                fs::metadata(path.join("p1").join("lib/a.js"))
+                    .unwrap()
+                    .ino(),
+                fs::metadata(path.join("p2").join("lib/a.js"))
+                    .unwrap()
+                    .ino()
+            );
+        })
+    }
+
+    #[test]
+    fn writes_package_index() {
+        with_tmp_dir(|path| {
+            let file_store = FileStore::new(&path.join("store")).unwrap();
+            create_package(
+                &path.join("p1"),
+                vec![("index.js", "", 0o644), ("bin/cli.js", "", 0o755)],
+            );
+
+            file_store
+                .import_package("p1@1.0.0", &path.join("p1"))
+                .unwrap();
+            let index = file_store.read_index("p1@1.0.0").unwrap().unwrap();
+
+            assert_eq!(
+                index.files.keys().collect::<Vec<&String>>(),
+                vec!["bin/cli.js", "index.js"]
+            );
+            assert!(index.files["bin/cli.js"].executable);
+            assert!(!index.files["index.js"].executable);
+            assert_eq!(
+                index.files["index.js"].hash,
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+            );
+            assert_eq!(file_store.read_index("p2@1.0.0").unwrap(), None);
+        })
+    }
+
+    #[test]
+    fn materializes_packages_from_the_index() {
+        with_tmp_dir(|path| {
+            let file_store = FileStore::new(&path.join("store")).unwrap();
+            create_package(
+                &path.join("p1"),
+                vec![
+                    ("index.js", "const x = 1", 0o644),
+                    ("bin/cli.js", "cli", 0o755),
+                ],
+            );
+            file_store
+                .import_package("p1@1.0.0", &path.join("p1"))
+                .unwrap();
+            let index = file_store.read_index("p1@1.0.0").unwrap().unwrap();
+
+            let stats = file_store
+                .materialize_package(&index, &path.join("target"))
+                .unwrap();
+
+            let cli_path = path.join("target").join("bin").join("cli.js");
+            assert_eq!(stats.files, 2);
+            assert_eq!(stats.saved_bytes, 14);
+            assert_eq!(fs::read_to_string(&cli_path).unwrap(), "cli");
+            assert_eq!(
+                fs::metadata(&cli_path).unwrap().permissions().mode() & 0o777,
+                0o755
+            );
+        })
+    }
+
+    #[test]
+    fn copies_files_when_links_are_not_supported() {
+        with_tmp_dir(|path| {
+            let file_store = FileStore::new(&path.join("store")).unwrap();
+            create_package(&path.join("p1"), vec![("bin/cli.js", "cli", 0o755)]);
+            file_store
+                .import_package("p1@1.0.0", &path.join("p1"))
+                .unwrap();
+            let index = file_store.read_index("p1@1.0.0").unwrap().unwrap();
+
+            file_store.reflink_supported.store(false, Ordering::Relaxed);
+            file_store
+                .hardlink_supported
+                .store(false, Ordering::Relaxed);
+            let stats = file_store
+                .materialize_package(&index, &path.join("target"))
+                .unwrap();
+
+            let cli_path = path.join("target").join("bin").join("cli.js");
+            let cli_metadata = fs::metadata(&cli_path).unwrap();
+            assert_eq!(stats.saved_bytes, 0);
+            assert_eq!(cli_metadata.permissions().mode() & 0o777, 0o755);
+            assert_ne!(
+                cli_metadata.ino(),
+                fs::metadata(path.join("p1").join("bin").join("cli.js"))
+                    .unwrap()
+                    .ino()
+            );
+        })
+    }
+
+    #[test]
+    fn fails_to_materialize_missing_files() {
+        with_tmp_dir(|path| {
+            let file_store = FileStore::new(&path.join("store")).unwrap();
+            create_package(&path.join("p1"), vec![("index.js", "const x = 1", 0o644)]);
+            file_store
+                .import_package("p1@1.0.0", &path.join("p1"))
+                .unwrap();
+            let index = file_store.read_index("p1@1.0.0").unwrap().unwrap();
+
+            fs::remove_dir_all(path.join("store").join(FILES_DIR_NAME)).unwrap();
+
+            assert!(file_store
+                .materialize_package(&index, &path.join("target"))
+                .is_err());
+        })
+    }
+
+    #[test]
+    fn reports_modified_missing_and_added_files() {
+        with_tmp_dir(|path| {
+            let file_store = FileStore::new(&path.join("store")).unwrap();
+            let package_path = path.join("p1");
+            create_package(
+                &package_path,
+                vec![
+                    ("index.js", "const x = 1", 0o644),
+                    ("lib/a.js", "a", 0o644),
+                    ("bin/cli.js", "cli", 0o755),
+                    ("README.md", "readme", 0o644),
+                ],
+            );
+            file_store
+                .import_package("p1@1.0.0", &package_path)
+                .unwrap();
+            let index = file_store.read_index("p1@1.0.0").unwrap().unwrap();
+
+            assert_eq!(
+                file_store.verify_package(&index, &package_path).unwrap(),
+                vec![]
+            );
+
+            fs::write(package_path.join("index.js"), "const x = 2").unwrap();
+            fs::remove_file(package_path.join("lib/a.js")).unwrap();
+            fs::set_permissions(
+                package_path.join("bin/cli.js"),
+                fs::Permissions::from_mode(0o644),
+            )
+            .unwrap();
+            fs::write(package_path.join("lib/b.js"), "b").unwrap();
+
+            assert_eq!(
+                file_store.verify_package(&index, &package_path).unwrap(),
+                vec![
+                    FileProblem::Modified("bin/cli.js".to_string()),
+                    FileProblem::Modified("index.js".to_string()),
+                    FileProblem::Missing("lib/a.js".to_string()),
+                    FileProblem::Added("lib/b.js".to_string()),
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn removes_stored_files_modified_through_links() {
+        with_tmp_dir(|path| {
+            let file_store = FileStore::new(&path.join("store")).unwrap();
+            file_store.reflink_supported.store(false, Ordering::Relaxed);
+            create_package(&path.join("p1"), vec![("index.js", "const x = 1", 0o644)]);
+            create_package(&path.join("p2"), vec![("index.js", "const x = 1", 0o644)]);
+            file_store
+                .import_package("p1@1.0.0", &path.join("p1"))
+                .unwrap();
+            file_store
+                .import_package("p2@1.0.0", &path.join("p2"))
+                .unwrap();
+            let index = file_store.read_index("p1@1.0.0").unwrap().unwrap();
+
+            fs::write(path.join("p2").join("index.js"), "const x = 2").unwrap();
+
+            assert_eq!(file_store.remove_corrupted_files(&index).unwrap(), 1);
+            assert_eq!(file_store.size().unwrap(), (0, 0));
+            assert_eq!(file_store.remove_corrupted_files(&index).unwrap(), 0);
+        })
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MB");
+    }
+}
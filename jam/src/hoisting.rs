@@ -0,0 +1,585 @@
+use crate::errors::JamError;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use jam_core::package::{NpmPackage, Package};
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::Bfs;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+// Transitive packages matching `public` are linked into the root node_modules, the ones matching
+// `hidden` into `node_modules/.jam/node_modules`, for packages requiring undeclared dependencies
+#[derive(Debug, Clone)]
+pub struct HoistPatterns {
+    public: Vec<String>,
+    hidden: Vec<String>,
+    public_set: GlobSet,
+    hidden_set: GlobSet,
+}
+
+impl HoistPatterns {
+    pub fn new(public: Vec<String>, hidden: Vec<String>) -> Result<HoistPatterns, JamError> {
+        Ok(HoistPatterns {
+            public_set: build_glob_set(&public, "publicHoistPattern")?,
+            hidden_set: build_glob_set(&hidden, "hoistPattern")?,
+            public,
+            hidden,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.public.is_empty() && self.hidden.is_empty()
+    }
+
+    // The version closest to the workspace packages wins when several match the same name
+    pub fn select<'a>(
+        &self,
+        starting_nodes: &[NodeIndex],
+        graph: &'a Graph<Package, ()>,
+    ) -> (Vec<&'a NpmPackage>, Vec<&'a NpmPackage>) {
+        let mut selected: HashMap<&str, &NpmPackage> = HashMap::new();
+        let mut order = vec![];
+
+        for node in starting_nodes {
+            let mut bfs = Bfs::new(graph, *node);
+            while let Some(nx) = bfs.next(graph) {
+                if let Package::NpmPackage(npm_package) = &graph[nx] {
+                    if !selected.contains_key(npm_package.name.as_str()) {
+                        selected.insert(&npm_package.name, npm_package);
+                        order.push(npm_package);
+                    }
+                }
+            }
+        }
+
+        let public = order
+            .iter()
+            .filter(|package| self.public_set.is_match(&package.name))
+            .copied()
+            .collect();
+        let hidden = order
+            .iter()
+            .filter(|package| self.hidden_set.is_match(&package.name))
+            .copied()
+            .collect();
+
+        (public, hidden)
+    }
+}
+
+impl Default for HoistPatterns {
+    fn default() -> HoistPatterns {
+        HoistPatterns::new(vec![], vec![]).unwrap()
+    }
+}
+
+impl PartialEq for HoistPatterns {
+    fn eq(&self, other: &HoistPatterns) -> bool {
+        self.public == other.public && self.hidden == other.hidden
+    }
+}
+
+fn build_glob_set(patterns: &[String], setting: &str) -> Result<GlobSet, JamError> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|err| {
+            JamError::new(format!(
+                "Invalid pattern '{}' in '{}': {}",
+                pattern, setting, err
+            ))
+        })?);
+    }
+
+    builder
+        .build()
+        .map_err(|err| JamError::new(format!("Invalid patterns in '{}': {}", setting, err)))
+}
+
+// A package copy (or a link, for workspace packages) at `<parent>/node_modules/<name>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub package: NodeIndex,
+    pub path: PathBuf,
+}
+
+// A directory node resolves packages from: the project root, a workspace package or a placed copy
+struct Location {
+    package: Option<NodeIndex>,
+    parent: Option<usize>,
+    path: PathBuf,
+    children: HashMap<String, usize>,
+    // Dependencies of processed locations are already placed, moving them would break resolution
+    processed: bool,
+}
+
+struct Hoister<'a> {
+    graph: &'a Graph<Package, ()>,
+    locations: Vec<Location>,
+    placements: Vec<usize>,
+    // Copied workspace packages resolve their dependencies from the tree like npm packages
+    copy_workspaces: bool,
+}
+
+// Computes a flat node_modules tree: every dependency is placed as close to the root as possible,
+// and nested under its dependent only when a different version already holds its name. Placements
+// are ordered parents first.
+pub fn hoist(
+    root_path: &Path,
+    starting_nodes: &[NodeIndex],
+    graph: &Graph<Package, ()>,
+) -> Result<Vec<Placement>, JamError> {
+    let mut hoister = Hoister::new(root_path, graph, false);
+    let mut queue = VecDeque::new();
+
+    for node in starting_nodes {
+        let path = match &graph[*node] {
+            Package::WorkspacePackage(workspace_package) => workspace_package.base_path.clone(),
+            Package::NpmPackage(npm_package) => {
+                return Err(JamError::new(format!(
+                    "Expected a workspace package, got {}",
+                    npm_package.name
+                )))
+            }
+        };
+
+        // A workspace package at the root shares its node_modules
+        if path == root_path {
+            hoister.locations[0].package = Some(*node);
+            continue;
+        }
+
+        queue.push_back(hoister.add_location(Some(*node), Some(0), path));
+    }
+    queue.push_front(0);
+
+    hoister.place_dependencies(queue)
+}
+
+// Like `hoist` for a single workspace package deployed at `root_path`, its workspace dependencies
+// are placed as copies instead of links
+pub fn hoist_deployed(
+    root_path: &Path,
+    workspace_node: NodeIndex,
+    graph: &Graph<Package, ()>,
+) -> Result<Vec<Placement>, JamError> {
+    let mut hoister = Hoister::new(root_path, graph, true);
+    hoister.locations[0].package = Some(workspace_node);
+
+    hoister.place_dependencies(VecDeque::from(vec![0]))
+}
+
+impl<'a> Hoister<'a> {
+    fn new(root_path: &Path, graph: &'a Graph<Package, ()>, copy_workspaces: bool) -> Hoister<'a> {
+        Hoister {
+            graph,
+            locations: vec![Location {
+                package: None,
+                parent: None,
+                path: root_path.to_path_buf(),
+                children: HashMap::new(),
+                processed: false,
+            }],
+            placements: vec![],
+            copy_workspaces,
+        }
+    }
+
+    fn place_dependencies(
+        mut self,
+        mut queue: VecDeque<usize>,
+    ) -> Result<Vec<Placement>, JamError> {
+        let graph = self.graph;
+
+        while let Some(location) = queue.pop_front() {
+            let package = match self.locations[location].package {
+                Some(package) => package,
+                None => continue,
+            };
+
+            let mut dependencies: Vec<NodeIndex> = graph.neighbors(package).collect();
+            dependencies.sort_by(|a, b| graph[*a].name().cmp(graph[*b].name()));
+            dependencies.dedup();
+
+            for dependency in dependencies {
+                if let Some(placed) = self.place(location, dependency)? {
+                    // Linked workspace packages resolve their dependencies from their own directory
+                    if self.copy_workspaces || matches!(graph[dependency], Package::NpmPackage(_)) {
+                        queue.push_back(placed);
+                    }
+                }
+            }
+
+            self.locations[location].processed = true;
+        }
+
+        Ok(self
+            .placements
+            .iter()
+            .map(|location| Placement {
+                package: self.locations[*location].package.unwrap(),
+                path: self.locations[*location].path.clone(),
+            })
+            .collect())
+    }
+
+    fn add_location(
+        &mut self,
+        package: Option<NodeIndex>,
+        parent: Option<usize>,
+        path: PathBuf,
+    ) -> usize {
+        self.locations.push(Location {
+            package,
+            parent,
+            path,
+            children: HashMap::new(),
+            processed: false,
+        });
+
+        self.locations.len() - 1
+    }
+
+    // Returns the new location, or None when an existing copy already resolves for `from`
+    fn place(&mut self, from: usize, package: NodeIndex) -> Result<Option<usize>, JamError> {
+        let name = self.graph[package].name().to_string();
+
+        if let Some(resolved) = self.resolve(from, &name, None) {
+            if self.locations[resolved].package == Some(package) {
+                return Ok(None);
+            }
+        }
+
+        let mut target = None;
+        let mut current = Some(from);
+        while let Some(location) = current {
+            if self.locations[location].children.contains_key(&name)
+                || self.shadows(location, &name, package)
+            {
+                break;
+            }
+
+            target = Some(location);
+            current = self.locations[location].parent;
+        }
+
+        let target = target.ok_or_else(|| {
+            JamError::new(format!(
+                "Failed to hoist {}@{}, another version is in the way",
+                name,
+                self.graph[package].version()
+            ))
+        })?;
+
+        if self
+            .ancestors(target)
+            .any(|location| self.locations[location].package == Some(package))
+        {
+            return Err(JamError::new(format!(
+                "Failed to hoist {}@{}, its dependencies form a cycle that can't be flattened",
+                name,
+                self.graph[package].version()
+            )));
+        }
+
+        let path = self.locations[target].path.join("node_modules").join(&name);
+        let placed = self.add_location(Some(package), Some(target), path);
+
+        self.locations[target].children.insert(name, placed);
+        self.placements.push(placed);
+
+        Ok(Some(placed))
+    }
+
+    // Finds the location `name` resolves to from `from`, looking no further up than `until`
+    fn resolve(&self, from: usize, name: &str, until: Option<usize>) -> Option<usize> {
+        for location in self.ancestors(from) {
+            if Some(location) == until {
+                return None;
+            }
+
+            if let Some(child) = self.locations[location].children.get(name) {
+                return Some(*child);
+            }
+        }
+
+        None
+    }
+
+    // Placing the package at `target` must not change what already placed dependencies resolve to
+    fn shadows(&self, target: usize, name: &str, package: NodeIndex) -> bool {
+        (0..self.locations.len()).any(|location| {
+            let dependent = &self.locations[location];
+
+            match dependent.package {
+                Some(dependent_package) if dependent.processed => {
+                    self.graph.neighbors(dependent_package).any(|dependency| {
+                        dependency != package && self.graph[dependency].name() == name
+                    }) && self.ancestors(location).any(|ancestor| ancestor == target)
+                        && self.resolve(location, name, Some(target)).is_none()
+                }
+                _ => false,
+            }
+        })
+    }
+
+    fn ancestors(&self, location: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(Some(location), move |location| {
+            self.locations[*location].parent
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jam_core::package::WorkspacePackage;
+
+    fn npm_package(name: &str, version: &str) -> Package {
+        Package::NpmPackage(NpmPackage::new(
+            name.to_string(),
+            version.to_string(),
+            None,
+            "shasum".to_string(),
+            "tarball".to_string(),
+            vec![],
+        ))
+    }
+
+    fn workspace_package(name: &str, path: PathBuf) -> Package {
+        Package::WorkspacePackage(WorkspacePackage::new(
+            name.to_string(),
+            "1.0.0".to_string(),
+            None,
+            None,
+            vec![],
+            path,
+        ))
+    }
+
+    fn layout(graph: &Graph<Package, ()>, placements: &[Placement]) -> Vec<(String, String)> {
+        let mut layout: Vec<(String, String)> = placements
+            .iter()
+            .map(|placement| {
+                (
+                    placement.path.to_string_lossy().to_string(),
+                    graph[placement.package].version().to_string(),
+                )
+            })
+            .collect();
+        layout.sort();
+
+        layout
+    }
+
+    #[test]
+    fn hoists_dependencies_to_the_root() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let a = graph.add_node(npm_package("a", "1.0.0"));
+        let b = graph.add_node(npm_package("b", "1.0.0"));
+        graph.add_edge(wp1, a, ());
+        graph.add_edge(a, b, ());
+
+        let placements = hoist(Path::new("/r"), &[wp1], &graph).unwrap();
+
+        assert_eq!(
+            layout(&graph, &placements),
+            vec![
+                ("/r/node_modules/a".to_string(), "1.0.0".to_string()),
+                ("/r/node_modules/b".to_string(), "1.0.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn nests_conflicting_versions_under_their_dependents() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let wp2 = graph.add_node(workspace_package("wp2", PathBuf::from("/r/wp2")));
+        let a = graph.add_node(npm_package("a", "1.0.0"));
+        let b1 = graph.add_node(npm_package("b", "1.0.0"));
+        let b2 = graph.add_node(npm_package("b", "2.0.0"));
+        graph.add_edge(wp1, a, ());
+        graph.add_edge(wp1, b1, ());
+        graph.add_edge(a, b2, ());
+        graph.add_edge(wp2, b2, ());
+
+        let placements = hoist(Path::new("/r"), &[wp1, wp2], &graph).unwrap();
+
+        assert_eq!(
+            layout(&graph, &placements),
+            vec![
+                ("/r/node_modules/a".to_string(), "1.0.0".to_string()),
+                (
+                    "/r/node_modules/a/node_modules/b".to_string(),
+                    "2.0.0".to_string()
+                ),
+                ("/r/node_modules/b".to_string(), "1.0.0".to_string()),
+                ("/r/wp2/node_modules/b".to_string(), "2.0.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_placed_dependencies_resolving_to_their_version() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let a = graph.add_node(npm_package("a", "1.0.0"));
+        let c = graph.add_node(npm_package("c", "1.0.0"));
+        let b1 = graph.add_node(npm_package("b", "1.0.0"));
+        let b2 = graph.add_node(npm_package("b", "2.0.0"));
+        graph.add_edge(wp1, a, ());
+        graph.add_edge(wp1, c, ());
+        graph.add_edge(a, b2, ());
+        graph.add_edge(c, b1, ());
+
+        let placements = hoist(Path::new("/r"), &[wp1], &graph).unwrap();
+
+        // `a` is processed first and takes the root slot, `c` gets its own copy
+        assert_eq!(
+            layout(&graph, &placements),
+            vec![
+                ("/r/node_modules/a".to_string(), "1.0.0".to_string()),
+                ("/r/node_modules/b".to_string(), "2.0.0".to_string()),
+                ("/r/node_modules/c".to_string(), "1.0.0".to_string()),
+                (
+                    "/r/node_modules/c/node_modules/b".to_string(),
+                    "1.0.0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_shadow_dependencies_of_the_workspace_package() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let wp2 = graph.add_node(workspace_package("wp2", PathBuf::from("/r/wp2")));
+        let a1 = graph.add_node(npm_package("a", "1.0.0"));
+        let a2 = graph.add_node(npm_package("a", "2.0.0"));
+        let b = graph.add_node(npm_package("b", "1.0.0"));
+        graph.add_edge(wp1, a1, ());
+        graph.add_edge(wp2, b, ());
+        graph.add_edge(b, a2, ());
+
+        let placements = hoist(Path::new("/r"), &[wp1, wp2], &graph).unwrap();
+
+        assert_eq!(
+            layout(&graph, &placements),
+            vec![
+                ("/r/node_modules/a".to_string(), "1.0.0".to_string()),
+                ("/r/node_modules/b".to_string(), "1.0.0".to_string()),
+                (
+                    "/r/node_modules/b/node_modules/a".to_string(),
+                    "2.0.0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_workspace_dependencies_without_traversing_them() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let wp2 = graph.add_node(workspace_package("wp2", PathBuf::from("/r/wp2")));
+        let a = graph.add_node(npm_package("a", "1.0.0"));
+        graph.add_edge(wp1, wp2, ());
+        graph.add_edge(wp2, a, ());
+
+        let placements = hoist(Path::new("/r"), &[wp1, wp2], &graph).unwrap();
+
+        assert_eq!(
+            layout(&graph, &placements),
+            vec![
+                ("/r/node_modules/a".to_string(), "1.0.0".to_string()),
+                ("/r/node_modules/wp2".to_string(), "1.0.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn copies_workspace_dependencies_of_deployed_packages() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let wp2 = graph.add_node(workspace_package("wp2", PathBuf::from("/r/wp2")));
+        let wp3 = graph.add_node(workspace_package("wp3", PathBuf::from("/r/wp3")));
+        let a1 = graph.add_node(npm_package("a", "1.0.0"));
+        let a2 = graph.add_node(npm_package("a", "2.0.0"));
+        let b = graph.add_node(npm_package("b", "1.0.0"));
+        graph.add_edge(wp1, wp2, ());
+        graph.add_edge(wp1, a1, ());
+        graph.add_edge(wp2, a2, ());
+        graph.add_edge(wp3, b, ());
+
+        let placements = hoist_deployed(Path::new("/d"), wp1, &graph).unwrap();
+
+        assert_eq!(
+            layout(&graph, &placements),
+            vec![
+                ("/d/node_modules/a".to_string(), "1.0.0".to_string()),
+                ("/d/node_modules/wp2".to_string(), "1.0.0".to_string()),
+                (
+                    "/d/node_modules/wp2/node_modules/a".to_string(),
+                    "2.0.0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn places_shared_cycles_once() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let a = graph.add_node(npm_package("a", "1.0.0"));
+        let b = graph.add_node(npm_package("b", "1.0.0"));
+        graph.add_edge(wp1, a, ());
+        graph.add_edge(a, b, ());
+        graph.add_edge(b, a, ());
+
+        let placements = hoist(Path::new("/r"), &[wp1], &graph).unwrap();
+
+        assert_eq!(placements.len(), 2);
+    }
+
+    #[test]
+    fn selects_packages_matching_the_hoist_patterns() {
+        let mut graph = Graph::new();
+        let wp1 = graph.add_node(workspace_package("wp1", PathBuf::from("/r/wp1")));
+        let a = graph.add_node(npm_package("eslint-plugin-a", "1.0.0"));
+        let b1 = graph.add_node(npm_package("@types/b", "1.0.0"));
+        let b2 = graph.add_node(npm_package("@types/b", "2.0.0"));
+        let c = graph.add_node(npm_package("c", "1.0.0"));
+        graph.add_edge(wp1, a, ());
+        graph.add_edge(wp1, c, ());
+        graph.add_edge(a, b2, ());
+        graph.add_edge(c, b1, ());
+        graph.add_edge(b2, c, ());
+
+        let patterns = HoistPatterns::new(
+            vec!["eslint-plugin-*".to_string()],
+            vec!["@types/*".to_string()],
+        )
+        .unwrap();
+        let (public, hidden) = patterns.select(&[wp1], &graph);
+
+        assert_eq!(
+            public
+                .iter()
+                .map(|package| (package.name.as_str(), package.version.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("eslint-plugin-a", "1.0.0")]
+        );
+        assert_eq!(hidden.len(), 1);
+        assert_eq!(hidden[0].name, "@types/b");
+    }
+
+    #[test]
+    fn fails_on_invalid_hoist_patterns() {
+        let result = HoistPatterns::new(vec![], vec!["[".to_string()]);
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid pattern '[' in 'hoistPattern'"));
+    }
+}
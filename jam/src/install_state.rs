@@ -0,0 +1,355 @@
+use crate::config::Config;
+use crate::errors::JamError;
+use crate::workspace::DependencyScope;
+use jam_core::package::{Package, WorkspacePackage};
+use log::debug;
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::Dfs;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Kept inside the root node_modules, removing it forces a full install
+const INSTALL_STATE_FILE_NAME: &str = ".jam-state.json";
+
+// Bump whenever the recorded hashes are computed differently
+const INSTALL_STATE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorkspaceState {
+    pub manifest_hash: String,
+    // `name@version` of every store package the workspace links to
+    pub packages: Vec<String>,
+}
+
+// Records the inputs of the last install, so unchanged workspaces are not resolved and linked again
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InstallState {
+    pub version: u32,
+    // Covers what every workspace depends on: jam.json, the dependency scope, the registries and
+    // the workspace set
+    pub inputs_hash: String,
+    pub workspaces: BTreeMap<PathBuf, WorkspaceState>,
+}
+
+impl InstallState {
+    pub fn new(inputs_hash: String) -> InstallState {
+        InstallState {
+            version: INSTALL_STATE_VERSION,
+            inputs_hash,
+            workspaces: BTreeMap::new(),
+        }
+    }
+
+    // A missing or unreadable state only costs a full install
+    pub fn load(root_path: &Path) -> Option<InstallState> {
+        let state_path = state_path(root_path);
+        let content = fs::read(&state_path).ok()?;
+
+        match serde_json::from_slice::<InstallState>(&content) {
+            Ok(state) if state.version == INSTALL_STATE_VERSION => Some(state),
+            Ok(state) => {
+                debug!(
+                    "Ignoring install state of version {}, expected {}",
+                    state.version, INSTALL_STATE_VERSION
+                );
+                None
+            }
+            Err(err) => {
+                debug!(
+                    "Ignoring unreadable install state {:?}: {}",
+                    state_path, err
+                );
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, root_path: &Path) -> Result<(), JamError> {
+        let state_path = state_path(root_path);
+        let temp_path = state_path.with_extension(format!("json.{}", process::id()));
+        let content = serde_json::to_vec(self).map_err(|err| {
+            JamError::new(format!(
+                "Failed to write install state {:?}: {}",
+                state_path, err
+            ))
+        })?;
+
+        fs::create_dir_all(state_path.parent().unwrap())?;
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &state_path)?;
+
+        Ok(())
+    }
+
+    // Workspaces are reinstalled when their manifest changed or their node_modules was removed
+    pub fn is_up_to_date(&self, workspace_package: &WorkspacePackage, manifest_hash: &str) -> bool {
+        self.workspaces
+            .get(&workspace_package.base_path)
+            .is_some_and(|workspace_state| workspace_state.manifest_hash == manifest_hash)
+            && workspace_package.base_path.join("node_modules").is_dir()
+    }
+
+    // Workspaces link the workspaces they depend on together with their store packages, so they are
+    // outdated whenever one of those is
+    pub fn outdated_packages<'a>(
+        &self,
+        workspace_packages: &'a [WorkspacePackage],
+        manifest_hashes: &[String],
+    ) -> Vec<&'a WorkspacePackage> {
+        let mut outdated_names: HashSet<&str> = workspace_packages
+            .iter()
+            .zip(manifest_hashes)
+            .filter(|(workspace_package, manifest_hash)| {
+                !self.is_up_to_date(workspace_package, manifest_hash)
+            })
+            .map(|(workspace_package, _)| workspace_package.name.as_str())
+            .collect();
+
+        loop {
+            let dependents: Vec<&str> = workspace_packages
+                .iter()
+                .filter(|workspace_package| {
+                    !outdated_names.contains(workspace_package.name.as_str())
+                        && workspace_package
+                            .dependencies
+                            .iter()
+                            .chain(&workspace_package.dev_dependencies)
+                            .any(|dependency| {
+                                outdated_names.contains(dependency.real_name.as_str())
+                            })
+                })
+                .map(|workspace_package| workspace_package.name.as_str())
+                .collect();
+
+            if dependents.is_empty() {
+                break;
+            }
+            outdated_names.extend(dependents);
+        }
+
+        workspace_packages
+            .iter()
+            .filter(|workspace_package| outdated_names.contains(workspace_package.name.as_str()))
+            .collect()
+    }
+
+    pub fn packages(&self) -> Vec<String> {
+        self.workspaces
+            .values()
+            .flat_map(|workspace_state| workspace_state.packages.iter().cloned())
+            .collect()
+    }
+}
+
+pub fn hash_inputs(
+    config: &Config,
+    scope: DependencyScope,
+    workspace_packages: &[WorkspacePackage],
+) -> Result<String, JamError> {
+    let mut hasher = Sha256::new();
+
+    hasher.update(fs::read(config.root_path.join("jam.json"))?);
+    hasher.update(format!("{:?}\n", scope).as_bytes());
+    for registry in &config.registries {
+        hasher.update(registry.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    let mut base_paths: Vec<&PathBuf> = workspace_packages
+        .iter()
+        .map(|workspace_package| &workspace_package.base_path)
+        .collect();
+    base_paths.sort();
+
+    for base_path in base_paths {
+        hasher.update(base_path.to_string_lossy().as_bytes());
+        hasher.update(b"\n");
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn hash_manifest(workspace_package: &WorkspacePackage) -> Result<String, JamError> {
+    let content = fs::read(workspace_package.base_path.join("package.json"))?;
+
+    Ok(hex::encode(Sha256::digest(&content)))
+}
+
+pub fn collect_packages(node: NodeIndex, graph: &Graph<Package, ()>) -> Vec<String> {
+    let mut packages = vec![];
+    let mut dfs = Dfs::new(graph, node);
+
+    while let Some(nx) = dfs.next(graph) {
+        if let Package::NpmPackage(npm_package) = &graph[nx] {
+            packages.push(format!("{}@{}", npm_package.name, npm_package.version));
+        }
+    }
+
+    packages.sort();
+    packages
+}
+
+fn state_path(root_path: &Path) -> PathBuf {
+    root_path.join("node_modules").join(INSTALL_STATE_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jam_core::package::NpmPackage;
+    use tempdir::TempDir;
+
+    fn create_workspace_package(base_path: PathBuf) -> WorkspacePackage {
+        WorkspacePackage::new(
+            String::from("p1"),
+            String::from("1.0.0"),
+            None,
+            None,
+            vec![],
+            base_path,
+        )
+    }
+
+    #[test]
+    fn loads_saved_states() {
+        let tmp_dir = TempDir::new("jam-install-state").unwrap();
+        let mut state = InstallState::new(String::from("inputs"));
+        state.workspaces.insert(
+            tmp_dir.path().join("p1"),
+            WorkspaceState {
+                manifest_hash: String::from("manifest"),
+                packages: vec![String::from("lib@1.0.0")],
+            },
+        );
+
+        state.save(tmp_dir.path()).unwrap();
+
+        assert_eq!(InstallState::load(tmp_dir.path()), Some(state));
+    }
+
+    #[test]
+    fn ignores_missing_unreadable_and_outdated_states() {
+        let tmp_dir = TempDir::new("jam-install-state").unwrap();
+
+        assert_eq!(InstallState::load(tmp_dir.path()), None);
+
+        fs::create_dir_all(tmp_dir.path().join("node_modules")).unwrap();
+        fs::write(state_path(tmp_dir.path()), "not json").unwrap();
+
+        assert_eq!(InstallState::load(tmp_dir.path()), None);
+
+        let mut state = InstallState::new(String::from("inputs"));
+        state.version = INSTALL_STATE_VERSION + 1;
+        state.save(tmp_dir.path()).unwrap();
+
+        assert_eq!(InstallState::load(tmp_dir.path()), None);
+    }
+
+    #[test]
+    fn reinstalls_changed_and_removed_workspaces() {
+        let tmp_dir = TempDir::new("jam-install-state").unwrap();
+        let workspace_package = create_workspace_package(tmp_dir.path().join("p1"));
+        let mut state = InstallState::new(String::from("inputs"));
+
+        assert!(!state.is_up_to_date(&workspace_package, "manifest"));
+
+        state.workspaces.insert(
+            workspace_package.base_path.clone(),
+            WorkspaceState {
+                manifest_hash: String::from("manifest"),
+                packages: vec![],
+            },
+        );
+
+        // Its node_modules is missing
+        assert!(!state.is_up_to_date(&workspace_package, "manifest"));
+
+        fs::create_dir_all(workspace_package.base_path.join("node_modules")).unwrap();
+
+        assert!(state.is_up_to_date(&workspace_package, "manifest"));
+        assert!(!state.is_up_to_date(&workspace_package, "other"));
+
+        // p2 depends on p1 and p3 on p2, p4 is unrelated
+        let mut workspace_packages = vec![workspace_package];
+        for (name, dependency) in [("p2", Some("p1")), ("p3", Some("p2")), ("p4", None)] {
+            let base_path = tmp_dir.path().join(name);
+            fs::create_dir_all(base_path.join("node_modules")).unwrap();
+            state.workspaces.insert(
+                base_path.clone(),
+                WorkspaceState {
+                    manifest_hash: String::from("manifest"),
+                    packages: vec![],
+                },
+            );
+            workspace_packages.push(WorkspacePackage::new(
+                String::from(name),
+                String::from("1.0.0"),
+                dependency.map(|dependency| {
+                    vec![(String::from(dependency), String::from("1.0.0"))]
+                        .into_iter()
+                        .collect()
+                }),
+                None,
+                vec![],
+                base_path,
+            ));
+        }
+        let names = |outdated_packages: Vec<&WorkspacePackage>| -> Vec<String> {
+            outdated_packages
+                .into_iter()
+                .map(|workspace_package| workspace_package.name.clone())
+                .collect()
+        };
+
+        assert!(state
+            .outdated_packages(&workspace_packages, &vec![String::from("manifest"); 4])
+            .is_empty());
+        assert_eq!(
+            names(state.outdated_packages(
+                &workspace_packages,
+                &[
+                    String::from("other"),
+                    String::from("manifest"),
+                    String::from("manifest"),
+                    String::from("manifest"),
+                ]
+            )),
+            vec!["p1", "p2", "p3"]
+        );
+    }
+
+    #[test]
+    fn collects_the_store_packages_of_a_workspace() {
+        let mut graph = Graph::new();
+        let workspace_node = graph.add_node(Package::WorkspacePackage(create_workspace_package(
+            PathBuf::from("p1"),
+        )));
+        let lib_node = graph.add_node(Package::NpmPackage(NpmPackage::new(
+            String::from("lib"),
+            String::from("1.0.0"),
+            None,
+            String::from("shasum"),
+            String::from("tarball"),
+            vec![],
+        )));
+        let other_node = graph.add_node(Package::NpmPackage(NpmPackage::new(
+            String::from("other"),
+            String::from("2.0.0"),
+            None,
+            String::from("shasum"),
+            String::from("tarball"),
+            vec![],
+        )));
+        graph.add_edge(workspace_node, lib_node, ());
+        graph.add_edge(other_node, lib_node, ());
+
+        assert_eq!(
+            collect_packages(workspace_node, &graph),
+            vec![String::from("lib@1.0.0")]
+        );
+    }
+}
@@ -2,10 +2,17 @@ pub mod cli_options;
 pub mod errors;
 
 mod archiver;
+mod binaries;
 mod commands;
 mod common;
 mod config;
 mod downloader;
+mod file_store;
+mod hoisting;
+mod install_state;
+mod linker;
+mod network;
+mod projects;
 mod resolver;
 mod root_locator;
 mod store;
@@ -14,32 +21,88 @@ mod writer;
 
 use crate::cli_options::CliOptions;
 use crate::errors::JamError;
-use cli_options::Command;
+use cli_options::{CacheCommand, Command, Install, StoreCommand};
+use commands::cache::{clean, stats};
+use commands::deploy::deploy;
 use commands::install::install;
+use commands::store::{prune, status, verify};
 use common::read_manifest_file;
 use config::Config;
 use directories::ProjectDirs;
 use log::debug;
+use network::load_fallback_network_settings;
 use root_locator::find_root_dir;
 use std::path::PathBuf;
-use workspace::Workspace;
+use workspace::{DependencyScope, Workspace};
 use writer::Writer;
 
 pub async fn run(cwd: PathBuf, options: CliOptions) -> Result<(), JamError> {
+    let project_dirs = ProjectDirs::from("com", "jam", &options.cache_group)
+        .expect("Failed to locate project dir");
+    debug!("Project Dirs {:?}", project_dirs);
+
+    match &options.command {
+        Command::Install(install_options) | Command::I(install_options) => {
+            let config = load_config(cwd, &options)?;
+
+            install(&config, &project_dirs, to_dependency_scope(install_options)).await
+        }
+        Command::Deploy(deploy_options) => {
+            let deploy_path = cwd.join(&deploy_options.dir);
+            let config = load_config(cwd, &options)?;
+
+            deploy(
+                &config,
+                &project_dirs,
+                &deploy_options.workspace,
+                &deploy_path,
+            )
+            .await
+        }
+        Command::Store(store) => match &store.command {
+            StoreCommand::Prune(options) => prune(&project_dirs, options.dry_run, options.force),
+            StoreCommand::Verify(options) => verify(&project_dirs, options.repair),
+            StoreCommand::Status(_) => status(&project_dirs),
+        },
+        Command::Cache(cache) => match &cache.command {
+            CacheCommand::Stats(_) => stats(&project_dirs),
+            CacheCommand::Clean(options) => clean(&project_dirs, options.cache_name.as_deref()),
+        },
+    }
+}
+
+fn to_dependency_scope(install_options: &Install) -> DependencyScope {
+    if install_options.prod {
+        DependencyScope::Production
+    } else if install_options.dev {
+        DependencyScope::Development
+    } else {
+        DependencyScope::All
+    }
+}
+
+fn load_config(cwd: PathBuf, options: &CliOptions) -> Result<Config, JamError> {
     let root_path = find_root_dir(cwd)?;
     debug!("Root path {:?}", root_path);
 
     let manifest_file_path = root_path.join("jam.json");
     let manifest_file_content = read_manifest_file(manifest_file_path)?;
 
-    let config = Config::new(root_path, &manifest_file_content, &options.registry)?;
+    let mut config = Config::new(
+        root_path,
+        &manifest_file_content,
+        options.registry.as_deref(),
+    )?;
+    config.network = config
+        .network
+        .or(load_fallback_network_settings(&config.root_path)?);
+    if let Some(network_concurrency) = options.network_concurrency {
+        config.limiter_options.concurrency = network_concurrency;
+    }
+    if let Some(requests_per_second) = options.requests_per_second {
+        config.limiter_options.requests_per_second = Some(requests_per_second);
+    }
     debug!("Config {:?}", config);
 
-    let project_dirs = ProjectDirs::from("com", "jam", &options.cache_group)
-        .expect("Failed to locate project dir");
-    debug!("Project Dirs {:?}", config);
-
-    match options.command {
-        Command::Install(_) | Command::I(_) => install(&config, &project_dirs).await,
-    }
+    Ok(config)
 }
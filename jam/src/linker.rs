@@ -0,0 +1,330 @@
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+
+// How packages and binaries are made available inside a project's node_modules
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStrategy {
+    #[default]
+    Symlink,
+    // Links relative to their location, node_modules can be moved along with the store
+    Relative,
+    Hardlink,
+    Copy,
+}
+
+pub trait Linker: Send + Sync {
+    // Makes the npm package at `original` available at `link`
+    fn link_package(&self, original: &Path, link: &Path) -> io::Result<()>;
+
+    // Workspace packages and binaries are always linked, binaries resolve their requires from
+    // their real path and a copied workspace package would not see later changes
+    fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        symlink(original, link)
+    }
+
+    // Whether `link` already provides `original`, stale entries are replaced
+    fn is_package_linked(&self, original: &Path, link: &Path) -> bool {
+        self.is_symlinked(original, link)
+    }
+
+    fn is_symlinked(&self, original: &Path, link: &Path) -> bool {
+        fs::read_link(link).is_ok_and(|target| target == original)
+    }
+
+    // Copied packages no longer resolve their dependencies next to their real path in the store
+    fn is_copy(&self) -> bool {
+        false
+    }
+}
+
+pub fn create_linker(link_strategy: LinkStrategy) -> Box<dyn Linker> {
+    match link_strategy {
+        LinkStrategy::Symlink => Box::new(SymlinkLinker::new()),
+        LinkStrategy::Relative => Box::new(RelativeSymlinkLinker::new()),
+        LinkStrategy::Hardlink => Box::new(HardlinkLinker::new()),
+        LinkStrategy::Copy => Box::new(CopyLinker::new()),
+    }
+}
+
+pub struct SymlinkLinker {}
+
+impl SymlinkLinker {
+    pub fn new() -> SymlinkLinker {
+        SymlinkLinker {}
+    }
+}
+
+impl Linker for SymlinkLinker {
+    fn link_package(&self, original: &Path, link: &Path) -> io::Result<()> {
+        symlink(original, link)
+    }
+}
+
+pub struct RelativeSymlinkLinker {}
+
+impl RelativeSymlinkLinker {
+    pub fn new() -> RelativeSymlinkLinker {
+        RelativeSymlinkLinker {}
+    }
+}
+
+impl Linker for RelativeSymlinkLinker {
+    fn link_package(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.symlink(original, link)
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> io::Result<()> {
+        symlink(relative_path(link.parent().unwrap(), original), link)
+    }
+
+    fn is_symlinked(&self, original: &Path, link: &Path) -> bool {
+        fs::read_link(link)
+            .is_ok_and(|target| target == relative_path(link.parent().unwrap(), original))
+    }
+}
+
+pub struct HardlinkLinker {}
+
+impl HardlinkLinker {
+    pub fn new() -> HardlinkLinker {
+        HardlinkLinker {}
+    }
+}
+
+impl Linker for HardlinkLinker {
+    fn link_package(&self, original: &Path, link: &Path) -> io::Result<()> {
+        copy_tree(original, link, &|from, to| fs::hard_link(from, to))
+    }
+
+    fn is_package_linked(&self, original: &Path, link: &Path) -> bool {
+        if !is_directory(link) {
+            return false;
+        }
+
+        match (
+            fs::metadata(original.join("package.json")),
+            fs::symlink_metadata(link.join("package.json")),
+        ) {
+            (Ok(original), Ok(link)) => {
+                original.ino() == link.ino() && original.dev() == link.dev()
+            }
+            _ => false,
+        }
+    }
+
+    fn is_copy(&self) -> bool {
+        true
+    }
+}
+
+pub struct CopyLinker {}
+
+impl CopyLinker {
+    pub fn new() -> CopyLinker {
+        CopyLinker {}
+    }
+}
+
+impl Linker for CopyLinker {
+    fn link_package(&self, original: &Path, link: &Path) -> io::Result<()> {
+        copy_tree(original, link, &|from, to| fs::copy(from, to).map(|_| ()))
+    }
+
+    // Packages are only copied once, a changed manifest means another version was installed
+    fn is_package_linked(&self, original: &Path, link: &Path) -> bool {
+        if !is_directory(link) {
+            return false;
+        }
+
+        match (
+            fs::read(original.join("package.json")),
+            fs::read(link.join("package.json")),
+        ) {
+            (Ok(original), Ok(link)) => original == link,
+            _ => false,
+        }
+    }
+
+    fn is_copy(&self) -> bool {
+        true
+    }
+}
+
+// Links left by other strategies are replaced by a copy
+fn is_directory(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+// Symlinks inside the package are kept as they are
+fn copy_tree(
+    original: &Path,
+    link: &Path,
+    copy_file: &dyn Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    if !fs::metadata(original)?.is_dir() {
+        return copy_file(original, link);
+    }
+
+    fs::create_dir(link)?;
+
+    for entry in fs::read_dir(original)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let link_path = link.join(entry.file_name());
+
+        if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &link_path)?;
+        } else if file_type.is_dir() {
+            copy_tree(&entry.path(), &link_path, copy_file)?;
+        } else {
+            copy_file(&entry.path(), &link_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Copies a workspace package without its installed node_modules
+pub fn copy_workspace_package(base_path: &Path, path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)?;
+
+    for entry in fs::read_dir(base_path)? {
+        let entry = entry?;
+        let link_path = path.join(entry.file_name());
+
+        if entry.file_name() == "node_modules" {
+            continue;
+        }
+
+        if entry.file_type()?.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &link_path)?;
+        } else {
+            copy_tree(&entry.path(), &link_path, &|from, to| {
+                fs::copy(from, to).map(|_| ())
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+// Both paths are absolute, symlinks along them are not resolved
+pub fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let from_components: Vec<Component> = from_dir.components().collect();
+    let to_components: Vec<Component> = to.components().collect();
+    let common = from_components
+        .iter()
+        .zip(&to_components)
+        .take_while(|(from, to)| from == to)
+        .count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from_components.len() {
+        path.push("..");
+    }
+    for component in &to_components[common..] {
+        path.push(component);
+    }
+
+    path
+}
+
+// The absolute target of a link, `..` components of relative targets are resolved without
+// following symlinks
+pub fn resolve_link(link: &Path) -> io::Result<PathBuf> {
+    let mut path = link.parent().unwrap().to_path_buf();
+
+    for component in fs::read_link(link)?.components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => {}
+            component => path.push(component),
+        }
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn create_package(path: &Path) {
+        fs::create_dir_all(path.join("lib")).unwrap();
+        fs::write(path.join("package.json"), r#"{"version": "1.0.0"}"#).unwrap();
+        fs::write(path.join("lib").join("index.js"), "").unwrap();
+        symlink("lib/index.js", path.join("main.js")).unwrap();
+    }
+
+    #[test]
+    fn computes_relative_paths() {
+        assert_eq!(
+            relative_path(Path::new("/a/b/node_modules"), Path::new("/a/store/p1")),
+            PathBuf::from("../../store/p1")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a/b"), Path::new("/a/b/c")),
+            PathBuf::from("c")
+        );
+    }
+
+    #[test]
+    fn links_packages_relative_to_the_link() {
+        let tmp_dir = TempDir::new("jam-linker").unwrap();
+        let original = tmp_dir.path().join("store").join("p1");
+        let link = tmp_dir
+            .path()
+            .join("project")
+            .join("node_modules")
+            .join("p1");
+        create_package(&original);
+        fs::create_dir_all(link.parent().unwrap()).unwrap();
+
+        let linker = create_linker(LinkStrategy::Relative);
+        linker.link_package(&original, &link).unwrap();
+
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            PathBuf::from("../../store/p1")
+        );
+        assert!(link.join("lib").join("index.js").is_file());
+        assert!(linker.is_package_linked(&original, &link));
+        assert!(!SymlinkLinker::new().is_package_linked(&original, &link));
+    }
+
+    #[test]
+    fn copies_and_hardlinks_packages() {
+        for link_strategy in [LinkStrategy::Copy, LinkStrategy::Hardlink] {
+            let tmp_dir = TempDir::new("jam-linker").unwrap();
+            let original = tmp_dir.path().join("store").join("p1");
+            let link = tmp_dir.path().join("p1");
+            create_package(&original);
+
+            let linker = create_linker(link_strategy);
+            linker.link_package(&original, &link).unwrap();
+
+            assert!(!fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert!(link.join("lib").join("index.js").is_file());
+            assert_eq!(
+                fs::read_link(link.join("main.js")).unwrap(),
+                PathBuf::from("lib/index.js")
+            );
+            assert!(linker.is_package_linked(&original, &link));
+
+            fs::remove_file(original.join("package.json")).unwrap();
+            fs::write(original.join("package.json"), r#"{"version": "2.0.0"}"#).unwrap();
+
+            assert!(!linker.is_package_linked(&original, &link));
+        }
+    }
+}
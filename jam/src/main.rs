@@ -1,4 +1,3 @@
-use env_logger;
 use log::{debug, LevelFilter};
 use std::env::current_dir;
 use std::process;
@@ -0,0 +1,210 @@
+use crate::errors::JamError;
+use directories::BaseDirs;
+use jam_core::http::ClientOptions;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NPMRC_FILE_NAME: &str = ".npmrc";
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkSettings {
+    pub proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    pub cafile: Option<PathBuf>,
+    pub strict_ssl: Option<bool>,
+}
+
+impl NetworkSettings {
+    pub fn from_env(vars: impl IntoIterator<Item = (String, String)>) -> NetworkSettings {
+        let vars: HashMap<String, String> = vars.into_iter().collect();
+        let lookup = |keys: &[&str]| keys.iter().find_map(|key| vars.get(*key).cloned());
+
+        NetworkSettings {
+            proxy: lookup(&["npm_config_proxy", "HTTP_PROXY", "http_proxy"]),
+            https_proxy: lookup(&["npm_config_https_proxy", "HTTPS_PROXY", "https_proxy"]),
+            no_proxy: lookup(&["npm_config_noproxy", "NO_PROXY", "no_proxy"]),
+            cafile: lookup(&["npm_config_cafile"]).map(PathBuf::from),
+            strict_ssl: lookup(&["npm_config_strict_ssl"]).and_then(|value| parse_bool(&value)),
+        }
+    }
+
+    pub fn from_npmrc(content: &str) -> NetworkSettings {
+        let entries = parse_npmrc(content);
+
+        NetworkSettings {
+            proxy: entries.get("proxy").cloned(),
+            https_proxy: entries.get("https-proxy").cloned(),
+            no_proxy: entries.get("noproxy").cloned(),
+            cafile: entries.get("cafile").map(PathBuf::from),
+            strict_ssl: entries
+                .get("strict-ssl")
+                .and_then(|value| parse_bool(value)),
+        }
+    }
+
+    // Settings are layered per field, so a project can override the proxy while keeping the user's cafile
+    pub fn or(self, fallback: NetworkSettings) -> NetworkSettings {
+        NetworkSettings {
+            proxy: self.proxy.or(fallback.proxy),
+            https_proxy: self.https_proxy.or(fallback.https_proxy),
+            no_proxy: self.no_proxy.or(fallback.no_proxy),
+            cafile: self.cafile.or(fallback.cafile),
+            strict_ssl: self.strict_ssl.or(fallback.strict_ssl),
+        }
+    }
+
+    pub fn to_client_options(&self) -> ClientOptions {
+        ClientOptions {
+            proxy: self.proxy.clone(),
+            https_proxy: self.https_proxy.clone(),
+            no_proxy: self
+                .no_proxy
+                .iter()
+                .flat_map(|no_proxy| no_proxy.split(','))
+                .map(|host| host.trim().to_string())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            ca_file: self.cafile.clone(),
+            strict_ssl: self.strict_ssl.unwrap_or(true),
+        }
+    }
+}
+
+// Environment variables take precedence over the project .npmrc, which takes precedence over the user one
+pub fn load_fallback_network_settings(root_path: &Path) -> Result<NetworkSettings, JamError> {
+    let mut settings = NetworkSettings::from_env(std::env::vars())
+        .or(read_npmrc_settings(&root_path.join(NPMRC_FILE_NAME))?);
+
+    if let Some(base_dirs) = BaseDirs::new() {
+        settings = settings.or(read_npmrc_settings(
+            &base_dirs.home_dir().join(NPMRC_FILE_NAME),
+        )?);
+    }
+
+    Ok(settings)
+}
+
+fn read_npmrc_settings(path: &Path) -> Result<NetworkSettings, JamError> {
+    if path.exists() {
+        Ok(NetworkSettings::from_npmrc(&fs::read_to_string(path)?))
+    } else {
+        Ok(NetworkSettings::default())
+    }
+}
+
+fn parse_npmrc(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with(';') && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next()?.trim();
+            let value = parts.next()?.trim().trim_matches('"');
+
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_settings_from_npmrc() {
+        let content = r#"
+            ; corporate settings
+            proxy=http://proxy:8080
+            https-proxy = "http://secure-proxy:8443"
+            noproxy=localhost,.internal.corp
+            cafile=/etc/ssl/corp.pem
+            strict-ssl=false
+            registry=https://registry.npmjs.org
+        "#;
+
+        assert_eq!(
+            NetworkSettings::from_npmrc(content),
+            NetworkSettings {
+                proxy: Some("http://proxy:8080".to_string()),
+                https_proxy: Some("http://secure-proxy:8443".to_string()),
+                no_proxy: Some("localhost,.internal.corp".to_string()),
+                cafile: Some(PathBuf::from("/etc/ssl/corp.pem")),
+                strict_ssl: Some(false),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_settings_from_env_preferring_npm_config_variables() {
+        let vars = vec![
+            (
+                "HTTPS_PROXY".to_string(),
+                "http://env-proxy:8443".to_string(),
+            ),
+            (
+                "npm_config_https_proxy".to_string(),
+                "http://npm-proxy:8443".to_string(),
+            ),
+            ("no_proxy".to_string(), "localhost".to_string()),
+            ("npm_config_strict_ssl".to_string(), "true".to_string()),
+        ];
+
+        assert_eq!(
+            NetworkSettings::from_env(vars),
+            NetworkSettings {
+                proxy: None,
+                https_proxy: Some("http://npm-proxy:8443".to_string()),
+                no_proxy: Some("localhost".to_string()),
+                cafile: None,
+                strict_ssl: Some(true),
+            }
+        );
+    }
+
+    #[test]
+    fn merges_settings_per_field() {
+        let primary = NetworkSettings {
+            proxy: Some("http://primary:8080".to_string()),
+            ..NetworkSettings::default()
+        };
+        let fallback = NetworkSettings {
+            proxy: Some("http://fallback:8080".to_string()),
+            strict_ssl: Some(false),
+            ..NetworkSettings::default()
+        };
+
+        let result = primary.or(fallback);
+
+        assert_eq!(result.proxy, Some("http://primary:8080".to_string()));
+        assert_eq!(result.strict_ssl, Some(false));
+    }
+
+    #[test]
+    fn converts_to_client_options() {
+        let settings = NetworkSettings {
+            no_proxy: Some("localhost, .internal.corp,".to_string()),
+            ..NetworkSettings::default()
+        };
+
+        let options = settings.to_client_options();
+
+        assert_eq!(
+            options.no_proxy,
+            vec!["localhost".to_string(), ".internal.corp".to_string()]
+        );
+        assert!(options.strict_ssl);
+    }
+}
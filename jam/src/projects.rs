@@ -0,0 +1,196 @@
+use crate::errors::JamError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+// Created along with the registry, store entries older than it may belong to unregistered projects
+const TRACKED_SINCE_FILE_NAME: &str = ".tracked-since";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProjectRecord {
+    pub root_path: PathBuf,
+    // `name@version` of every store package the project links to
+    pub packages: Vec<String>,
+}
+
+impl ProjectRecord {
+    // Projects that were deleted or moved no longer hold on to their packages
+    pub fn exists(&self) -> bool {
+        self.root_path.join("jam.json").is_file()
+    }
+}
+
+// Records which projects were installed from the store, so pruning knows what is still in use
+pub struct ProjectRegistry {
+    projects_path: PathBuf,
+}
+
+impl ProjectRegistry {
+    pub fn new(data_dir: &Path) -> Result<ProjectRegistry, JamError> {
+        let projects_path = data_dir.join("projects");
+
+        fs::create_dir_all(&projects_path)?;
+
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(projects_path.join(TRACKED_SINCE_FILE_NAME))
+        {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(ProjectRegistry { projects_path })
+    }
+
+    // Projects installed before this were never registered
+    pub fn tracked_since(&self) -> Result<SystemTime, JamError> {
+        Ok(fs::metadata(self.projects_path.join(TRACKED_SINCE_FILE_NAME))?.modified()?)
+    }
+
+    // Replaces the previous record of the project, packages it stopped using are no longer referenced
+    pub fn register(&self, root_path: &Path, mut packages: Vec<String>) -> Result<(), JamError> {
+        packages.sort();
+        packages.dedup();
+
+        let record_path = self.record_path(root_path);
+        let temp_path = record_path.with_extension(format!("json.{}", process::id()));
+        let content = serde_json::to_vec(&ProjectRecord {
+            root_path: root_path.to_path_buf(),
+            packages,
+        })
+        .map_err(|err| JamError::new(format!("Failed to register {:?}: {}", root_path, err)))?;
+
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &record_path)?;
+
+        Ok(())
+    }
+
+    pub fn unregister(&self, root_path: &Path) -> Result<(), JamError> {
+        fs::remove_file(self.record_path(root_path))?;
+
+        Ok(())
+    }
+
+    // Fails on unreadable records, pruning without them could remove packages that are still in use
+    pub fn projects(&self) -> Result<Vec<ProjectRecord>, JamError> {
+        let mut projects = vec![];
+
+        for entry in fs::read_dir(&self.projects_path)? {
+            let path = entry?.path();
+
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                let record = serde_json::from_slice(&fs::read(&path)?).map_err(|err| {
+                    JamError::new(format!("Failed to read project record {:?}: {}", path, err))
+                })?;
+
+                projects.push(record);
+            }
+        }
+
+        Ok(projects)
+    }
+
+    fn record_path(&self, root_path: &Path) -> PathBuf {
+        let hash = Sha256::digest(root_path.to_string_lossy().as_bytes());
+
+        self.projects_path
+            .join(format!("{}.json", hex::encode(hash)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jam_test_utils::sync_helpers::with_tmp_dir;
+
+    #[test]
+    fn replaces_project_records() {
+        with_tmp_dir(|path| {
+            let registry = ProjectRegistry::new(&path).unwrap();
+            let root_path = path.join("project");
+
+            registry
+                .register(&root_path, vec!["p1@1.0.0".to_string()])
+                .unwrap();
+            registry
+                .register(
+                    &root_path,
+                    vec!["p2@1.0.0".to_string(), "p2@1.0.0".to_string()],
+                )
+                .unwrap();
+
+            assert_eq!(
+                registry.projects().unwrap(),
+                vec![ProjectRecord {
+                    root_path,
+                    packages: vec!["p2@1.0.0".to_string()],
+                }]
+            );
+        })
+    }
+
+    #[test]
+    fn keeps_the_time_projects_are_tracked_since() {
+        with_tmp_dir(|path| {
+            let tracked_since = ProjectRegistry::new(&path)
+                .unwrap()
+                .tracked_since()
+                .unwrap();
+
+            assert!(tracked_since <= SystemTime::now());
+            assert_eq!(
+                ProjectRegistry::new(&path)
+                    .unwrap()
+                    .tracked_since()
+                    .unwrap(),
+                tracked_since
+            );
+            assert_eq!(
+                ProjectRegistry::new(&path).unwrap().projects().unwrap(),
+                vec![]
+            );
+        })
+    }
+
+    #[test]
+    fn unregisters_projects() {
+        with_tmp_dir(|path| {
+            let registry = ProjectRegistry::new(&path).unwrap();
+
+            registry.register(&path.join("p1"), vec![]).unwrap();
+            registry.register(&path.join("p2"), vec![]).unwrap();
+            registry.unregister(&path.join("p1")).unwrap();
+
+            let projects = registry.projects().unwrap();
+
+            assert_eq!(projects.len(), 1);
+            assert_eq!(projects[0].root_path, path.join("p2"));
+        })
+    }
+
+    #[test]
+    fn projects_without_manifest_do_not_exist() {
+        with_tmp_dir(|path| {
+            let record = ProjectRecord {
+                root_path: path.clone(),
+                packages: vec![],
+            };
+
+            assert!(!record.exists());
+
+            fs::write(path.join("jam.json"), "{}").unwrap();
+
+            assert!(record.exists());
+        })
+    }
+}
@@ -86,7 +86,7 @@ impl<'a> Resolver<'a> {
 
         Ok(version_matches(
             &package_requested_version,
-            &package.version(),
+            package.version(),
         ))
     }
 }
@@ -132,7 +132,7 @@ impl<'a> PackageResolver for Resolver<'a> {
                 let package = self.get_dependency(requester, dependency).await?;
                 debug!("Got {} package from remote", package_name);
 
-                let set = DashSet::from_iter(vec![package.clone()].into_iter());
+                let set = DashSet::from_iter(vec![package.clone()]);
                 self.cache.insert(package_name.to_string(), set);
 
                 Ok((package, dependency))
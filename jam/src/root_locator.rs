@@ -1,7 +1,7 @@
 use crate::errors::JamError;
 use std::path::PathBuf;
 
-const MANIFEST_FILE_NAME: &'static str = "jam.json";
+const MANIFEST_FILE_NAME: &str = "jam.json";
 
 pub fn find_root_dir(cwd: PathBuf) -> Result<PathBuf, JamError> {
     let possible_manifest_file_path = cwd.join(MANIFEST_FILE_NAME);
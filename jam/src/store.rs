@@ -1,45 +1,808 @@
+use crate::archiver::Archiver;
+use crate::file_store::{
+    list_files, FileProblem, FileStore, LinkStats, FILES_DIR_NAME, INDEX_DIR_NAME,
+};
+use crate::linker::relative_path;
 use crate::JamError;
-use jam_common::sanitize_package_name;
+use jam_common::lock::FileLock;
+use jam_common::{decode_key, encode_key, sanitize_package_name};
 use jam_core::package::NpmPackage;
+use log::info;
+use std::collections::HashSet;
 use std::fs;
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::os::unix::fs::{symlink, MetadataExt};
 use std::path::Path;
 use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Written once a package is fully extracted and linked, entries without it are rebuilt
+const COMPLETE_SENTINEL_FILE_NAME: &str = ".jam-complete";
+const STAGING_DIR_PREFIX: &str = ".staging-";
+const LOCKS_DIR_NAME: &str = ".locks";
+
+// Bump whenever the layout of the store entries changes, and teach `migrate_layout` to move
+// entries of the previous layout over
+pub const STORE_LAYOUT_VERSION: u32 = 1;
+// The newest layout the store was migrated to, stores without it have the unversioned layout
+const LAYOUT_VERSION_FILE_NAME: &str = "layout-version";
+const LAYOUT_LOCK_FILE_NAME: &str = ".layout.lock";
+// Written next to the layout version once the links of every entry were made relative
+const RELATIVE_LINKS_FILE_NAME: &str = ".relative-links";
+
+static NEXT_STAGING_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PruneOptions {
+    pub dry_run: bool,
+    pub grace_period: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PruneStats {
+    pub packages: u64,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreEntry {
+    pub name: String,
+    pub version: String,
+    pub complete: bool,
+}
+
+impl StoreEntry {
+    pub fn key(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    Valid,
+    // Entries written before the file store have nothing to be verified against
+    Unindexed,
+    Invalid(Vec<FileProblem>),
+}
 
 pub struct Store {
+    root_path: PathBuf,
     store_path: PathBuf,
+    files: FileStore,
 }
 
 impl Store {
+    // Every layout version lives in its own `v<version>` directory, so older jam versions keep
+    // using their own layout side by side with newer ones
     pub fn new(data_dir: &Path) -> Result<Store, JamError> {
-        let store_path = data_dir.join("store");
+        let root_path = data_dir.join("store");
+        let store_path = root_path.join(layout_dir_name(STORE_LAYOUT_VERSION));
 
         fs::create_dir_all(&store_path)?;
+        migrate_layout(&root_path, &store_path)?;
 
-        Ok(Store { store_path })
+        let files = FileStore::new(&store_path)?;
+        let store = Store {
+            root_path,
+            store_path,
+            files,
+        };
+        store.migrate_legacy_entries()?;
+        store.migrate_absolute_links()?;
+
+        Ok(store)
     }
 
     pub fn package_root_path_in_store(&self, package: &NpmPackage) -> PathBuf {
-        let package_dir_name = format!(
-            "{}@{}",
-            sanitize_package_name(&package.name),
-            package.version
-        );
-
-        self.store_path.join(package_dir_name)
+        self.store_path
+            .join(package_dir_name(&package.name, &package.version))
     }
 
     pub fn package_code_path_in_store(&self, package: &NpmPackage) -> PathBuf {
-        let package_dir_name = format!(
-            "{}@{}",
-            sanitize_package_name(&package.name),
-            package.version
-        );
+        self.package_root_path_in_store(package)
+            .join("node_modules")
+            .join(&package.name)
+    }
+
+    pub fn is_package_complete(&self, package: &NpmPackage) -> bool {
+        self.package_root_path_in_store(package)
+            .join(COMPLETE_SENTINEL_FILE_NAME)
+            .exists()
+    }
+
+    // Blocks until no other process (or write in this process) is populating the package
+    pub async fn lock_package(&self, package: &NpmPackage) -> Result<FileLock, JamError> {
+        let lock_path = self.lock_path(&package_dir_name(&package.name, &package.version));
+
+        tokio::task::spawn_blocking(move || FileLock::acquire(&lock_path))
+            .await
+            .map_err(|err| JamError::new(format!("Failed to lock {}: {}", package.name, err)))?
+            .map_err(JamError::from)
+    }
+
+    // Packages are extracted next to the store entries and only moved into place once complete.
+    // Must be called while holding the package lock, so any other staging directory of the
+    // package was left behind by an interrupted install.
+    pub fn create_staging_path(&self, package: &NpmPackage) -> Result<PathBuf, JamError> {
+        self.create_entry_staging_path(&package_dir_name(&package.name, &package.version))
+    }
+
+    fn create_entry_staging_path(&self, dir_name: &str) -> Result<PathBuf, JamError> {
+        let staging_prefix = format!("{}{}.", STAGING_DIR_PREFIX, dir_name);
+
+        for entry in fs::read_dir(&self.store_path)? {
+            let entry = entry?;
+
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(&staging_prefix)
+            {
+                info!("Removing stale staging directory {:?}", entry.path());
+
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+
+        let staging_path = self.store_path.join(format!(
+            "{}{}.{}",
+            staging_prefix,
+            process::id(),
+            NEXT_STAGING_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        fs::create_dir_all(&staging_path)?;
+
+        Ok(staging_path)
+    }
+
+    pub fn commit_package(
+        &self,
+        package: &NpmPackage,
+        staging_path: &Path,
+    ) -> Result<(), JamError> {
+        let package_path = self.package_root_path_in_store(package);
+
+        if package_path.exists() && !self.is_package_complete(package) {
+            info!("Repairing incomplete store entry {:?}", package_path);
+
+            fs::remove_dir_all(&package_path)?;
+        }
+
+        if let Err(err) = fs::rename(staging_path, &package_path) {
+            fs::remove_dir_all(staging_path)?;
+
+            // Another write of the same package got there first
+            if !package_path.exists() {
+                return Err(JamError::new(format!(
+                    "Failed to move {:?} into the store: {}",
+                    package_path, err
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Replaces the extracted files with links into the content-addressable file store
+    pub fn import_package(
+        &self,
+        package: &NpmPackage,
+        code_path: &Path,
+    ) -> Result<LinkStats, JamError> {
+        self.files.import_package(
+            &package_dir_name(&package.name, &package.version),
+            code_path,
+        )
+    }
+
+    // Returns None when the package was never imported, its tarball has to be extracted instead
+    pub fn materialize_package(
+        &self,
+        package: &NpmPackage,
+        code_path: &Path,
+    ) -> Result<Option<LinkStats>, JamError> {
+        match self
+            .files
+            .read_index(&package_dir_name(&package.name, &package.version))?
+        {
+            Some(index) => self.files.materialize_package(&index, code_path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn mark_package_complete(&self, package: &NpmPackage) -> Result<(), JamError> {
+        let package_path = self.package_root_path_in_store(package);
+        let temp_path =
+            package_path.join(format!("{}.{}", COMPLETE_SENTINEL_FILE_NAME, process::id()));
+
+        fs::write(&temp_path, &package.shasum)?;
+        fs::rename(&temp_path, package_path.join(COMPLETE_SENTINEL_FILE_NAME))?;
+
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<StoreEntry>, JamError> {
+        let mut entries = vec![];
+
+        for entry in fs::read_dir(&self.store_path)? {
+            let entry = entry?;
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+
+            if !entry.file_type()?.is_dir() || !is_current_entry(&dir_name) {
+                continue;
+            }
+
+            let key = decode_key(&dir_name).unwrap();
+            if let Some(index) = key.rfind('@').filter(|index| *index > 0) {
+                entries.push(StoreEntry {
+                    name: key[..index].to_string(),
+                    version: key[index + 1..].to_string(),
+                    complete: entry.path().join(COMPLETE_SENTINEL_FILE_NAME).exists(),
+                });
+            }
+        }
 
+        entries.sort_by_key(|entry| entry.key());
+
+        Ok(entries)
+    }
+
+    // The shasum of the tarball the entry was extracted from
+    pub fn recorded_shasum(&self, entry: &StoreEntry) -> Result<Option<String>, JamError> {
+        match fs::read_to_string(self.entry_path(entry).join(COMPLETE_SENTINEL_FILE_NAME)) {
+            Ok(shasum) => Ok(Some(shasum)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn verify_entry(&self, entry: &StoreEntry) -> Result<Verification, JamError> {
+        let index = match self
+            .files
+            .read_index(&package_dir_name(&entry.name, &entry.version))?
+        {
+            Some(index) => index,
+            None => return Ok(Verification::Unindexed),
+        };
+
+        let problems = self
+            .files
+            .verify_package(&index, &self.entry_code_path(entry))?;
+
+        if problems.is_empty() {
+            Ok(Verification::Valid)
+        } else {
+            Ok(Verification::Invalid(problems))
+        }
+    }
+
+    // The file index only proves the files didn't change since the import, comparing it with a
+    // fresh extraction of the tarball also catches packages that were corrupted before
+    pub fn verify_entry_tarball(
+        &self,
+        entry: &StoreEntry,
+        tarball_path: &Path,
+        archiver: &dyn Archiver,
+    ) -> Result<Verification, JamError> {
+        let dir_name = package_dir_name(&entry.name, &entry.version);
+        let _lock = FileLock::acquire(&self.lock_path(&dir_name))?;
+
+        let index = match self.files.read_index(&dir_name)? {
+            Some(index) => index,
+            None => return Ok(Verification::Unindexed),
+        };
+
+        let staging_path = self.create_entry_staging_path(&dir_name)?;
+        let problems = archiver
+            .extract_to(tarball_path, &staging_path)
+            .and_then(|_| self.files.verify_package(&index, &staging_path));
+        fs::remove_dir_all(&staging_path)?;
+
+        let problems = problems?;
+        if problems.is_empty() {
+            Ok(Verification::Valid)
+        } else {
+            Ok(Verification::Invalid(problems))
+        }
+    }
+
+    // Replaces the package files with a fresh extraction, keeping the links to its dependencies
+    pub fn repair_entry(
+        &self,
+        entry: &StoreEntry,
+        tarball_path: &Path,
+        archiver: &dyn Archiver,
+    ) -> Result<(), JamError> {
+        let dir_name = package_dir_name(&entry.name, &entry.version);
+        let _lock = FileLock::acquire(&self.lock_path(&dir_name))?;
+
+        if let Some(index) = self.files.read_index(&dir_name)? {
+            self.files.remove_corrupted_files(&index)?;
+        }
+
+        let staging_path = self.create_entry_staging_path(&dir_name)?;
+        let staging_code_path = staging_path.join("node_modules").join(&entry.name);
+
+        fs::create_dir_all(&staging_code_path)?;
+        if let Err(err) = archiver
+            .extract_to(tarball_path, &staging_code_path)
+            .and_then(|_| self.files.import_package(&dir_name, &staging_code_path))
+        {
+            fs::remove_dir_all(&staging_path)?;
+
+            return Err(err);
+        }
+
+        let code_path = self.entry_code_path(entry);
+        if code_path.exists() {
+            fs::remove_dir_all(&code_path)?;
+        }
+        fs::create_dir_all(code_path.parent().unwrap())?;
+        fs::rename(&staging_code_path, &code_path)?;
+        fs::remove_dir_all(&staging_path)?;
+
+        Ok(())
+    }
+
+    // Number and total size of the files shared by the store entries
+    pub fn files_size(&self) -> Result<(u64, u64), JamError> {
+        self.files.size()
+    }
+
+    // Uses the change time like `PruneOptions::is_recent`
+    pub fn entry_changed_at(&self, entry: &StoreEntry) -> Result<SystemTime, JamError> {
+        let metadata = fs::metadata(self.entry_path(entry))?;
+
+        Ok(UNIX_EPOCH + Duration::new(metadata.ctime() as u64, metadata.ctime_nsec() as u32))
+    }
+
+    fn entry_path(&self, entry: &StoreEntry) -> PathBuf {
         self.store_path
-            .join(package_dir_name)
+            .join(package_dir_name(&entry.name, &entry.version))
+    }
+
+    fn entry_code_path(&self, entry: &StoreEntry) -> PathBuf {
+        self.entry_path(entry)
             .join("node_modules")
-            .join(&package.name)
+            .join(&entry.name)
     }
+
+    // Removes every package entry that isn't in `referenced` (given as `name@version`), along with
+    // the stored files only they used and staging directories left behind by interrupted installs
+    pub fn prune(
+        &self,
+        referenced: &HashSet<String>,
+        options: &PruneOptions,
+    ) -> Result<PruneStats, JamError> {
+        let mut stats = PruneStats::default();
+        let mut kept_entries = HashSet::new();
+        let mut legacy_links = vec![];
+
+        for entry in fs::read_dir(&self.store_path)? {
+            let entry = entry?;
+            let path = entry.path();
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+            let file_type = entry.file_type()?;
+
+            if let Some(entry_name) = staging_entry_name(&dir_name) {
+                let _lock = self.lock_entry(entry_name, options)?;
+                self.remove_entry(&path, options, &mut stats)?;
+                continue;
+            }
+
+            if file_type.is_symlink() {
+                legacy_links.push(path);
+                continue;
+            }
+
+            if !file_type.is_dir()
+                || dir_name.starts_with('.')
+                || dir_name == FILES_DIR_NAME
+                || dir_name == INDEX_DIR_NAME
+                || !is_current_entry(&dir_name)
+            {
+                continue;
+            }
+
+            let key = decode_key(&dir_name).unwrap();
+            if referenced.contains(&key) || options.is_recent(&entry.metadata()?) {
+                kept_entries.insert(dir_name);
+                continue;
+            }
+
+            let _lock = self.lock_entry(&dir_name, options)?;
+            info!("Removing store entry {}", key);
+
+            self.remove_entry(&path, options, &mut stats)?;
+            stats.packages += 1;
+        }
+
+        // Entries moved out of the unversioned layout left links behind in the store root
+        for entry in fs::read_dir(&self.root_path)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_symlink() {
+                legacy_links.push(entry.path());
+            }
+        }
+
+        // Links left behind by the store migrations are removed with the entry they point to
+        for link_path in legacy_links {
+            let target_name = fs::read_link(&link_path)?
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string());
+
+            if !target_name.is_some_and(|name| kept_entries.contains(&name)) {
+                if !options.dry_run {
+                    fs::remove_file(&link_path)?;
+                }
+                stats.files += 1;
+            }
+        }
+
+        let files_stats = self.files.prune(
+            |key| {
+                kept_entries.contains(key)
+                    || decode_key(key).is_some_and(|key| referenced.contains(&key))
+            },
+            options,
+        )?;
+        stats.files += files_stats.files;
+        stats.bytes += files_stats.bytes;
+
+        Ok(stats)
+    }
+
+    fn lock_path(&self, dir_name: &str) -> PathBuf {
+        self.store_path
+            .join(LOCKS_DIR_NAME)
+            .join(format!("{}.lock", dir_name))
+    }
+
+    // Nothing is removed on a dry run, so there is no install to wait for
+    fn lock_entry(
+        &self,
+        dir_name: &str,
+        options: &PruneOptions,
+    ) -> Result<Option<FileLock>, JamError> {
+        if options.dry_run {
+            Ok(None)
+        } else {
+            Ok(Some(FileLock::acquire(&self.lock_path(dir_name))?))
+        }
+    }
+
+    // Files linked into the file store only free space once their stored copy is removed too
+    fn remove_entry(
+        &self,
+        path: &Path,
+        options: &PruneOptions,
+        stats: &mut PruneStats,
+    ) -> Result<(), JamError> {
+        if !path.exists() {
+            return Ok(());
+        }
+
+        for relative_path in list_files(path)? {
+            let metadata = fs::symlink_metadata(path.join(relative_path))?;
+
+            stats.files += 1;
+            if metadata.nlink() == 1 {
+                stats.bytes += metadata.len();
+            }
+        }
+
+        if !options.dry_run {
+            fs::remove_dir_all(path)?;
+        }
+
+        Ok(())
+    }
+
+    // Entries extracted before store keys were encoded are renamed, leaving a link behind
+    // so node_modules links created by older installs keep resolving
+    fn migrate_legacy_entries(&self) -> Result<(), JamError> {
+        for entry in fs::read_dir(&self.store_path)? {
+            let entry = entry?;
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+
+            // Hidden entries hold locks and staging directories, never packages
+            if !entry.file_type()?.is_dir()
+                || dir_name.starts_with('.')
+                || is_current_entry(&dir_name)
+            {
+                continue;
+            }
+
+            if let Some((name, version)) = find_legacy_package(&entry.path(), &dir_name)? {
+                let package_path = self.store_path.join(package_dir_name(&name, &version));
+
+                if package_path.exists() {
+                    continue;
+                }
+
+                info!("Migrating store entry {} to {:?}", dir_name, package_path);
+
+                fs::rename(entry.path(), &package_path)?;
+                symlink(&package_path, entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Entries written by older versions link their dependencies absolutely, which breaks once the
+    // store is moved. Links are swapped atomically, other installs may resolve through them.
+    fn migrate_absolute_links(&self) -> Result<(), JamError> {
+        let marker_path = self.root_path.join(RELATIVE_LINKS_FILE_NAME);
+        if marker_path.exists() {
+            return Ok(());
+        }
+
+        let _lock = FileLock::acquire(&self.root_path.join(LAYOUT_LOCK_FILE_NAME))?;
+
+        // Another process may have migrated the links while we were waiting for the lock
+        if marker_path.exists() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&self.store_path)? {
+            let entry = entry?;
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+
+            if !entry.file_type()?.is_dir() || !is_current_entry(&dir_name) {
+                continue;
+            }
+
+            for link_path in list_links(&entry.path().join("node_modules"))? {
+                let target = fs::read_link(&link_path)?;
+                if target.is_relative() {
+                    continue;
+                }
+
+                let temp_path = link_path.with_file_name(format!(
+                    ".{}.{}",
+                    link_path.file_name().unwrap().to_string_lossy(),
+                    process::id()
+                ));
+                symlink(
+                    relative_path(link_path.parent().unwrap(), &target),
+                    &temp_path,
+                )?;
+                fs::rename(&temp_path, &link_path)?;
+            }
+        }
+
+        fs::write(&marker_path, "")?;
+
+        Ok(())
+    }
+}
+
+impl PruneOptions {
+    // The change time moves whenever a file is created, renamed or linked, unlike the
+    // modification time which extracted files inherit from their tarball
+    pub fn is_recent(&self, metadata: &Metadata) -> bool {
+        let changed_at =
+            UNIX_EPOCH + Duration::new(metadata.ctime() as u64, metadata.ctime_nsec() as u32);
+
+        match SystemTime::now().checked_sub(self.grace_period) {
+            Some(cutoff) => changed_at > cutoff,
+            None => true,
+        }
+    }
+
+    pub fn remove_file(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        stats: &mut PruneStats,
+    ) -> Result<(), JamError> {
+        if !self.dry_run {
+            fs::remove_file(path)?;
+        }
+
+        stats.files += 1;
+        stats.bytes += metadata.len();
+
+        Ok(())
+    }
+}
+
+fn layout_dir_name(version: u32) -> String {
+    format!("v{}", version)
+}
+
+fn read_layout_version(root_path: &Path) -> Result<Option<u32>, JamError> {
+    let version_path = root_path.join(LAYOUT_VERSION_FILE_NAME);
+
+    match fs::read_to_string(&version_path) {
+        Ok(content) => content.trim().parse().map(Some).map_err(|_| {
+            JamError::new(format!(
+                "Invalid store layout version {:?} in {:?}",
+                content, version_path
+            ))
+        }),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_layout_version(root_path: &Path, version: u32) -> Result<(), JamError> {
+    let version_path = root_path.join(LAYOUT_VERSION_FILE_NAME);
+    let temp_path = root_path.join(format!(".{}.{}", LAYOUT_VERSION_FILE_NAME, process::id()));
+
+    fs::write(&temp_path, version.to_string())?;
+    fs::rename(&temp_path, &version_path)?;
+
+    Ok(())
+}
+
+// Moves the entries of the previous layout into the current one. Stores last opened by a newer
+// jam are left as they are, the newer layout directory is simply not used.
+fn migrate_layout(root_path: &Path, store_path: &Path) -> Result<(), JamError> {
+    if read_layout_version(root_path)?.is_some_and(|version| version >= STORE_LAYOUT_VERSION) {
+        return Ok(());
+    }
+
+    let _lock = FileLock::acquire(&root_path.join(LAYOUT_LOCK_FILE_NAME))?;
+
+    // Another process may have migrated the store while we were waiting for the lock
+    match read_layout_version(root_path)? {
+        Some(version) if version >= STORE_LAYOUT_VERSION => return Ok(()),
+        Some(_) => {}
+        None => migrate_unversioned_layout(root_path, store_path)?,
+    }
+
+    write_layout_version(root_path, STORE_LAYOUT_VERSION)
+}
+
+// The unversioned layout kept its entries in the store root. They are renamed into the versioned
+// directory, leaving links behind so node_modules links created by older installs keep resolving.
+// Locks stay in place, older jam versions still running keep coordinating through them.
+fn migrate_unversioned_layout(root_path: &Path, store_path: &Path) -> Result<(), JamError> {
+    let mut migrated = 0;
+
+    for entry in fs::read_dir(root_path)? {
+        let entry = entry?;
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+
+        // Links were left behind by the store key migration and keep resolving through the new links
+        if !entry.file_type()?.is_dir()
+            || dir_name == LOCKS_DIR_NAME
+            || (dir_name.starts_with('v') && dir_name[1..].parse::<u32>().is_ok())
+        {
+            continue;
+        }
+
+        let target_path = store_path.join(&dir_name);
+        if target_path.exists() {
+            continue;
+        }
+
+        fs::rename(entry.path(), &target_path)?;
+
+        if !dir_name.starts_with('.') && dir_name != FILES_DIR_NAME && dir_name != INDEX_DIR_NAME {
+            symlink(&target_path, entry.path())?;
+        }
+        migrated += 1;
+    }
+
+    if migrated > 0 {
+        info!(
+            "Migrated {} store entries to layout version {}",
+            migrated, STORE_LAYOUT_VERSION
+        );
+    }
+
+    Ok(())
+}
+
+fn package_dir_name(name: &str, version: &str) -> String {
+    encode_key(&format!("{}@{}", name, version))
+}
+
+// Staging directories are named `.staging-<entry>.<pid>.<id>`
+fn staging_entry_name(dir_name: &str) -> Option<&str> {
+    let name = dir_name.strip_prefix(STAGING_DIR_PREFIX)?;
+    let mut parts = name.rsplitn(3, '.');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(_), Some(entry_name)) => Some(entry_name),
+        _ => None,
+    }
+}
+
+// Links of a node_modules directory, including the ones of scoped packages
+fn list_links(node_modules_path: &Path) -> Result<Vec<PathBuf>, JamError> {
+    let mut links = vec![];
+
+    if !node_modules_path.is_dir() {
+        return Ok(links);
+    }
+
+    for entry in fs::read_dir(node_modules_path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            links.push(entry.path());
+        } else if file_type.is_dir() && entry.file_name().to_string_lossy().starts_with('@') {
+            for scoped_entry in fs::read_dir(entry.path())? {
+                let scoped_entry = scoped_entry?;
+
+                if scoped_entry.file_type()?.is_symlink() {
+                    links.push(scoped_entry.path());
+                }
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+// Scoped package names always contain a `/`, so a decoded `@scope_name@1.0.0` is an old entry
+fn is_current_entry(dir_name: &str) -> bool {
+    match decode_key(dir_name) {
+        Some(key) => !key.starts_with('@') || key.contains('/'),
+        None => false,
+    }
+}
+
+// The old directory names are ambiguous, the real package name is recovered from the extracted package
+fn find_legacy_package(
+    entry_path: &Path,
+    dir_name: &str,
+) -> Result<Option<(String, String)>, JamError> {
+    let (legacy_name, version) = match dir_name.rfind('@') {
+        Some(index) if index > 0 => (&dir_name[..index], &dir_name[index + 1..]),
+        _ => return Ok(None),
+    };
+
+    let node_modules_path = entry_path.join("node_modules");
+    if !node_modules_path.is_dir() {
+        return Ok(None);
+    }
+
+    for child_name in package_dirs(&node_modules_path)? {
+        let candidates = if child_name.starts_with('@') {
+            package_dirs(&node_modules_path.join(&child_name))?
+                .into_iter()
+                .map(|name| format!("{}/{}", child_name, name))
+                .collect()
+        } else {
+            vec![child_name]
+        };
+
+        if let Some(name) = candidates
+            .into_iter()
+            .find(|name| sanitize_package_name(name) == legacy_name)
+        {
+            return Ok(Some((name, version.to_string())));
+        }
+    }
+
+    Ok(None)
+}
+
+// Dependencies are symlinked next to the package itself, only real directories are candidates
+fn package_dirs(path: &Path) -> Result<Vec<String>, JamError> {
+    let mut names = vec![];
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    Ok(names)
 }
 
 #[cfg(test)]
@@ -47,13 +810,22 @@ mod tests {
     use super::*;
     use jam_test_utils::sync_helpers::with_tmp_dir;
 
+    fn layout_path(path: &Path) -> PathBuf {
+        path.join("store")
+            .join(layout_dir_name(STORE_LAYOUT_VERSION))
+    }
+
     #[test]
     fn creates_store_on_initialization() {
         with_tmp_dir(|path| {
             let result = Store::new(&path);
 
             assert!(result.is_ok());
-            assert!(path.join("store").exists());
+            assert!(layout_path(&path).exists());
+            assert_eq!(
+                read_layout_version(&path.join("store")).unwrap(),
+                Some(STORE_LAYOUT_VERSION)
+            );
         })
     }
 
@@ -73,7 +845,7 @@ mod tests {
 
             let package_path = store.package_root_path_in_store(&npm_package);
 
-            assert_eq!(package_path, path.join("store").join("package_name@1.0.0"));
+            assert_eq!(package_path, layout_path(&path).join("package_name@1.0.0"));
         })
     }
 
@@ -95,7 +867,7 @@ mod tests {
 
             assert_eq!(
                 package_path,
-                path.join("store").join("@scope_package_name@1.0.0")
+                layout_path(&path).join("@scope%2Fpackage_name@1.0.0")
             );
         })
     }
@@ -118,7 +890,7 @@ mod tests {
 
             assert_eq!(
                 package_path,
-                path.join("store")
+                layout_path(&path)
                     .join("package_name@1.0.0")
                     .join("node_modules")
                     .join("package_name")
@@ -144,12 +916,591 @@ mod tests {
 
             assert_eq!(
                 package_path,
-                path.join("store")
-                    .join("@scope_package_name@1.0.0")
+                layout_path(&path)
+                    .join("@scope%2Fpackage_name@1.0.0")
                     .join("node_modules")
                     .join("@scope")
                     .join("package_name")
             );
         })
     }
+
+    fn create_legacy_entry(store_path: &Path, dir_name: &str, package_name: &str) -> PathBuf {
+        let entry_path = store_path.join(dir_name);
+        let code_path = entry_path.join("node_modules").join(package_name);
+
+        fs::create_dir_all(&code_path).unwrap();
+        fs::write(code_path.join("index.js"), "const x = 1").unwrap();
+
+        entry_path
+    }
+
+    #[test]
+    fn migrates_legacy_scoped_entries() {
+        with_tmp_dir(|path| {
+            let store_path = path.join("store");
+            let legacy_path = create_legacy_entry(
+                &store_path,
+                "@scope_package_name@1.0.0",
+                "@scope/package_name",
+            );
+            let dependency_path =
+                create_legacy_entry(&store_path, "dependency@1.0.0", "dependency");
+            symlink(
+                dependency_path.join("node_modules").join("dependency"),
+                legacy_path.join("node_modules").join("dependency"),
+            )
+            .unwrap();
+
+            let store = Store::new(&path).unwrap();
+
+            let npm_package = NpmPackage::new(
+                "@scope/package_name".to_string(),
+                "1.0.0".to_string(),
+                None,
+                "shasum".to_string(),
+                "tarball".to_string(),
+                vec![],
+            );
+            let code_path = store.package_code_path_in_store(&npm_package);
+
+            assert!(code_path.join("index.js").exists());
+            assert_eq!(
+                fs::canonicalize(&legacy_path).unwrap(),
+                store.package_root_path_in_store(&npm_package)
+            );
+            assert!(legacy_path
+                .join("node_modules")
+                .join("@scope")
+                .join("package_name")
+                .join("index.js")
+                .exists());
+            assert!(dependency_path.is_dir());
+        })
+    }
+
+    #[test]
+    fn relativizes_absolute_links_once() {
+        with_tmp_dir(|path| {
+            let store_path = layout_path(&path);
+            let entry_path =
+                create_legacy_entry(&store_path, &package_dir_name("p1", "1.0.0"), "p1");
+            let dependency_path = create_legacy_entry(
+                &store_path,
+                &package_dir_name("@scope/p2", "1.0.0"),
+                "@scope/p2",
+            );
+            let link_path = entry_path.join("node_modules").join("@scope").join("p2");
+            fs::create_dir_all(link_path.parent().unwrap()).unwrap();
+            symlink(
+                dependency_path
+                    .join("node_modules")
+                    .join("@scope")
+                    .join("p2"),
+                &link_path,
+            )
+            .unwrap();
+
+            Store::new(&path).unwrap();
+
+            assert!(fs::read_link(&link_path).unwrap().is_relative());
+            assert!(link_path.join("index.js").exists());
+
+            let dependency_code_path = fs::canonicalize(&link_path).unwrap();
+            fs::remove_file(&link_path).unwrap();
+            symlink(&dependency_code_path, &link_path).unwrap();
+
+            Store::new(&path).unwrap();
+
+            assert!(fs::read_link(&link_path).unwrap().is_absolute());
+        })
+    }
+
+    #[test]
+    fn migrates_legacy_entries_with_upper_case_names() {
+        with_tmp_dir(|path| {
+            create_legacy_entry(&path.join("store"), "JSONStream@1.3.5", "JSONStream");
+
+            let store = Store::new(&path).unwrap();
+
+            let npm_package = NpmPackage::new(
+                "JSONStream".to_string(),
+                "1.3.5".to_string(),
+                None,
+                "shasum".to_string(),
+                "tarball".to_string(),
+                vec![],
+            );
+
+            assert!(store
+                .package_code_path_in_store(&npm_package)
+                .join("index.js")
+                .exists());
+        })
+    }
+
+    #[test]
+    fn keeps_migrated_entries_on_reopen() {
+        with_tmp_dir(|path| {
+            create_legacy_entry(&path.join("store"), "@scope_a@1.0.0", "@scope/a");
+
+            Store::new(&path).unwrap();
+            let result = Store::new(&path);
+
+            assert!(result.is_ok());
+            assert!(layout_path(&path).join("@scope%2Fa@1.0.0").is_dir());
+        })
+    }
+
+    fn create_package(name: &str) -> NpmPackage {
+        NpmPackage::new(
+            name.to_string(),
+            "1.0.0".to_string(),
+            None,
+            "shasum".to_string(),
+            "tarball".to_string(),
+            vec![],
+        )
+    }
+
+    fn stage_package(store: &Store, package: &NpmPackage, content: &str) -> PathBuf {
+        let staging_path = store.create_staging_path(package).unwrap();
+        let code_path = staging_path.join("node_modules").join(&package.name);
+
+        fs::create_dir_all(&code_path).unwrap();
+        fs::write(code_path.join("index.js"), content).unwrap();
+
+        staging_path
+    }
+
+    #[test]
+    fn packages_are_complete_only_once_marked() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let package = create_package("@scope/p1");
+
+            let staging_path = stage_package(&store, &package, "const x = 1");
+            store.commit_package(&package, &staging_path).unwrap();
+
+            assert!(store.package_code_path_in_store(&package).exists());
+            assert!(!store.is_package_complete(&package));
+
+            store.mark_package_complete(&package).unwrap();
+
+            assert!(store.is_package_complete(&package));
+            assert!(!staging_path.exists());
+        })
+    }
+
+    #[test]
+    fn replaces_incomplete_entries_on_commit() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let package = create_package("p1");
+            fs::create_dir_all(store.package_code_path_in_store(&package)).unwrap();
+
+            let staging_path = stage_package(&store, &package, "const x = 1");
+            store.commit_package(&package, &staging_path).unwrap();
+
+            assert_eq!(
+                fs::read_to_string(store.package_code_path_in_store(&package).join("index.js"))
+                    .unwrap(),
+                "const x = 1"
+            );
+        })
+    }
+
+    #[test]
+    fn keeps_complete_entries_when_committing_a_duplicate() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let package = create_package("p1");
+
+            let staging_path = stage_package(&store, &package, "first");
+            store.commit_package(&package, &staging_path).unwrap();
+            store.mark_package_complete(&package).unwrap();
+
+            let duplicate_staging_path = stage_package(&store, &package, "second");
+            store
+                .commit_package(&package, &duplicate_staging_path)
+                .unwrap();
+
+            assert!(store.is_package_complete(&package));
+            assert!(!duplicate_staging_path.exists());
+            assert_eq!(
+                fs::read_to_string(store.package_code_path_in_store(&package).join("index.js"))
+                    .unwrap(),
+                "first"
+            );
+        })
+    }
+
+    #[tokio::test]
+    async fn package_locks_are_exclusive() {
+        let tmp_dir = tempdir::TempDir::new("jam-store").unwrap();
+        let store = Store::new(tmp_dir.path()).unwrap();
+        let package = create_package("@scope/p1");
+
+        let lock = store.lock_package(&package).await.unwrap();
+        let second_lock = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            store.lock_package(&package),
+        )
+        .await;
+
+        assert!(second_lock.is_err());
+
+        drop(lock);
+
+        assert!(store.lock_package(&package).await.is_ok());
+    }
+
+    #[test]
+    fn removes_stale_staging_directories() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let package = create_package("p1");
+
+            let stale_staging_path = stage_package(&store, &package, "const x = 1");
+            let staging_path = store.create_staging_path(&package).unwrap();
+
+            assert!(!stale_staging_path.exists());
+            assert!(staging_path.exists());
+        })
+    }
+
+    fn install_package(store: &Store, package: &NpmPackage, content: &str) {
+        let staging_path = stage_package(store, package, content);
+        store
+            .import_package(
+                package,
+                &staging_path.join("node_modules").join(&package.name),
+            )
+            .unwrap();
+        store.commit_package(package, &staging_path).unwrap();
+        store.mark_package_complete(package).unwrap();
+    }
+
+    fn prune_options(dry_run: bool) -> PruneOptions {
+        PruneOptions {
+            dry_run,
+            grace_period: Duration::from_secs(0),
+        }
+    }
+
+    fn index_size(path: &Path, package: &NpmPackage) -> u64 {
+        fs::metadata(layout_path(path).join("index").join(format!(
+            "{}.json",
+            package_dir_name(&package.name, &package.version)
+        )))
+        .unwrap()
+        .len()
+    }
+
+    #[test]
+    fn prunes_unreferenced_packages_and_their_files() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let p1 = create_package("p1");
+            let p2 = create_package("@scope/p2");
+            let p3 = create_package("p3");
+            install_package(&store, &p1, "shared");
+            install_package(&store, &p2, "shared");
+            install_package(&store, &p3, "only p3");
+            let index_bytes = index_size(&path, &p2) + index_size(&path, &p3);
+
+            let referenced = vec!["p1@1.0.0".to_string()].into_iter().collect();
+            let stats = store.prune(&referenced, &prune_options(false)).unwrap();
+
+            // Two package files, their sentinels, two indexes and the stored file only p3 used
+            assert_eq!(
+                stats,
+                PruneStats {
+                    packages: 2,
+                    files: 7,
+                    bytes: index_bytes + 2 * "shasum".len() as u64 + 7,
+                }
+            );
+            assert!(store.is_package_complete(&p1));
+            assert!(!store.package_root_path_in_store(&p2).exists());
+            assert!(!store.package_root_path_in_store(&p3).exists());
+            assert!(store.files.read_index("p1@1.0.0").unwrap().is_some());
+            assert!(store.files.read_index("p3@1.0.0").unwrap().is_none());
+
+            // The remaining index still rebuilds its package
+            let index = store.files.read_index("p1@1.0.0").unwrap().unwrap();
+            assert!(store
+                .files
+                .materialize_package(&index, &path.join("target"))
+                .is_ok());
+        })
+    }
+
+    #[test]
+    fn reports_without_removing_on_dry_run() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let p1 = create_package("p1");
+            install_package(&store, &p1, "const x = 1");
+
+            let stats = store.prune(&HashSet::new(), &prune_options(true)).unwrap();
+
+            assert_eq!(stats.packages, 1);
+            assert_eq!(
+                stats.bytes,
+                index_size(&path, &p1) + "shasum".len() as u64 + 11
+            );
+            assert!(store.is_package_complete(&p1));
+            assert!(store.files.read_index("p1@1.0.0").unwrap().is_some());
+        })
+    }
+
+    #[test]
+    fn keeps_entries_changed_within_the_grace_period() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let p1 = create_package("p1");
+            install_package(&store, &p1, "const x = 1");
+
+            let options = PruneOptions {
+                dry_run: false,
+                grace_period: Duration::from_secs(60 * 60),
+            };
+            let stats = store.prune(&HashSet::new(), &options).unwrap();
+
+            assert_eq!(stats, PruneStats::default());
+            assert!(store.is_package_complete(&p1));
+        })
+    }
+
+    #[test]
+    fn prunes_staging_directories_and_legacy_links() {
+        with_tmp_dir(|path| {
+            create_legacy_entry(&path.join("store"), "@scope_a@1.0.0", "@scope/a");
+            let store = Store::new(&path).unwrap();
+            let staging_path = stage_package(&store, &create_package("p1"), "const x = 1");
+
+            let stats = store.prune(&HashSet::new(), &prune_options(false)).unwrap();
+
+            assert_eq!(stats.packages, 1);
+            assert!(!staging_path.exists());
+            assert!(fs::symlink_metadata(path.join("store").join("@scope_a@1.0.0")).is_err());
+        })
+    }
+
+    // Installs the package, then moves the store back to the layout used before it was versioned
+    fn create_unversioned_store(path: &Path, package: &NpmPackage) {
+        install_package(&Store::new(path).unwrap(), package, "const x = 1");
+
+        let root_path = path.join("store");
+        for entry in fs::read_dir(layout_path(path)).unwrap() {
+            let entry = entry.unwrap();
+            fs::rename(entry.path(), root_path.join(entry.file_name())).unwrap();
+        }
+        fs::remove_dir(layout_path(path)).unwrap();
+        fs::remove_file(root_path.join(LAYOUT_VERSION_FILE_NAME)).unwrap();
+    }
+
+    #[test]
+    fn migrates_unversioned_stores() {
+        with_tmp_dir(|path| {
+            let package = create_package("@scope/p1");
+            create_unversioned_store(&path, &package);
+            let old_code_path = path
+                .join("store")
+                .join(package_dir_name(&package.name, &package.version))
+                .join("node_modules")
+                .join(&package.name);
+
+            let store = Store::new(&path).unwrap();
+            let entry = store.entries().unwrap().remove(0);
+
+            assert!(store.is_package_complete(&package));
+            assert_eq!(store.verify_entry(&entry).unwrap(), Verification::Valid);
+            assert_eq!(
+                fs::canonicalize(&old_code_path).unwrap(),
+                store.package_code_path_in_store(&package)
+            );
+            assert_eq!(
+                read_layout_version(&path.join("store")).unwrap(),
+                Some(STORE_LAYOUT_VERSION)
+            );
+            assert!(!path.join("store").join(FILES_DIR_NAME).exists());
+        })
+    }
+
+    #[test]
+    fn prunes_links_left_by_the_layout_migration() {
+        with_tmp_dir(|path| {
+            let package = create_package("p1");
+            create_unversioned_store(&path, &package);
+            let store = Store::new(&path).unwrap();
+
+            let stats = store.prune(&HashSet::new(), &prune_options(false)).unwrap();
+
+            assert_eq!(stats.packages, 1);
+            assert!(fs::symlink_metadata(path.join("store").join("p1@1.0.0")).is_err());
+        })
+    }
+
+    #[test]
+    fn leaves_stores_of_newer_layouts_alone() {
+        with_tmp_dir(|path| {
+            let newer_path = path
+                .join("store")
+                .join(layout_dir_name(STORE_LAYOUT_VERSION + 1));
+            fs::create_dir_all(&newer_path).unwrap();
+            write_layout_version(&path.join("store"), STORE_LAYOUT_VERSION + 1).unwrap();
+
+            Store::new(&path).unwrap();
+
+            assert!(newer_path.is_dir());
+            assert!(layout_path(&path).is_dir());
+            assert_eq!(
+                read_layout_version(&path.join("store")).unwrap(),
+                Some(STORE_LAYOUT_VERSION + 1)
+            );
+        })
+    }
+
+    #[test]
+    fn fails_on_invalid_layout_versions() {
+        with_tmp_dir(|path| {
+            fs::create_dir_all(path.join("store")).unwrap();
+            fs::write(path.join("store").join(LAYOUT_VERSION_FILE_NAME), "new").unwrap();
+
+            assert!(Store::new(&path).is_err());
+        })
+    }
+
+    fn create_tarball(path: &Path, content: &str) -> PathBuf {
+        let tarball_path = path.join("package.tgz");
+        let encoder = flate2::write::GzEncoder::new(
+            fs::File::create(&tarball_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "package/index.js", content.as_bytes())
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        tarball_path
+    }
+
+    #[test]
+    fn lists_store_entries() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            install_package(&store, &create_package("@scope/p1"), "const x = 1");
+            let staging_path = stage_package(&store, &create_package("p2"), "const x = 1");
+            store
+                .commit_package(&create_package("p2"), &staging_path)
+                .unwrap();
+
+            assert_eq!(
+                store.entries().unwrap(),
+                vec![
+                    StoreEntry {
+                        name: "@scope/p1".to_string(),
+                        version: "1.0.0".to_string(),
+                        complete: true,
+                    },
+                    StoreEntry {
+                        name: "p2".to_string(),
+                        version: "1.0.0".to_string(),
+                        complete: false,
+                    },
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn verifies_entries_against_their_index() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let package = create_package("p1");
+            install_package(&store, &package, "const x = 1");
+            let entry = store.entries().unwrap().remove(0);
+
+            assert_eq!(store.verify_entry(&entry).unwrap(), Verification::Valid);
+
+            fs::write(
+                store.package_code_path_in_store(&package).join("index.js"),
+                "const x = 2",
+            )
+            .unwrap();
+
+            assert_eq!(
+                store.verify_entry(&entry).unwrap(),
+                Verification::Invalid(vec![FileProblem::Modified("index.js".to_string())])
+            );
+        })
+    }
+
+    #[test]
+    fn verifies_entries_against_their_tarball() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let package = create_package("p1");
+            install_package(&store, &package, "const x = 1");
+            let entry = store.entries().unwrap().remove(0);
+            let archiver = crate::archiver::DefaultArchiver::new();
+
+            assert_eq!(
+                store
+                    .verify_entry_tarball(&entry, &create_tarball(&path, "const x = 1"), &archiver)
+                    .unwrap(),
+                Verification::Valid
+            );
+
+            // The files still match the index, which was written from a different tarball
+            assert_eq!(store.verify_entry(&entry).unwrap(), Verification::Valid);
+            assert_eq!(
+                store
+                    .verify_entry_tarball(&entry, &create_tarball(&path, "const x = 2"), &archiver)
+                    .unwrap(),
+                Verification::Invalid(vec![FileProblem::Modified("index.js".to_string())])
+            );
+        })
+    }
+
+    #[test]
+    fn repairs_entries_from_their_tarball() {
+        with_tmp_dir(|path| {
+            let store = Store::new(&path).unwrap();
+            let package = create_package("p1");
+            install_package(&store, &package, "const x = 1");
+            let code_path = store.package_code_path_in_store(&package);
+            let dependency_link_path = store
+                .package_root_path_in_store(&package)
+                .join("node_modules")
+                .join("dependency");
+            symlink(path.join("dependency"), &dependency_link_path).unwrap();
+            fs::write(code_path.join("index.js"), "const x = 2").unwrap();
+            fs::write(code_path.join("added.js"), "").unwrap();
+            let entry = store.entries().unwrap().remove(0);
+
+            store
+                .repair_entry(
+                    &entry,
+                    &create_tarball(&path, "const x = 1"),
+                    &crate::archiver::DefaultArchiver::new(),
+                )
+                .unwrap();
+
+            assert_eq!(store.verify_entry(&entry).unwrap(), Verification::Valid);
+            assert_eq!(
+                fs::read_to_string(code_path.join("index.js")).unwrap(),
+                "const x = 1"
+            );
+            assert!(fs::symlink_metadata(&dependency_link_path).is_ok());
+            assert!(store.is_package_complete(&package));
+        })
+    }
 }
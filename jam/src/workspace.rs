@@ -3,26 +3,41 @@ use crate::config::Config;
 use crate::errors::JamError;
 use globwalk::GlobWalkerBuilder;
 use jam_common::extract_binaries;
+use jam_core::dependency::Dependency;
 use jam_core::package::BinaryScript;
 use jam_core::package::{Package, WorkspacePackage};
 use jam_npm_metadata::NpmBinMetadata;
 use serde::Deserialize;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 const IGNORE_PATTERS: [&str; 1] = ["!**/node_modules/**"];
+// Workspace packages are resolved by name, npm names can't start with `_` so an unnamed root never
+// shadows a registry package
+const ROOT_PACKAGE_NAME: &str = "_root";
+const ROOT_PACKAGE_VERSION: &str = "0.0.0";
 
+// Only the root manifest may omit its name and version, it is rarely published
 #[derive(Debug, PartialEq, Deserialize)]
 struct PackageJson {
-    name: String,
-    version: String,
+    name: Option<String>,
+    version: Option<String>,
     dependencies: Option<HashMap<String, String>>,
     #[serde(alias = "devDependencies")]
     dev_dependencies: Option<HashMap<String, String>>,
     bin: Option<NpmBinMetadata>,
 }
 
+// Which dependencies of the workspace packages are installed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DependencyScope {
+    #[default]
+    All,
+    Production,
+    Development,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Workspace {
     pub workspace_packages: Vec<WorkspacePackage>,
@@ -45,43 +60,122 @@ impl Workspace {
                 .collect::<Vec<String>>(),
         );
 
-        let walker = GlobWalkerBuilder::from_patterns(&config.root_path, &paths).build()?;
+        // Sorted so the order of workspace packages doesn't depend on the file system
+        let walker = GlobWalkerBuilder::from_patterns(&config.root_path, &paths)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .build()?;
 
         for entry in walker.into_iter().filter_map(Result::ok) {
-            let manifest_file_path = entry.path().to_path_buf();
-            let manifest_file_content = read_manifest_file(manifest_file_path.clone())?;
-            match serde_json::from_str::<PackageJson>(&manifest_file_content) {
-                Ok(package_json) => workspace_packages.push(WorkspacePackage::new(
-                    package_json.name.clone(),
-                    package_json.version,
-                    package_json.dependencies,
-                    package_json.dev_dependencies,
-                    extract_binaries(&package_json.name, &package_json.bin)
-                        .iter()
-                        .map(|(k, v)| {
-                            BinaryScript::new(k.to_string(), PathBuf::from_str(v).unwrap())
-                        })
-                        .collect(),
-                    entry.path().parent().unwrap().to_path_buf(),
-                )),
-                Err(_) => {
-                    return Err(JamError::new(format!(
-                        "Failed to parse {:?}",
-                        manifest_file_path,
-                    )))
+            workspace_packages.push(read_workspace_package(
+                entry.path(),
+                entry.path().parent().unwrap(),
+                false,
+            )?);
+        }
+
+        // The root manifest holds the tooling used across the workspace, it is installed even when
+        // no glob matches it and every other workspace package is linked into it
+        let root_manifest_file_path = config.root_path.join("package.json");
+        if !workspace_packages
+            .iter()
+            .any(|workspace_package| workspace_package.base_path == config.root_path)
+            && root_manifest_file_path.is_file()
+        {
+            workspace_packages.insert(
+                0,
+                read_workspace_package(&root_manifest_file_path, &config.root_path, true)?,
+            );
+        }
+
+        let workspace_dependencies: Vec<Dependency> = workspace_packages
+            .iter()
+            .filter(|workspace_package| workspace_package.base_path != config.root_path)
+            .map(|workspace_package| {
+                Dependency::from_entry(&workspace_package.name, &workspace_package.version)
+            })
+            .collect();
+
+        if let Some(root_package) = workspace_packages
+            .iter_mut()
+            .find(|workspace_package| workspace_package.base_path == config.root_path)
+        {
+            for dependency in workspace_dependencies {
+                if !root_package
+                    .dependencies
+                    .iter()
+                    .chain(root_package.dev_dependencies.iter())
+                    .any(|existing| existing.name == dependency.name)
+                {
+                    root_package.dependencies.push(dependency);
                 }
             }
         }
 
-        if workspace_packages.len() == 0 {
-            Err(JamError::new(format!(
-                "No packages were found in workspace"
-            )))
+        if workspace_packages.is_empty() {
+            Err(JamError::new(
+                "No packages were found in workspace".to_string(),
+            ))
         } else {
             Ok(Workspace { workspace_packages })
         }
     }
 
+    // Workspace packages other workspace packages depend on are needed at runtime, they keep their
+    // production dependencies when only development dependencies are installed
+    pub fn with_scope(mut self, scope: DependencyScope) -> Workspace {
+        match scope {
+            DependencyScope::All => {}
+            DependencyScope::Production => {
+                for workspace_package in &mut self.workspace_packages {
+                    workspace_package.dev_dependencies.clear();
+                }
+            }
+            DependencyScope::Development => {
+                let names: HashSet<String> = self
+                    .workspace_packages
+                    .iter()
+                    .map(|workspace_package| workspace_package.name.clone())
+                    .collect();
+                let mut required: HashSet<String> = HashSet::new();
+
+                loop {
+                    let newly_required: Vec<String> = self
+                        .workspace_packages
+                        .iter()
+                        .flat_map(|workspace_package| {
+                            let production_dependencies =
+                                if required.contains(&workspace_package.name) {
+                                    workspace_package.dependencies.iter()
+                                } else {
+                                    [].iter()
+                                };
+
+                            workspace_package
+                                .dev_dependencies
+                                .iter()
+                                .chain(production_dependencies)
+                        })
+                        .map(|dependency| dependency.real_name.clone())
+                        .filter(|name| names.contains(name) && !required.contains(name))
+                        .collect();
+
+                    if newly_required.is_empty() {
+                        break;
+                    }
+                    required.extend(newly_required);
+                }
+
+                for workspace_package in &mut self.workspace_packages {
+                    if !required.contains(&workspace_package.name) {
+                        workspace_package.dependencies.clear();
+                    }
+                }
+            }
+        }
+
+        self
+    }
+
     pub fn packages(&self) -> Vec<Package> {
         self.workspace_packages
             .iter()
@@ -90,6 +184,39 @@ impl Workspace {
     }
 }
 
+fn read_workspace_package(
+    manifest_file_path: &Path,
+    base_path: &Path,
+    is_root: bool,
+) -> Result<WorkspacePackage, JamError> {
+    let manifest_file_content = read_manifest_file(manifest_file_path.to_path_buf())?;
+    let parse_error = || JamError::new(format!("Failed to parse {:?}", manifest_file_path));
+
+    let package_json =
+        serde_json::from_str::<PackageJson>(&manifest_file_content).map_err(|_| parse_error())?;
+
+    let (name, version) = match (package_json.name, package_json.version) {
+        (Some(name), Some(version)) => (name, version),
+        (name, version) if is_root => (
+            name.unwrap_or_else(|| ROOT_PACKAGE_NAME.to_string()),
+            version.unwrap_or_else(|| ROOT_PACKAGE_VERSION.to_string()),
+        ),
+        _ => return Err(parse_error()),
+    };
+
+    Ok(WorkspacePackage::new(
+        name.clone(),
+        version,
+        package_json.dependencies,
+        package_json.dev_dependencies,
+        extract_binaries(&name, &package_json.bin)
+            .iter()
+            .map(|(k, v)| BinaryScript::new(k.to_string(), PathBuf::from_str(v).unwrap()))
+            .collect(),
+        base_path.to_path_buf(),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,7 +237,7 @@ mod tests {
             let config = Config::new(
                 path.clone(),
                 &with_manifest_file_content(vec!["**/*"]),
-                &registry,
+                Some(&registry),
             )
             .unwrap();
 
@@ -138,7 +265,7 @@ mod tests {
             let config = Config::new(
                 path.clone(),
                 &with_manifest_file_content(vec!["packages/p2"]),
-                &registry,
+                Some(&registry),
             )
             .unwrap();
 
@@ -165,7 +292,7 @@ mod tests {
             let config = Config::new(
                 path.clone(),
                 &with_manifest_file_content(vec!["?", "packages/p1"]),
-                &registry,
+                Some(&registry),
             )
             .unwrap();
 
@@ -200,7 +327,7 @@ mod tests {
             let config = Config::new(
                 path.clone(),
                 &with_manifest_file_content(vec!["**/*"]),
-                &registry,
+                Some(&registry),
             )
             .unwrap();
 
@@ -211,17 +338,17 @@ mod tests {
                 Ok(Workspace {
                     workspace_packages: vec![
                         WorkspacePackage {
-                            base_path: path.join("packages").join("p2"),
-                            name: String::from("p2"),
-                            version: String::from("1.1.0"),
+                            base_path: path.join("packages").join("p1"),
+                            name: String::from("p1"),
+                            version: String::from("1.0.0"),
                             dependencies: vec![],
                             dev_dependencies: vec![],
                             binaries: vec![],
                         },
                         WorkspacePackage {
-                            base_path: path.join("packages").join("p1"),
-                            name: String::from("p1"),
-                            version: String::from("1.0.0"),
+                            base_path: path.join("packages").join("p2"),
+                            name: String::from("p2"),
+                            version: String::from("1.1.0"),
                             dependencies: vec![],
                             dev_dependencies: vec![],
                             binaries: vec![],
@@ -245,7 +372,7 @@ mod tests {
             let config = Config::new(
                 path.clone(),
                 &with_manifest_file_content(vec!["**/*", "!**/p2/**"]),
-                &registry,
+                Some(&registry),
             )
             .unwrap();
 
@@ -280,7 +407,7 @@ mod tests {
             let config = Config::new(
                 path.clone(),
                 &with_manifest_file_content(vec!["**/*", "!**/p2/**"]),
-                &registry,
+                Some(&registry),
             )
             .unwrap();
 
@@ -302,6 +429,151 @@ mod tests {
         });
     }
 
+    #[test]
+    fn links_workspace_packages_into_the_root_package() {
+        let contents = hashmap! {
+            PathBuf::from("") => with_package_json_file_content("monorepo", "1.0.0", Some(hashmap! { "typescript" => "^4.0.0" })),
+            PathBuf::from("packages/p1") => with_package_json_file_content("p1", "1.0.0", None),
+            PathBuf::from("packages/p2") => with_package_json_file_content("p2", "1.1.0", None)
+        };
+
+        given_mono_repo_with(contents, |path| {
+            let registry = String::from("http://some/url");
+
+            let config = Config::new(
+                path.clone(),
+                &with_manifest_file_content(vec!["packages/*"]),
+                Some(&registry),
+            )
+            .unwrap();
+
+            let workspace = Workspace::from_config(&config).unwrap();
+
+            assert_eq!(
+                workspace.workspace_packages[0],
+                WorkspacePackage {
+                    base_path: path.clone(),
+                    name: String::from("monorepo"),
+                    version: String::from("1.0.0"),
+                    dependencies: vec![
+                        Dependency::from_entry("typescript", "^4.0.0"),
+                        Dependency::from_entry("p1", "1.0.0"),
+                        Dependency::from_entry("p2", "1.1.0"),
+                    ],
+                    dev_dependencies: vec![],
+                    binaries: vec![],
+                }
+            );
+            assert_eq!(workspace.workspace_packages.len(), 3);
+        });
+    }
+
+    #[test]
+    fn accepts_root_manifests_without_name_and_version() {
+        let contents = hashmap! {
+            PathBuf::from("") => String::from(r#"{ "devDependencies": { "p1": "*" } }"#),
+            PathBuf::from("packages/p1") => with_package_json_file_content("p1", "1.0.0", None),
+            PathBuf::from("packages/p2") => with_package_json_file_content("p2", "1.1.0", None)
+        };
+
+        given_mono_repo_with(contents, |path| {
+            let registry = String::from("http://some/url");
+
+            let config = Config::new(
+                path.clone(),
+                &with_manifest_file_content(vec!["packages/*"]),
+                Some(&registry),
+            )
+            .unwrap();
+
+            let workspace = Workspace::from_config(&config).unwrap();
+
+            // Workspace packages the root already declares are left as declared
+            assert_eq!(
+                workspace.workspace_packages[0],
+                WorkspacePackage {
+                    base_path: path.clone(),
+                    name: String::from(ROOT_PACKAGE_NAME),
+                    version: String::from(ROOT_PACKAGE_VERSION),
+                    dependencies: vec![Dependency::from_entry("p2", "1.1.0")],
+                    dev_dependencies: vec![Dependency::from_entry("p1", "*")],
+                    binaries: vec![],
+                }
+            );
+        });
+    }
+
+    fn create_scoped_workspace() -> Workspace {
+        let create_package = |name: &str, dependency: &str, dev_dependency: &str| {
+            WorkspacePackage::new(
+                name.to_string(),
+                "1.0.0".to_string(),
+                Some(hashmap! { dependency.to_string() => "^1.0.0".to_string() }),
+                Some(hashmap! { dev_dependency.to_string() => "^1.0.0".to_string() }),
+                vec![],
+                PathBuf::from(name),
+            )
+        };
+
+        Workspace {
+            workspace_packages: vec![
+                create_package("p1", "lib1", "p2"),
+                create_package("p2", "lib2", "test2"),
+                create_package("p3", "lib3", "test3"),
+            ],
+        }
+    }
+
+    fn dependency_names(workspace: &Workspace) -> Vec<(Vec<&str>, Vec<&str>)> {
+        workspace
+            .workspace_packages
+            .iter()
+            .map(|workspace_package| {
+                (
+                    workspace_package
+                        .dependencies
+                        .iter()
+                        .map(|dependency| dependency.name.as_str())
+                        .collect(),
+                    workspace_package
+                        .dev_dependencies
+                        .iter()
+                        .map(|dependency| dependency.name.as_str())
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drops_dev_dependencies_in_production_scope() {
+        let workspace = create_scoped_workspace().with_scope(DependencyScope::Production);
+
+        assert_eq!(
+            dependency_names(&workspace),
+            vec![
+                (vec!["lib1"], vec![]),
+                (vec!["lib2"], vec![]),
+                (vec!["lib3"], vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_dependencies_of_required_workspace_packages_in_development_scope() {
+        let workspace = create_scoped_workspace().with_scope(DependencyScope::Development);
+
+        // p2 is a dev dependency of p1, so it needs its own dependencies
+        assert_eq!(
+            dependency_names(&workspace),
+            vec![
+                (vec![], vec!["p2"]),
+                (vec!["lib2"], vec!["test2"]),
+                (vec![], vec!["test3"])
+            ]
+        );
+    }
+
     #[test]
     fn test_get_packages() {
         let workspace = Workspace {
@@ -1,28 +1,56 @@
+use crate::binaries::{select_binaries, BinaryProvider};
 use crate::downloader::Downloader;
 use crate::errors::JamError;
+use crate::file_store::{format_size, LinkStats};
+use crate::hoisting::{hoist, hoist_deployed, HoistPatterns};
+use crate::linker::{copy_workspace_package, relative_path, resolve_link, Linker};
 use crate::store::Store;
-use futures::StreamExt;
+use futures::stream::{FuturesUnordered, StreamExt};
+use jam_core::package::NpmPackage;
 use jam_core::package::Package;
-use jam_core::package::WorkspacePackage;
-use log::debug;
+use log::{debug, info};
 use path_abs::{PathAbs, PathInfo};
 use petgraph::graph::{Graph, NodeIndex};
-use petgraph::visit::Dfs;
+use petgraph::visit::DfsPostOrder;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::io::ErrorKind;
 use std::os::unix::fs::symlink;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-const CONCURRENCY: usize = 20;
+// Lists the packages jam copied into a node_modules directory, any other directory in it belongs
+// to another tool
+const OWNED_PACKAGES_FILE_NAME: &str = ".jam-packages";
+
+// Holds the packages matching `hoistPattern`, inside the root node_modules
+const HIDDEN_HOIST_DIR_NAME: &str = ".jam";
+
+// Packages extracted or linked at the same time
+const WRITE_CONCURRENCY: usize = 16;
 
 pub struct Writer<'a> {
     store: &'a Store,
     downloader: &'a dyn Downloader,
+    linker: Box<dyn Linker>,
+    bin_overrides: HashMap<String, String>,
+    link_stats: Mutex<LinkStats>,
 }
 
 impl<'a> Writer<'a> {
-    pub fn new(store: &'a Store, downloader: &'a dyn Downloader) -> Writer<'a> {
-        Writer { store, downloader }
+    pub fn new(
+        store: &'a Store,
+        downloader: &'a dyn Downloader,
+        linker: Box<dyn Linker>,
+        bin_overrides: HashMap<String, String>,
+    ) -> Writer<'a> {
+        Writer {
+            store,
+            downloader,
+            linker,
+            bin_overrides,
+            link_stats: Mutex::new(LinkStats::default()),
+        }
     }
 
     // TODO: handle native modules
@@ -31,25 +59,359 @@ impl<'a> Writer<'a> {
         starting_nodes: Vec<NodeIndex>,
         graph: &Graph<Package, ()>,
     ) -> Result<(), JamError> {
-        let mut futures = vec![];
+        // The store is filled before anything links to it, copies need their originals in place
+        let (npm_nodes, workspace_nodes) = write_order(&starting_nodes, graph)
+            .into_iter()
+            .partition(|nx| matches!(graph[*nx], Package::NpmPackage(_)));
+        self.write_packages(npm_nodes, graph).await?;
+        self.write_packages(workspace_nodes, graph).await?;
+
+        self.log_link_stats();
+
+        Ok(())
+    }
+
+    // Fills the store like `write`, then copies the packages into a flat node_modules tree under
+    // `root_path` instead of linking every workspace package to the store
+    pub async fn write_hoisted(
+        &self,
+        root_path: &Path,
+        starting_nodes: Vec<NodeIndex>,
+        graph: &Graph<Package, ()>,
+    ) -> Result<(), JamError> {
+        let placements = hoist(root_path, &starting_nodes, graph)?;
+
+        let npm_nodes = write_order(&starting_nodes, graph)
+            .into_iter()
+            .filter(|nx| matches!(graph[*nx], Package::NpmPackage(_)))
+            .collect();
+        self.write_packages(npm_nodes, graph).await?;
+
+        for placement in &placements {
+            self.write_placement(&graph[placement.package], &placement.path)?;
+        }
+
+        let placed_paths: HashSet<&PathBuf> =
+            placements.iter().map(|placement| &placement.path).collect();
+
+        // Placements are rewritten along with everything nested in them, only the top level
+        // node_modules can hold packages an earlier install placed
+        let mut node_modules_paths = vec![root_path.join("node_modules")];
+        for node in &starting_nodes {
+            if let Package::WorkspacePackage(workspace_package) = &graph[*node] {
+                node_modules_paths.push(workspace_package.base_path.join("node_modules"));
+            }
+        }
+        node_modules_paths.sort();
+        node_modules_paths.dedup();
+
+        for node_modules_path in &node_modules_paths {
+            let names = placements
+                .iter()
+                .map(|placement| graph[placement.package].name())
+                .filter(|name| placed_paths.contains(&node_modules_path.join(name)))
+                .map(String::from)
+                .collect();
+
+            self.prune_entries(node_modules_path, &names, true)?;
+        }
 
         for node in starting_nodes {
-            let mut dfs = Dfs::new(graph, node);
-            while let Some(nx) = dfs.next(graph) {
-                // TODO: We can skip already seen nodes
-                let package = &graph[nx];
-                let dependencies: Vec<&Package> = graph.neighbors(nx).map(|n| &graph[n]).collect();
+            if let Package::WorkspacePackage(workspace_package) = &graph[node] {
+                fs::create_dir_all(workspace_package.base_path.join("node_modules"))?;
 
-                futures.push(self.write_package(package, dependencies));
+                let mut providers = vec![];
+                for dependency in graph.neighbors(node).map(|n| &graph[n]) {
+                    let resolved_path = [&workspace_package.base_path, root_path]
+                        .iter()
+                        .map(|path| path.join("node_modules").join(dependency.name()))
+                        .find(|path| placed_paths.contains(path));
+
+                    if let Some(resolved_path) = resolved_path {
+                        providers.push(BinaryProvider::new(dependency, resolved_path, true));
+                    }
+                }
+
+                self.link_binaries(
+                    &workspace_package
+                        .base_path
+                        .join("node_modules")
+                        .join(".bin"),
+                    &providers,
+                )?;
             }
         }
 
-        futures::stream::iter(futures.into_iter())
-            .buffer_unordered(CONCURRENCY)
-            .collect::<Vec<Result<_, _>>>()
-            .await
+        self.log_link_stats();
+
+        Ok(())
+    }
+
+    // Isolated installs only expose declared dependencies, packages matching `publicHoistPattern`
+    // are additionally linked into the root node_modules and the ones matching `hoistPattern` into
+    // `node_modules/.jam/node_modules`. Store packages resolve requires from their real path, so the
+    // hidden directory is only on their lookup path once NODE_PATH points to it. It is kept per
+    // project and rebuilt on every install, so hoisting never leaks into another project.
+    pub fn link_hoisted_packages(
+        &self,
+        root_path: &Path,
+        starting_nodes: &[NodeIndex],
+        graph: &Graph<Package, ()>,
+        hoist_patterns: &HoistPatterns,
+    ) -> Result<(), JamError> {
+        let hidden_path = root_path.join("node_modules").join(HIDDEN_HOIST_DIR_NAME);
+        if hidden_path.exists() {
+            fs::remove_dir_all(&hidden_path)?;
+        }
+
+        let (public, hidden) = if hoist_patterns.is_empty() {
+            (vec![], vec![])
+        } else {
+            hoist_patterns.select(starting_nodes, graph)
+        };
+        let workspace_names: HashSet<&str> = starting_nodes
+            .iter()
+            .map(|node| graph[*node].name())
+            .collect();
+
+        // A root workspace had its node_modules pruned while writing it, otherwise the root only
+        // holds hoisted links
+        let root_node = starting_nodes
+            .iter()
+            .find(|node| match &graph[**node] {
+                Package::WorkspacePackage(workspace_package) => {
+                    workspace_package.base_path == root_path
+                }
+                Package::NpmPackage(_) => false,
+            })
+            .copied();
+        if root_node.is_none() {
+            self.prune_links(
+                &root_path.join("node_modules"),
+                &public
+                    .iter()
+                    .map(|npm_package| npm_package.name.clone())
+                    .collect(),
+            )?;
+        }
+
+        // Workspace packages are never shadowed by a hoisted package of the same name
+        let hoisted_packages: Vec<Package> = public
+            .into_iter()
+            .filter(|npm_package| !workspace_names.contains(npm_package.name.as_str()))
+            .map(|npm_package| Package::NpmPackage(npm_package.clone()))
+            .collect();
+
+        for package in &hoisted_packages {
+            self.replace_link(root_path, package)?;
+        }
+
+        // Hoisted packages add their binaries to the root, behind the declared dependencies of a
+        // root workspace
+        if root_node.is_none() || !hoisted_packages.is_empty() {
+            let mut providers: Vec<BinaryProvider> = root_node
+                .into_iter()
+                .flat_map(|node| graph.neighbors(node))
+                .map(|n| BinaryProvider::new(&graph[n], self.package_path(&graph[n]), true))
+                .collect();
+            providers.extend(
+                hoisted_packages
+                    .iter()
+                    .map(|package| BinaryProvider::new(package, self.package_path(package), false)),
+            );
+
+            self.link_binaries(&root_path.join("node_modules").join(".bin"), &providers)?;
+        }
+
+        for npm_package in hidden {
+            self.link_package(&hidden_path, &Package::NpmPackage(npm_package.clone()))?;
+        }
+
+        Ok(())
+    }
+
+    // Fills the store, then copies the workspace package and every package it depends on into a
+    // flat node_modules tree under `deploy_path`, nothing in it links back to the store or the
+    // project
+    pub async fn deploy(
+        &self,
+        deploy_path: &Path,
+        workspace_node: NodeIndex,
+        graph: &Graph<Package, ()>,
+    ) -> Result<(), JamError> {
+        let placements = hoist_deployed(deploy_path, workspace_node, graph)?;
+
+        let npm_nodes = write_order(&[workspace_node], graph)
             .into_iter()
-            .collect::<Result<(), JamError>>()
+            .filter(|nx| matches!(graph[*nx], Package::NpmPackage(_)))
+            .collect();
+        self.write_packages(npm_nodes, graph).await?;
+
+        copy_workspace_package(&self.package_path(&graph[workspace_node]), deploy_path)?;
+
+        for placement in &placements {
+            let package = &graph[placement.package];
+            fs::create_dir_all(placement.path.parent().unwrap())?;
+
+            match package {
+                Package::NpmPackage(_) => self
+                    .linker
+                    .link_package(&self.package_path(package), &placement.path)?,
+                Package::WorkspacePackage(workspace_package) => {
+                    copy_workspace_package(&workspace_package.base_path, &placement.path)?
+                }
+            }
+        }
+
+        // Binaries link relative to the deployed directory, so it can be moved
+        let bin_path = deploy_path.join("node_modules").join(".bin");
+        let providers: Vec<BinaryProvider> = graph
+            .neighbors(workspace_node)
+            .map(|n| {
+                let path = deploy_path.join("node_modules").join(graph[n].name());
+                BinaryProvider::new(&graph[n], path, true)
+            })
+            .collect();
+
+        for link in select_binaries(&providers, &self.bin_overrides)? {
+            fs::create_dir_all(&bin_path)?;
+            symlink(
+                relative_path(&bin_path, &link.original),
+                bin_path.join(&link.name),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Takes nodes in `write_order` and starts each one once the dependencies before it are written.
+    // Dependencies after it close a cycle and are not waited for. Downloads are additionally
+    // bounded by the downloader's limiter.
+    async fn write_packages(
+        &self,
+        nodes: Vec<NodeIndex>,
+        graph: &Graph<Package, ()>,
+    ) -> Result<(), JamError> {
+        let positions: HashMap<(&str, &str), usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(position, nx)| ((graph[*nx].name(), graph[*nx].version()), position))
+            .collect();
+        let mut pending_dependencies = vec![0; nodes.len()];
+        let mut dependents = vec![vec![]; nodes.len()];
+
+        for (position, nx) in nodes.iter().enumerate() {
+            let dependencies: HashSet<usize> = graph
+                .neighbors(*nx)
+                .filter_map(|n| positions.get(&(graph[n].name(), graph[n].version())))
+                .copied()
+                .filter(|dependency| *dependency < position)
+                .collect();
+
+            pending_dependencies[position] = dependencies.len();
+            for dependency in dependencies {
+                dependents[dependency].push(position);
+            }
+        }
+
+        let mut ready: VecDeque<usize> = (0..nodes.len())
+            .filter(|position| pending_dependencies[*position] == 0)
+            .collect();
+        let mut running = FuturesUnordered::new();
+
+        while !ready.is_empty() || !running.is_empty() {
+            while running.len() < WRITE_CONCURRENCY {
+                let position = match ready.pop_front() {
+                    Some(position) => position,
+                    None => break,
+                };
+                let nx = nodes[position];
+
+                running.push(async move {
+                    self.write_package(&graph[nx], graph.neighbors(nx).map(|n| &graph[n]).collect())
+                        .await
+                        .map(|_| position)
+                });
+            }
+
+            if let Some(result) = running.next().await {
+                for dependent in &dependents[result?] {
+                    pending_dependencies[*dependent] -= 1;
+                    if pending_dependencies[*dependent] == 0 {
+                        ready.push_back(*dependent);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn log_link_stats(&self) {
+        let link_stats = *self.link_stats.lock().unwrap();
+        if link_stats.files > 0 {
+            info!(
+                "Linked {} files ({}) from the store, saving {} of disk space",
+                link_stats.files,
+                format_size(link_stats.bytes),
+                format_size(link_stats.saved_bytes)
+            );
+        }
+    }
+
+    // Replaces whatever a previous install left at the path, so it must run before the
+    // placements nested under it
+    fn write_placement(&self, package: &Package, path: &Path) -> Result<(), JamError> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        match package {
+            Package::NpmPackage(npm_package) => {
+                fs::create_dir_all(path)?;
+
+                let link_stats = self.materialize_package(npm_package, path)?;
+                self.link_stats.lock().unwrap().add(link_stats);
+            }
+            Package::WorkspacePackage(workspace_package) => {
+                self.linker.symlink(&workspace_package.base_path, path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Store entries written before the file store was introduced are indexed on first use
+    fn materialize_package(
+        &self,
+        npm_package: &NpmPackage,
+        path: &Path,
+    ) -> Result<LinkStats, JamError> {
+        if let Ok(Some(link_stats)) = self.store.materialize_package(npm_package, path) {
+            return Ok(link_stats);
+        }
+
+        fs::remove_dir_all(path)?;
+        fs::create_dir_all(path)?;
+
+        self.store.import_package(
+            npm_package,
+            &self.store.package_code_path_in_store(npm_package),
+        )?;
+        self.store
+            .materialize_package(npm_package, path)?
+            .ok_or_else(|| {
+                JamError::new(format!(
+                    "Failed to copy {}@{} from the store",
+                    npm_package.name, npm_package.version
+                ))
+            })
     }
 
     async fn write_package(
@@ -59,53 +421,132 @@ impl<'a> Writer<'a> {
     ) -> Result<(), JamError> {
         match package {
             Package::NpmPackage(npm_package) => {
-                let path = self.store.package_root_path_in_store(npm_package);
-                let package_files_path = self.store.package_code_path_in_store(npm_package);
+                if !self.store.is_package_complete(npm_package) {
+                    let _lock = self.store.lock_package(npm_package).await?;
 
-                if !package_files_path.exists() {
-                    debug!("Downloading {} to directory {:?}", &npm_package.name, &path);
-
-                    fs::create_dir_all(&package_files_path)?;
-                    self.downloader
-                        .download_to(&npm_package, &package_files_path)
-                        .await?;
-
-                    for dependency in dependencies {
-                        self.create_link(&path, dependency)?;
+                    // Another process may have written the package while we were waiting for the lock
+                    if !self.store.is_package_complete(npm_package) {
+                        self.write_npm_package(npm_package, dependencies).await?;
                     }
                 }
             }
             Package::WorkspacePackage(workspace_package) => {
-                fs::create_dir_all(&workspace_package.base_path.join("node_modules"))?;
-                for dependency in dependencies {
-                    self.create_link(&workspace_package.base_path, dependency)?;
-                    self.link_binaries(&workspace_package, dependency)?;
+                let node_modules_path = workspace_package.base_path.join("node_modules");
+                fs::create_dir_all(&node_modules_path)?;
+
+                for dependency in &dependencies {
+                    self.sync_link(&workspace_package.base_path, dependency)?;
                 }
+
+                self.prune_links(
+                    &node_modules_path,
+                    &dependencies
+                        .iter()
+                        .map(|dependency| dependency.name().to_string())
+                        .collect(),
+                )?;
+                self.link_binaries(
+                    &node_modules_path.join(".bin"),
+                    &dependencies
+                        .iter()
+                        .map(|dependency| {
+                            BinaryProvider::new(dependency, self.package_path(dependency), true)
+                        })
+                        .collect::<Vec<BinaryProvider>>(),
+                )?;
             }
         }
 
         Ok(())
     }
 
-    fn create_link(&self, package_root_path: &Path, to_package: &Package) -> Result<(), JamError> {
-        let original = match to_package {
-            Package::NpmPackage(npm_package) => self.store.package_code_path_in_store(&npm_package),
-            Package::WorkspacePackage(workspace_package) => workspace_package.base_path.clone(),
+    async fn write_npm_package(
+        &self,
+        npm_package: &NpmPackage,
+        dependencies: Vec<&Package>,
+    ) -> Result<(), JamError> {
+        let path = self.store.package_root_path_in_store(npm_package);
+        let staging_path = self.store.create_staging_path(npm_package)?;
+        let staging_files_path = staging_path.join("node_modules").join(&npm_package.name);
+
+        fs::create_dir_all(&staging_files_path)?;
+        let link_stats = match self
+            .write_package_files(npm_package, &path, &staging_files_path)
+            .await
+        {
+            Ok(link_stats) => link_stats,
+            Err(err) => {
+                fs::remove_dir_all(&staging_path)?;
+
+                return Err(err);
+            }
         };
 
+        self.store.commit_package(npm_package, &staging_path)?;
+        self.link_stats.lock().unwrap().add(link_stats);
+
+        for dependency in dependencies {
+            self.create_link(&path, dependency)?;
+        }
+
+        self.store.mark_package_complete(npm_package)?;
+
+        Ok(())
+    }
+
+    // Packages already in the file store are linked from it, only new packages are downloaded
+    async fn write_package_files(
+        &self,
+        npm_package: &NpmPackage,
+        path: &Path,
+        staging_files_path: &Path,
+    ) -> Result<LinkStats, JamError> {
+        match self
+            .store
+            .materialize_package(npm_package, staging_files_path)
+        {
+            Ok(Some(link_stats)) => return Ok(link_stats),
+            Ok(None) => {}
+            Err(err) => {
+                debug!(
+                    "Failed to link {} from the store, downloading it again: {}",
+                    &npm_package.name, err
+                );
+
+                fs::remove_dir_all(staging_files_path)?;
+                fs::create_dir_all(staging_files_path)?;
+            }
+        }
+
+        debug!("Downloading {} to directory {:?}", &npm_package.name, path);
+
+        self.downloader
+            .download_to(npm_package, staging_files_path)
+            .await?;
+
+        self.store.import_package(npm_package, staging_files_path)
+    }
+
+    fn package_path(&self, package: &Package) -> PathBuf {
+        match package {
+            Package::NpmPackage(npm_package) => self.store.package_code_path_in_store(npm_package),
+            Package::WorkspacePackage(workspace_package) => workspace_package.base_path.clone(),
+        }
+    }
+
+    // Links inside the store are relative, so they keep resolving when the store is moved
+    fn create_link(&self, package_root_path: &Path, to_package: &Package) -> Result<(), JamError> {
         let link = package_root_path
             .join("node_modules")
-            .join(&to_package.name());
+            .join(to_package.name());
+        let original = relative_path(link.parent().unwrap(), &self.package_path(to_package));
 
-        // TODO: move to a linker component
         fs::create_dir_all(link.parent().unwrap())?;
         if let Err(err) = symlink(&original, &link) {
             if err.kind() != ErrorKind::AlreadyExists {
                 return Err(JamError::new(format!(
                     "Failed to link package {:?}->{:?} {}",
-                    link,
-                    original,
-                    err.to_string()
+                    link, original, err
                 )));
             }
         }
@@ -113,47 +554,268 @@ impl<'a> Writer<'a> {
         Ok(())
     }
 
-    fn link_binaries(
+    // Links inside projects go through the linker, links inside the store are always relative
+    fn link_package(&self, package_root_path: &Path, to_package: &Package) -> Result<(), JamError> {
+        let original = self.package_path(to_package);
+
+        let link = package_root_path
+            .join("node_modules")
+            .join(to_package.name());
+
+        fs::create_dir_all(link.parent().unwrap())?;
+        let result = match to_package {
+            Package::NpmPackage(_) => self.linker.link_package(&original, &link),
+            Package::WorkspacePackage(_) => self.linker.symlink(&original, &link),
+        };
+        match result {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => return Ok(()),
+            Err(err) => {
+                return Err(JamError::new(format!(
+                    "Failed to link package {:?}->{:?} {}",
+                    link, original, err
+                )))
+            }
+        }
+
+        if let Package::NpmPackage(npm_package) = to_package {
+            if self.linker.is_copy() {
+                self.link_store_dependencies(npm_package, &link)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Copies resolve their dependencies from their own node_modules instead of next to their
+    // real path, so they get the links the package has in the store
+    fn link_store_dependencies(
         &self,
-        workspace_package: &WorkspacePackage,
-        to_package: &Package,
+        npm_package: &NpmPackage,
+        link: &Path,
     ) -> Result<(), JamError> {
-        let links_base_path = workspace_package
-            .base_path
-            .join("node_modules")
-            .join(".bin");
+        let store_node_modules_path = self
+            .store
+            .package_root_path_in_store(npm_package)
+            .join("node_modules");
 
-        fs::create_dir_all(&links_base_path)?;
+        for (name, entry) in package_entries(&store_node_modules_path)? {
+            if name == npm_package.name || !entry.file_type()?.is_symlink() {
+                continue;
+            }
 
-        for binary in to_package.binaries() {
-            let link = links_base_path.join(&binary.name);
-            let original = match to_package {
-                Package::NpmPackage(npm_package) => self
-                    .store
-                    .package_code_path_in_store(&npm_package)
-                    .join(&binary.path),
-                Package::WorkspacePackage(workspace_package) => {
-                    workspace_package.base_path.join(&binary.path)
+            let dependency_link = link.join("node_modules").join(&name);
+            fs::create_dir_all(dependency_link.parent().unwrap())?;
+            if let Err(err) = self
+                .linker
+                .symlink(&resolve_link(&entry.path())?, &dependency_link)
+            {
+                if err.kind() != ErrorKind::AlreadyExists {
+                    return Err(err.into());
                 }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Links left by a previous install may point to another version, or to another package
+    // altogether when the manifest used to declare it under an alias
+    fn sync_link(&self, package_root_path: &Path, to_package: &Package) -> Result<(), JamError> {
+        let link = package_root_path
+            .join("node_modules")
+            .join(to_package.name());
+
+        if let Ok(metadata) = fs::symlink_metadata(&link) {
+            let original = self.package_path(to_package);
+            let is_linked = match to_package {
+                Package::NpmPackage(_) => self.linker.is_package_linked(&original, &link),
+                Package::WorkspacePackage(_) => self.linker.is_symlinked(&original, &link),
             };
+
+            if is_linked {
+                return Ok(());
+            }
+
+            if metadata.is_dir() {
+                fs::remove_dir_all(&link)?;
+            } else {
+                fs::remove_file(&link)?;
+            }
+        }
+
+        self.link_package(package_root_path, to_package)
+    }
+
+    // Unlike declared dependencies, hoisted packages never replace a directory put there by others
+    fn replace_link(&self, package_root_path: &Path, to_package: &Package) -> Result<(), JamError> {
+        let link = package_root_path
+            .join("node_modules")
+            .join(to_package.name());
+
+        if let Ok(metadata) = fs::symlink_metadata(&link) {
+            if !metadata.file_type().is_symlink() && !self.linker.is_copy() {
+                debug!("Not hoisting {:?}, a directory is in the way", link);
+
+                return Ok(());
+            }
+        }
+
+        self.sync_link(package_root_path, to_package)
+    }
+
+    // Without a copying linker, a directory in the way of a link was left to its owner
+    fn prune_links(
+        &self,
+        node_modules_path: &Path,
+        names: &HashSet<String>,
+    ) -> Result<(), JamError> {
+        self.prune_entries(node_modules_path, names, self.linker.is_copy())
+    }
+
+    // Only links and the copies recorded by earlier installs are owned by jam, anything else was
+    // put there by another tool. `copied` tells whether the directories of `names` were written
+    // by this install.
+    fn prune_entries(
+        &self,
+        node_modules_path: &Path,
+        names: &HashSet<String>,
+        copied: bool,
+    ) -> Result<(), JamError> {
+        if !node_modules_path.is_dir() {
+            return Ok(());
+        }
+
+        let owned_packages_path = node_modules_path.join(OWNED_PACKAGES_FILE_NAME);
+        let owned_packages: HashSet<String> = match fs::read_to_string(&owned_packages_path) {
+            Ok(content) => content.lines().map(String::from).collect(),
+            Err(err) if err.kind() == ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut copied_packages = vec![];
+
+        for (name, entry) in package_entries(node_modules_path)? {
+            let file_type = entry.file_type()?;
+
+            if names.contains(&name) {
+                if file_type.is_dir() && (copied || owned_packages.contains(&name)) {
+                    copied_packages.push(name);
+                }
+                continue;
+            }
+            if !(file_type.is_symlink() || owned_packages.contains(&name)) {
+                continue;
+            }
+
+            debug!("Removing extraneous {:?}", entry.path());
+            if file_type.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+
+            let parent_path = entry.path().parent().unwrap().to_path_buf();
+            if parent_path != node_modules_path && fs::read_dir(&parent_path)?.next().is_none() {
+                fs::remove_dir(&parent_path)?;
+            }
+        }
+
+        if copied_packages.is_empty() {
+            if owned_packages_path.exists() {
+                fs::remove_file(&owned_packages_path)?;
+            }
+        } else {
+            copied_packages.sort();
+            fs::write(&owned_packages_path, copied_packages.join("\n"))?;
+        }
+
+        Ok(())
+    }
+
+    // Links the selected binary of every name and removes the ones no longer provided
+    fn link_binaries(&self, bin_path: &Path, providers: &[BinaryProvider]) -> Result<(), JamError> {
+        let links = select_binaries(providers, &self.bin_overrides)?;
+
+        fs::create_dir_all(bin_path)?;
+
+        for binary_link in &links {
+            let link = bin_path.join(&binary_link.name);
             // TODO: handle errors
-            let original = PathAbs::new(original).unwrap();
+            let original = PathAbs::new(&binary_link.original).unwrap();
+
+            if self.linker.is_symlinked(original.as_path(), &link) {
+                continue;
+            }
+            if fs::symlink_metadata(&link).is_ok() {
+                fs::remove_file(&link)?;
+            }
 
-            // TODO: move to linker
-            if let Err(err) = symlink(&original, &link) {
+            if let Err(err) = self.linker.symlink(original.as_path(), &link) {
                 if err.kind() != ErrorKind::AlreadyExists {
                     return Err(JamError::new(format!(
                         "Failed to link binary script {:?}->{:?} {}",
-                        link,
-                        original,
-                        err.to_string()
+                        link, original, err
                     )));
                 }
             }
         }
 
-        Ok(())
+        self.prune_links(
+            bin_path,
+            &links
+                .into_iter()
+                .map(|binary_link| binary_link.name)
+                .collect(),
+        )
+    }
+}
+
+// Dependencies come before their dependents, except along cycles, and shared packages are
+// scheduled once, so no two writes race on the same store entry or node_modules
+fn write_order(starting_nodes: &[NodeIndex], graph: &Graph<Package, ()>) -> Vec<NodeIndex> {
+    let mut order = vec![];
+    let mut scheduled = HashSet::new();
+    let mut dfs = DfsPostOrder::empty(graph);
+
+    for node in starting_nodes {
+        dfs.move_to(*node);
+
+        while let Some(nx) = dfs.next(graph) {
+            if scheduled.insert((graph[nx].name(), graph[nx].version())) {
+                order.push(nx);
+            }
+        }
+    }
+
+    order
+}
+
+// Entries of a node_modules directory by package name, hidden entries like `.bin` are skipped
+fn package_entries(node_modules_path: &Path) -> Result<Vec<(String, fs::DirEntry)>, JamError> {
+    let mut entries = vec![];
+
+    for entry in fs::read_dir(node_modules_path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if name.starts_with('.') {
+            continue;
+        }
+
+        if name.starts_with('@') && entry.file_type()?.is_dir() {
+            for scoped_entry in fs::read_dir(entry.path())? {
+                let scoped_entry = scoped_entry?;
+                let scoped_name =
+                    format!("{}/{}", name, scoped_entry.file_name().to_string_lossy());
+
+                entries.push((scoped_name, scoped_entry));
+            }
+        } else {
+            entries.push((name, entry));
+        }
     }
+
+    Ok(entries)
 }
 
 #[cfg(test)]
@@ -161,14 +823,21 @@ mod tests {
     use super::*;
     use crate::archiver::DefaultArchiver;
     use crate::downloader::TarDownloader;
+    use crate::linker::{relative_path, CopyLinker, RelativeSymlinkLinker, SymlinkLinker};
     use async_trait::async_trait;
     use jam_cache::CacheFactory;
+    use jam_core::http::{ClientFactory, ClientOptions};
+    use jam_core::limiter::{LimiterOptions, RequestLimiter};
     use jam_core::package::BinaryScript;
     use jam_core::package::NpmPackage;
     use jam_core::package::WorkspacePackage;
     use maplit::hashmap;
+    use std::collections::HashMap;
     use std::path::PathBuf;
+    use std::process;
     use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
     use tempdir::TempDir;
 
     fn create_context() -> (
@@ -252,13 +921,25 @@ mod tests {
     fn new_creates_store_folder() {
         let tmp_dir = TempDir::new("jam-writer").unwrap();
         let cache_factory = CacheFactory::new(tmp_dir.path().join("cache_factory"));
+        let client_factory = ClientFactory::new(ClientOptions::default());
 
-        let archiver = DefaultArchiver::new();
-        let downloader = TarDownloader::new(&cache_factory, &archiver).unwrap();
+        let limiter = RequestLimiter::new(LimiterOptions::default()).unwrap();
+        let downloader = TarDownloader::new(
+            &cache_factory,
+            &client_factory,
+            Arc::new(limiter),
+            Arc::new(DefaultArchiver::new()),
+        )
+        .unwrap();
         let store = Store::new(tmp_dir.as_ref()).unwrap();
-        let _ = Writer::new(&store, &downloader);
+        let _ = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
 
-        let expected_path = tmp_dir.path().join("store");
+        let expected_path = tmp_dir.path().join("store").join("v1");
 
         assert!(expected_path.exists());
     }
@@ -281,7 +962,12 @@ mod tests {
 
         let (starting_nodes, _, graph, tmp_dir) = create_context();
         let store = Store::new(tmp_dir.as_ref()).unwrap();
-        let writer = Writer::new(&store, &downloader);
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
 
         let result = writer.write(starting_nodes, &graph).await;
 
@@ -292,7 +978,670 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn succeeds_for_scoped_and_non_scoped_packages() {
+    async fn extracts_every_tarball_once() {
+        struct CountingDownloader {
+            downloads: Mutex<HashMap<String, usize>>,
+        }
+
+        #[async_trait]
+        impl Downloader for CountingDownloader {
+            async fn download_to(&self, package: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                *self
+                    .downloads
+                    .lock()
+                    .unwrap()
+                    .entry(format!("{}@{}", package.name, package.version))
+                    .or_default() += 1;
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = CountingDownloader {
+            downloads: Mutex::new(HashMap::new()),
+        };
+
+        let (starting_nodes, _, graph, tmp_dir) = create_context();
+        let store = Store::new(tmp_dir.as_ref()).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+
+        // p1 is shared by both workspace packages and @scope/p1
+        writer.write(starting_nodes, &graph).await.unwrap();
+
+        assert_eq!(
+            *downloader.downloads.lock().unwrap(),
+            hashmap! {
+                "p1@1.0.0".to_string() => 1,
+                "@scope/p1@2.0.0".to_string() => 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_dependencies_before_their_dependents() {
+        struct SlowDownloader {
+            downloaded: Mutex<Vec<String>>,
+        }
+
+        #[async_trait]
+        impl Downloader for SlowDownloader {
+            async fn download_to(&self, package: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                // @scope/p1 depends on p1, which would still be downloading if both were started
+                if package.name == "p1" {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                } else {
+                    assert_eq!(*self.downloaded.lock().unwrap(), vec!["p1"]);
+                }
+                fs::write(path.join("index.js"), "")?;
+                self.downloaded.lock().unwrap().push(package.name.clone());
+
+                Ok(())
+            }
+        }
+        let downloader = SlowDownloader {
+            downloaded: Mutex::new(vec![]),
+        };
+
+        let (starting_nodes, _, graph, tmp_dir) = create_context();
+        let store = Store::new(tmp_dir.as_ref()).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+
+        writer.write(starting_nodes, &graph).await.unwrap();
+
+        assert_eq!(
+            *downloader.downloaded.lock().unwrap(),
+            vec!["p1", "@scope/p1"]
+        );
+    }
+
+    #[test]
+    fn orders_dependencies_before_their_dependents() {
+        let (starting_nodes, _, graph, _tmp_dir) = create_context();
+
+        let order: Vec<&str> = write_order(&starting_nodes, &graph)
+            .into_iter()
+            .map(|nx| graph[nx].name())
+            .collect();
+
+        assert_eq!(
+            order,
+            vec!["p1", "@scope/p1", "workspace_package", "workspace_package2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_hoisted_node_modules() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, package: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), &package.version)?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, workspace_packages, graph, tmp_dir) = create_context();
+        let root_path = tmp_dir.path().to_path_buf();
+        let store = Store::new(&root_path.join("data")).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+
+        // Left behind by an isolated install
+        fs::create_dir_all(root_path.join("node_modules")).unwrap();
+        symlink(
+            store.package_code_path_in_store(&NpmPackage::new(
+                "p1".to_string(),
+                "1.0.0".to_string(),
+                None,
+                "shasum".to_string(),
+                "tarball-url".to_string(),
+                vec![],
+            )),
+            root_path.join("node_modules").join("p1"),
+        )
+        .unwrap();
+
+        writer
+            .write_hoisted(&root_path, starting_nodes, &graph)
+            .await
+            .unwrap();
+
+        let package_path = root_path.join("node_modules").join("p1");
+        assert!(!fs::symlink_metadata(&package_path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_to_string(package_path.join("index.js")).unwrap(),
+            "1.0.0"
+        );
+        assert_eq!(
+            fs::read_to_string(
+                root_path
+                    .join("node_modules")
+                    .join("@scope")
+                    .join("p1")
+                    .join("index.js")
+            )
+            .unwrap(),
+            "2.0.0"
+        );
+        assert_eq!(
+            fs::read_link(root_path.join("node_modules").join("workspace_package")).unwrap(),
+            workspace_packages[0].base_path
+        );
+        assert_eq!(
+            fs::read_link(
+                workspace_packages[0]
+                    .base_path
+                    .join("node_modules")
+                    .join(".bin")
+                    .join("p1_script")
+            )
+            .unwrap(),
+            package_path.join("bin").join("p1_script.js")
+        );
+        assert!(!workspace_packages[0]
+            .base_path
+            .join("node_modules")
+            .join("p1")
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn prunes_packages_placed_by_earlier_hoisted_installs() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, _: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, workspace_packages, mut graph, tmp_dir) = create_context();
+        let root_path = tmp_dir.path().to_path_buf();
+        let store = Store::new(&root_path.join("data")).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+        let node_modules_path = root_path.join("node_modules");
+        let workspace_node_modules_path = workspace_packages[0].base_path.join("node_modules");
+
+        writer
+            .write_hoisted(&root_path, starting_nodes.clone(), &graph)
+            .await
+            .unwrap();
+        assert!(node_modules_path.join("@scope").join("p1").is_dir());
+
+        fs::create_dir_all(node_modules_path.join("other-tool")).unwrap();
+        symlink(&root_path, workspace_node_modules_path.join("stale")).unwrap();
+
+        let scoped_node = graph
+            .node_indices()
+            .find(|nx| graph[*nx].name() == "@scope/p1")
+            .unwrap();
+        let edge = graph.find_edge(starting_nodes[0], scoped_node).unwrap();
+        graph.remove_edge(edge);
+        writer
+            .write_hoisted(&root_path, starting_nodes, &graph)
+            .await
+            .unwrap();
+
+        assert!(node_modules_path.join("p1").join("index.js").is_file());
+        assert!(!node_modules_path.join("@scope").exists());
+        assert!(node_modules_path.join("other-tool").is_dir());
+        assert!(fs::symlink_metadata(workspace_node_modules_path.join("stale")).is_err());
+    }
+
+    #[tokio::test]
+    async fn links_packages_matching_the_hoist_patterns() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, _: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, _, graph, tmp_dir) = create_context();
+        let root_path = tmp_dir.path().to_path_buf();
+        let store = Store::new(&root_path.join("data")).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+        let hoist_patterns =
+            HoistPatterns::new(vec!["p1".to_string()], vec!["@scope/*".to_string()]).unwrap();
+        let find_package = |name: &str| match &graph[graph
+            .node_indices()
+            .find(|nx| graph[*nx].name() == name)
+            .unwrap()]
+        {
+            Package::NpmPackage(npm_package) => npm_package.clone(),
+            _ => unreachable!(),
+        };
+        let package = find_package("p1");
+        let scoped_package = find_package("@scope/p1");
+        let hidden_path = root_path.join("node_modules").join(".jam");
+        // p1 doesn't declare @scope/p1, node has to find it through the hidden directory
+        let resolve_undeclared = || {
+            let output = process::Command::new("node")
+                .arg("-e")
+                .arg("process.stdout.write(require.resolve('@scope/p1'))")
+                .current_dir(store.package_code_path_in_store(&package))
+                .env("NODE_PATH", hidden_path.join("node_modules"))
+                .output()
+                .unwrap();
+
+            output
+                .status
+                .success()
+                .then(|| PathBuf::from(String::from_utf8(output.stdout).unwrap()))
+        };
+
+        writer.write(starting_nodes.clone(), &graph).await.unwrap();
+
+        assert_eq!(resolve_undeclared(), None);
+
+        writer
+            .link_hoisted_packages(&root_path, &starting_nodes, &graph, &hoist_patterns)
+            .unwrap();
+
+        assert_eq!(
+            fs::read_link(root_path.join("node_modules").join("p1")).unwrap(),
+            store.package_code_path_in_store(&package)
+        );
+        assert!(!root_path.join("node_modules").join("@scope").exists());
+        assert_eq!(
+            resolve_undeclared(),
+            Some(
+                fs::canonicalize(store.package_code_path_in_store(&scoped_package))
+                    .unwrap()
+                    .join("index.js")
+            )
+        );
+
+        writer
+            .link_hoisted_packages(
+                &root_path,
+                &starting_nodes,
+                &graph,
+                &HoistPatterns::default(),
+            )
+            .unwrap();
+
+        assert!(!root_path.join("node_modules").join("p1").exists());
+        assert!(!hidden_path.exists());
+    }
+
+    #[tokio::test]
+    async fn links_one_binary_per_name() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, _: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("cli.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let tmp_dir = TempDir::new("jam-writer").unwrap();
+        let workspace_package = WorkspacePackage::new(
+            String::from("w1"),
+            String::from("1.0.0"),
+            None,
+            None,
+            vec![],
+            tmp_dir.path().join("w1"),
+        );
+        let mut graph = Graph::new();
+        let w1 = graph.add_node(Package::WorkspacePackage(workspace_package.clone()));
+        for name in ["b", "a"] {
+            let node = graph.add_node(Package::NpmPackage(NpmPackage::new(
+                name.to_string(),
+                String::from("1.0.0"),
+                None,
+                String::from("shasum"),
+                String::from("tarball"),
+                vec![BinaryScript::new(
+                    String::from("cli"),
+                    PathBuf::from("cli.js"),
+                )],
+            )));
+            graph.add_edge(w1, node, ());
+        }
+
+        let store = Store::new(&tmp_dir.path().join("data")).unwrap();
+        let cli_path = workspace_package
+            .base_path
+            .join("node_modules")
+            .join(".bin")
+            .join("cli");
+        let cli_target = |name: &str| {
+            fs::read_link(&cli_path).unwrap().starts_with(
+                tmp_dir
+                    .path()
+                    .join("data")
+                    .join("store")
+                    .join("v1")
+                    .join(format!("{}@1.0.0", name)),
+            )
+        };
+
+        Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        )
+        .write(vec![w1], &graph)
+        .await
+        .unwrap();
+
+        assert!(cli_target("a"));
+
+        Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            hashmap! { String::from("cli") => String::from("b") },
+        )
+        .write(vec![w1], &graph)
+        .await
+        .unwrap();
+
+        assert!(cli_target("b"));
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_links_and_prunes_extraneous_ones() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, _: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, workspace_packages, graph, tmp_dir) = create_context();
+        let store = Store::new(&tmp_dir.path().join("data")).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+        let node_modules_path = workspace_packages[0].base_path.join("node_modules");
+        let old_path = tmp_dir.path().join("old");
+
+        writer.write(starting_nodes.clone(), &graph).await.unwrap();
+
+        fs::create_dir_all(node_modules_path.join("@old")).unwrap();
+        fs::create_dir_all(node_modules_path.join("not-linked")).unwrap();
+        fs::remove_file(node_modules_path.join("p1")).unwrap();
+        symlink(&old_path, node_modules_path.join("p1")).unwrap();
+        symlink(&old_path, node_modules_path.join("old")).unwrap();
+        symlink(&old_path, node_modules_path.join("@old").join("p1")).unwrap();
+        symlink(&old_path, node_modules_path.join(".bin").join("old_script")).unwrap();
+
+        writer.write(starting_nodes, &graph).await.unwrap();
+
+        let p1_package = match &graph[graph
+            .node_indices()
+            .find(|nx| graph[*nx].name() == "p1")
+            .unwrap()]
+        {
+            Package::NpmPackage(npm_package) => npm_package.clone(),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            fs::read_link(node_modules_path.join("p1")).unwrap(),
+            store.package_code_path_in_store(&p1_package)
+        );
+        assert!(fs::symlink_metadata(node_modules_path.join("old")).is_err());
+        assert!(!node_modules_path.join("@old").exists());
+        assert!(fs::symlink_metadata(node_modules_path.join(".bin").join("old_script")).is_err());
+        assert!(fs::symlink_metadata(node_modules_path.join(".bin").join("p1_script")).is_ok());
+        assert!(node_modules_path.join("@scope").join("p1").exists());
+        assert!(node_modules_path.join("not-linked").is_dir());
+    }
+
+    #[tokio::test]
+    async fn prunes_only_the_copies_it_created() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, _: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, workspace_packages, mut graph, tmp_dir) = create_context();
+        let store = Store::new(&tmp_dir.path().join("data")).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(CopyLinker::new()),
+            HashMap::new(),
+        );
+        let node_modules_path = workspace_packages[0].base_path.join("node_modules");
+
+        writer.write(starting_nodes.clone(), &graph).await.unwrap();
+        fs::create_dir_all(node_modules_path.join("other-tool")).unwrap();
+
+        let scoped_node = graph
+            .node_indices()
+            .find(|nx| graph[*nx].name() == "@scope/p1")
+            .unwrap();
+        let edge = graph.find_edge(starting_nodes[0], scoped_node).unwrap();
+        graph.remove_edge(edge);
+        writer.write(starting_nodes, &graph).await.unwrap();
+
+        assert!(node_modules_path.join("p1").is_dir());
+        assert!(!node_modules_path.join("@scope").exists());
+        assert!(node_modules_path.join("other-tool").is_dir());
+    }
+
+    #[tokio::test]
+    async fn links_packages_with_the_given_linker() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, _: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, workspace_packages, graph, tmp_dir) = create_context();
+        let store = Store::new(&tmp_dir.path().join("data")).unwrap();
+        let node_modules_path = workspace_packages[0].base_path.join("node_modules");
+        let p1_package = match &graph[graph
+            .node_indices()
+            .find(|nx| graph[*nx].name() == "p1")
+            .unwrap()]
+        {
+            Package::NpmPackage(npm_package) => npm_package.clone(),
+            _ => unreachable!(),
+        };
+
+        Writer::new(
+            &store,
+            &downloader,
+            Box::new(CopyLinker::new()),
+            HashMap::new(),
+        )
+        .write(starting_nodes.clone(), &graph)
+        .await
+        .unwrap();
+
+        let scoped_package_path = node_modules_path.join("@scope").join("p1");
+        assert!(!fs::symlink_metadata(&scoped_package_path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(scoped_package_path.join("index.js").is_file());
+        // Its dependencies still resolve from the store
+        assert_eq!(
+            fs::read_link(scoped_package_path.join("node_modules").join("p1")).unwrap(),
+            store.package_code_path_in_store(&p1_package)
+        );
+        assert_eq!(
+            fs::read_link(
+                workspace_packages[1]
+                    .base_path
+                    .join("node_modules")
+                    .join("workspace_package")
+            )
+            .unwrap(),
+            workspace_packages[0].base_path
+        );
+
+        Writer::new(
+            &store,
+            &downloader,
+            Box::new(RelativeSymlinkLinker::new()),
+            HashMap::new(),
+        )
+        .write(starting_nodes.clone(), &graph)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fs::read_link(node_modules_path.join("p1")).unwrap(),
+            relative_path(
+                &node_modules_path,
+                &store.package_code_path_in_store(&p1_package)
+            )
+        );
+        assert!(fs::read_link(&scoped_package_path).unwrap().is_relative());
+        assert!(node_modules_path.join("p1").join("index.js").is_file());
+
+        // Links left by another strategy are replaced
+        Writer::new(
+            &store,
+            &downloader,
+            Box::new(CopyLinker::new()),
+            HashMap::new(),
+        )
+        .write(starting_nodes, &graph)
+        .await
+        .unwrap();
+
+        assert!(!fs::symlink_metadata(node_modules_path.join("p1"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+
+    #[tokio::test]
+    async fn keeps_store_links_resolving_when_moved_with_the_project() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(&self, _: &NpmPackage, path: &Path) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, _, graph, tmp_dir) = create_context();
+        let store = Store::new(&tmp_dir.path().join("data")).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(RelativeSymlinkLinker::new()),
+            HashMap::new(),
+        );
+        let scoped_package = match &graph[graph
+            .node_indices()
+            .find(|nx| graph[*nx].name() == "@scope/p1")
+            .unwrap()]
+        {
+            Package::NpmPackage(npm_package) => npm_package.clone(),
+            _ => unreachable!(),
+        };
+        let store_link = store
+            .package_root_path_in_store(&scoped_package)
+            .join("node_modules")
+            .join("p1");
+
+        writer.write(starting_nodes, &graph).await.unwrap();
+
+        assert!(fs::read_link(&store_link).unwrap().is_relative());
+
+        let moved_dir = TempDir::new("jam-writer-moved").unwrap();
+        let moved_path = moved_dir.path().join("moved");
+        fs::rename(tmp_dir.path(), &moved_path).unwrap();
+
+        let scoped_package_path = fs::canonicalize(
+            moved_path
+                .join("wp1")
+                .join("node_modules")
+                .join("@scope")
+                .join("p1"),
+        )
+        .unwrap();
+        assert!(scoped_package_path
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("p1")
+            .join("index.js")
+            .is_file());
+    }
+
+    #[tokio::test]
+    async fn succeeds_for_scoped_and_non_scoped_packages() {
         struct DummyDownloader {}
 
         #[async_trait]
@@ -311,13 +1660,19 @@ mod tests {
 
         let (starting_nodes, workspace_packages, graph, tmp_dir) = create_context();
         let store = Store::new(tmp_dir.as_ref()).unwrap();
-        let writer = Writer::new(&store, &downloader);
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
 
         let result = writer.write(starting_nodes, &graph).await;
 
         let expected_package_path = tmp_dir
             .path()
             .join("store")
+            .join("v1")
             .join("p1@1.0.0")
             .join("node_modules")
             .join("p1")
@@ -325,17 +1680,19 @@ mod tests {
         let expected_scoped_package_path = tmp_dir
             .path()
             .join("store")
-            .join("@scope_p1@2.0.0")
+            .join("v1")
+            .join("@scope%2Fp1@2.0.0")
             .join("node_modules")
             .join("@scope")
             .join("p1")
             .join("index.js");
 
-        let expected_scoped_package_to_package_link_path = fs::read_link(
-            tmp_dir
+        let expected_scoped_package_to_package_link_path = resolve_link(
+            &tmp_dir
                 .path()
                 .join("store")
-                .join("@scope_p1@2.0.0")
+                .join("v1")
+                .join("@scope%2Fp1@2.0.0")
                 .join("node_modules")
                 .join("p1"),
         )
@@ -461,4 +1818,159 @@ mod tests {
                 .join("ws_script.js")
         );
     }
+
+    #[tokio::test]
+    async fn leaves_no_partial_store_entries_when_downloader_fails() {
+        struct FailingDownloader {}
+
+        #[async_trait]
+        impl Downloader for FailingDownloader {
+            async fn download_to(
+                &self,
+                _package: &NpmPackage,
+                path: &Path,
+            ) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Err(JamError::new(String::from("Failing downloader")))
+            }
+        }
+        let downloader = FailingDownloader {};
+
+        let (starting_nodes, _, graph, tmp_dir) = create_context();
+        let store = Store::new(tmp_dir.as_ref()).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+
+        let result = writer.write(starting_nodes, &graph).await;
+
+        let mut store_entries: Vec<String> = fs::read_dir(tmp_dir.path().join("store").join("v1"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        store_entries.sort();
+
+        assert!(result.is_err());
+        assert_eq!(store_entries, vec![".locks", "files", "index"]);
+    }
+
+    #[tokio::test]
+    async fn repairs_incomplete_store_entries() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(
+                &self,
+                _package: &NpmPackage,
+                path: &Path,
+            ) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "")?;
+
+                Ok(())
+            }
+        }
+        let downloader = DummyDownloader {};
+
+        let (starting_nodes, _, graph, tmp_dir) = create_context();
+        let store = Store::new(tmp_dir.as_ref()).unwrap();
+        let writer = Writer::new(
+            &store,
+            &downloader,
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+
+        // An interrupted install left an empty package directory behind
+        let interrupted_package_path = tmp_dir
+            .path()
+            .join("store")
+            .join("v1")
+            .join("p1@1.0.0")
+            .join("node_modules")
+            .join("p1");
+        fs::create_dir_all(&interrupted_package_path).unwrap();
+
+        let result = writer.write(starting_nodes, &graph).await;
+
+        assert_eq!(result, Ok(()));
+        assert!(interrupted_package_path.join("index.js").exists());
+        assert!(tmp_dir
+            .path()
+            .join("store")
+            .join("v1")
+            .join("p1@1.0.0")
+            .join(".jam-complete")
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn links_removed_store_entries_from_the_file_store() {
+        struct DummyDownloader {}
+
+        #[async_trait]
+        impl Downloader for DummyDownloader {
+            async fn download_to(
+                &self,
+                _package: &NpmPackage,
+                path: &Path,
+            ) -> Result<(), JamError> {
+                fs::write(path.join("index.js"), "const x = 1")?;
+
+                Ok(())
+            }
+        }
+        struct FailingDownloader {}
+
+        #[async_trait]
+        impl Downloader for FailingDownloader {
+            async fn download_to(
+                &self,
+                _package: &NpmPackage,
+                _path: &Path,
+            ) -> Result<(), JamError> {
+                Err(JamError::new(String::from("Failing downloader")))
+            }
+        }
+
+        let (starting_nodes, _, graph, tmp_dir) = create_context();
+        let store = Store::new(tmp_dir.as_ref()).unwrap();
+        Writer::new(
+            &store,
+            &DummyDownloader {},
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        )
+        .write(starting_nodes.clone(), &graph)
+        .await
+        .unwrap();
+
+        let package_path = tmp_dir.path().join("store").join("v1").join("p1@1.0.0");
+        fs::remove_dir_all(&package_path).unwrap();
+
+        let writer = Writer::new(
+            &store,
+            &FailingDownloader {},
+            Box::new(SymlinkLinker::new()),
+            HashMap::new(),
+        );
+        let result = writer.write(starting_nodes, &graph).await;
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            fs::read_to_string(
+                package_path
+                    .join("node_modules")
+                    .join("p1")
+                    .join("index.js")
+            )
+            .unwrap(),
+            "const x = 1"
+        );
+        assert_eq!(writer.link_stats.lock().unwrap().saved_bytes, 11);
+    }
 }
@@ -6,6 +6,7 @@ pub fn with_npm_package_metadata(
     version: &str,
     dependencies: Option<HashMap<String, String>>,
     dist_tags: Option<HashMap<String, String>>,
+    shasum: String,
     tarball_url: String,
 ) -> NpmPackageMetadata {
     NpmPackageMetadata {
@@ -14,7 +15,7 @@ pub fn with_npm_package_metadata(
           version.to_string() => NpmVersionMetadata {
             bin: None,
             dist: NpmDistMetadata {
-              shasum: String::from("some-shasum"),
+              shasum,
               tarball: tarball_url,
             },
             dependencies,
@@ -0,0 +1,143 @@
+mod common;
+
+use common::*;
+use jam::cli_options::{CliOptions, Command, Deploy};
+use jam::errors::JamError;
+use jam::run;
+use jam_test_utils::async_helpers::*;
+use jam_test_utils::common::*;
+use jam_test_utils::npm_mock_server::*;
+use maplit::hashmap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempdir::TempDir;
+
+fn is_copy(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.file_type().is_symlink())
+}
+
+#[tokio::test]
+async fn deploys_the_production_closure_of_a_workspace_package() {
+    let mut npm_mock_server = NpmMockServer::new();
+    let contents = hashmap! {
+        PathBuf::from("packages/p1") => String::from(r#"{
+            "name": "p1",
+            "version": "1.0.0",
+            "dependencies": { "lib": "~1.0.0", "p2": "~1.0.0" },
+            "devDependencies": { "test-lib": "~1.0.0" }
+        }"#),
+        PathBuf::from("packages/p2") => with_package_json_file_content("p2", "1.0.0", Some(hashmap! {
+            "other-lib" => "~1.0.0",
+        })),
+        PathBuf::from("packages/p3") => with_package_json_file_content("p3", "1.0.0", Some(hashmap! {
+            "unrelated-lib" => "~1.0.0",
+        })),
+    };
+
+    for name in &["lib", "other-lib", "test-lib", "unrelated-lib"] {
+        let shasum = npm_mock_server.with_tarball_data(
+            name,
+            hashmap! { "file.js".to_string() => "const x = 1;".to_string() },
+        );
+        let metadata = with_npm_package_metadata(
+            "1.0.4",
+            None,
+            None,
+            shasum,
+            format!("{}/tarball/{}", npm_mock_server.url(), name),
+        );
+
+        npm_mock_server.with_metadata(name, &metadata);
+    }
+
+    given_mono_repo_with(contents, |path| async move {
+        // Outside the mono repo, otherwise the deployed package.json would be a workspace package
+        let deploy_dir = TempDir::new("jam-deploy").unwrap();
+        let deploy_path = deploy_dir.path().join("p1");
+        let deploy = |workspace: &str| {
+            run(
+                path.to_path_buf(),
+                CliOptions {
+                    cache_group: String::from("tests"),
+                    registry: Some(npm_mock_server.url()),
+                    network_concurrency: None,
+                    requests_per_second: None,
+                    command: Command::Deploy(Deploy {
+                        workspace: workspace.to_string(),
+                        dir: deploy_path.clone(),
+                    }),
+                    debug: false,
+                },
+            )
+        };
+        let node_modules_path = deploy_path.join("node_modules");
+
+        assert_eq!(
+            deploy("p4").await,
+            Err(JamError::new(String::from(
+                "No workspace package named 'p4'"
+            )))
+        );
+
+        assert_eq!(deploy("p1").await, Ok(()));
+        assert!(is_copy(&deploy_path.join("package.json")));
+        assert!(is_copy(&node_modules_path.join("lib").join("file.js")));
+        assert!(is_copy(&node_modules_path.join("p2").join("package.json")));
+        assert!(is_copy(
+            &node_modules_path.join("other-lib").join("file.js")
+        ));
+        assert!(!node_modules_path.join("test-lib").exists());
+        assert!(!node_modules_path.join("unrelated-lib").exists());
+        assert!(!node_modules_path.join("p3").exists());
+
+        assert_eq!(
+            deploy("p1").await,
+            Err(JamError::new(format!(
+                "Deploy directory {:?} is not empty",
+                deploy_path
+            )))
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn resolves_relative_deploy_directories() {
+    let npm_mock_server = NpmMockServer::new();
+    let contents = hashmap! {
+        PathBuf::from("packages/p1") => with_package_json_file_content("p1", "1.0.0", None),
+        PathBuf::from("packages/p2") => with_package_json_file_content("p2", "1.0.0", None),
+    };
+
+    given_mono_repo_with(contents, |path| async move {
+        let deploy = |dir: &str| {
+            run(
+                path.to_path_buf(),
+                CliOptions {
+                    cache_group: String::from("tests"),
+                    registry: Some(npm_mock_server.url()),
+                    network_concurrency: None,
+                    requests_per_second: None,
+                    command: Command::Deploy(Deploy {
+                        workspace: String::from("p1"),
+                        dir: PathBuf::from(dir),
+                    }),
+                    debug: false,
+                },
+            )
+        };
+        let root_path = fs::canonicalize(&path).unwrap();
+
+        assert_eq!(
+            deploy("packages/p2/../p1/dist").await,
+            Err(JamError::new(format!(
+                "Can't deploy p1 into its own directory {:?}",
+                root_path.join("packages").join("p1").join("dist")
+            )))
+        );
+
+        assert_eq!(deploy("packages/p1/../../out").await, Ok(()));
+        assert!(is_copy(&root_path.join("out").join("package.json")));
+    })
+    .await;
+}
@@ -8,12 +8,11 @@ use jam_test_utils::async_helpers::*;
 use jam_test_utils::common::*;
 use jam_test_utils::npm_mock_server::*;
 use maplit::hashmap;
+use std::fs;
 use std::path::PathBuf;
 
 fn setup() -> NpmMockServer {
-    let npm_mock_server = NpmMockServer::new();
-
-    npm_mock_server
+    NpmMockServer::new()
 }
 
 #[tokio::test]
@@ -21,8 +20,10 @@ async fn fails_on_missing_manifest_file() {
     given_manifest_file_does_not_exist(|path| async move {
         let options = CliOptions {
             cache_group: String::from("tests"),
-            registry: String::from("http://some/url"),
-            command: Command::Install(Install {}),
+            registry: Some(String::from("http://some/url")),
+            network_concurrency: None,
+            requests_per_second: None,
+            command: Command::Install(Install::default()),
             debug: false,
         };
 
@@ -41,8 +42,10 @@ async fn with_empty_mono_repo() {
     given_valid_manifest_file(|path| async move {
         let options = CliOptions {
             cache_group: String::from("tests"),
-            registry: String::from("http://some/url"),
-            command: Command::Install(Install {}),
+            registry: Some(String::from("http://some/url")),
+            network_concurrency: None,
+            requests_per_second: None,
+            command: Command::Install(Install::default()),
             debug: false,
         };
 
@@ -69,38 +72,42 @@ async fn with_simple_mono_repo() {
         })),
     };
 
+    let lib_shasum = npm_mock_server.with_tarball_data(
+        "lib",
+        hashmap! { "file.js".to_string() => "const x = 1;".to_string() },
+    );
+    let types_lodash_shasum = npm_mock_server.with_tarball_data(
+        "@types/lodash",
+        hashmap! { "index.d.ts".to_string() => "declare const x = 2".to_string() },
+    );
+
     let lib_metadata = with_npm_package_metadata(
         "1.0.4",
         Some(hashmap! {
             "@types/lodash".to_string() => "~4.17.0".to_string()
         }),
         None,
+        lib_shasum,
         format!("{}/tarball/{}", npm_mock_server.url(), "lib"),
     );
     let types_lodash_metadata = with_npm_package_metadata(
         "4.17.21",
         None,
         None,
-        format!("{}/tarball/{}", npm_mock_server.url(), "lodash"),
+        types_lodash_shasum,
+        format!("{}/tarball/{}", npm_mock_server.url(), "%40types%2Flodash"),
     );
 
     npm_mock_server.with_metadata("lib", &lib_metadata);
-    npm_mock_server.with_tarball_data(
-        "lib",
-        hashmap! { "file.js".to_string() => "const x = 1;".to_string() },
-    );
-
     npm_mock_server.with_metadata("@types/lodash", &types_lodash_metadata);
-    npm_mock_server.with_tarball_data(
-        "@types/lodash",
-        hashmap! { "index.d.ts".to_string() => "declare const x = 2".to_string() },
-    );
 
     given_mono_repo_with(contents, |path| async move {
         let options = CliOptions {
             cache_group: String::from("tests"),
-            registry: String::from(npm_mock_server.url()),
-            command: Command::Install(Install {}),
+            registry: Some(npm_mock_server.url()),
+            network_concurrency: None,
+            requests_per_second: None,
+            command: Command::Install(Install::default()),
             debug: false,
         };
 
@@ -123,12 +130,22 @@ async fn with_mono_repo_with_cyclic_dependencies() {
         })),
     };
 
+    let lib_shasum = npm_mock_server.with_tarball_data(
+        "lib",
+        hashmap! { "file.js".to_string() => "const x = 1;".to_string() },
+    );
+    let lodash_shasum = npm_mock_server.with_tarball_data(
+        "lodash",
+        hashmap! { "file.js".to_string() => "const x = 2;".to_string() },
+    );
+
     let lib_metadata = with_npm_package_metadata(
         "1.0.4",
         Some(hashmap! {
             "lodash".to_string() => "~4.17.0".to_string()
         }),
         None,
+        lib_shasum,
         format!("{}/tarball/{}", npm_mock_server.url(), "lib"),
     );
     let lodash_metadata = with_npm_package_metadata(
@@ -137,26 +154,20 @@ async fn with_mono_repo_with_cyclic_dependencies() {
             "lib".to_string() => "^1.0.0".to_string()
         }),
         None,
+        lodash_shasum,
         format!("{}/tarball/{}", npm_mock_server.url(), "lodash"),
     );
 
     npm_mock_server.with_metadata("lib", &lib_metadata);
-    npm_mock_server.with_tarball_data(
-        "lib",
-        hashmap! { "file.js".to_string() => "const x = 1;".to_string() },
-    );
-
     npm_mock_server.with_metadata("lodash", &lodash_metadata);
-    npm_mock_server.with_tarball_data(
-        "lodash",
-        hashmap! { "file.js".to_string() => "const x = 2;".to_string() },
-    );
 
     given_mono_repo_with(contents, |path| async move {
         let options = CliOptions {
             cache_group: String::from("tests"),
-            registry: String::from(npm_mock_server.url()),
-            command: Command::Install(Install {}),
+            registry: Some(npm_mock_server.url()),
+            network_concurrency: Some(1),
+            requests_per_second: None,
+            command: Command::Install(Install::default()),
             debug: false,
         };
 
@@ -167,3 +178,134 @@ async fn with_mono_repo_with_cyclic_dependencies() {
     })
     .await;
 }
+
+#[tokio::test]
+async fn only_relinks_workspaces_whose_manifest_changed() {
+    let mut npm_mock_server = setup();
+    let contents = hashmap! {
+        PathBuf::from("packages/p1") => with_package_json_file_content("p1", "1.0.0", None),
+        PathBuf::from("packages/p2") => with_package_json_file_content("p2", "1.0.0", Some(hashmap! {
+            "lib" => "~1.0.0",
+        })),
+    };
+
+    let lib_shasum = npm_mock_server.with_tarball_data(
+        "lib",
+        hashmap! { "file.js".to_string() => "const x = 1;".to_string() },
+    );
+    let lib_metadata = with_npm_package_metadata(
+        "1.0.4",
+        None,
+        None,
+        lib_shasum,
+        format!("{}/tarball/{}", npm_mock_server.url(), "lib"),
+    );
+
+    npm_mock_server.with_metadata("lib", &lib_metadata);
+
+    given_mono_repo_with(contents, |path| async move {
+        let install = || {
+            run(
+                path.to_path_buf(),
+                CliOptions {
+                    cache_group: String::from("tests"),
+                    registry: Some(npm_mock_server.url()),
+                    network_concurrency: None,
+                    requests_per_second: None,
+                    command: Command::Install(Install::default()),
+                    debug: false,
+                },
+            )
+        };
+        let lib_link_path = path
+            .join("packages")
+            .join("p2")
+            .join("node_modules")
+            .join("lib");
+
+        assert_eq!(install().await, Ok(()));
+        assert!(path.join("node_modules").join(".jam-state.json").is_file());
+
+        fs::remove_file(&lib_link_path).unwrap();
+
+        // Nothing changed
+        assert_eq!(install().await, Ok(()));
+        assert!(fs::symlink_metadata(&lib_link_path).is_err());
+
+        fs::write(
+            path.join("packages").join("p1").join("package.json"),
+            with_package_json_file_content("p1", "1.0.1", None),
+        )
+        .unwrap();
+
+        assert_eq!(install().await, Ok(()));
+        assert!(fs::symlink_metadata(&lib_link_path).is_err());
+
+        fs::write(
+            path.join("packages").join("p2").join("package.json"),
+            with_package_json_file_content("p2", "1.0.1", Some(hashmap! { "lib" => "~1.0.0" })),
+        )
+        .unwrap();
+
+        assert_eq!(install().await, Ok(()));
+        assert!(lib_link_path.join("file.js").is_file());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn prod_installs_skip_dev_dependencies() {
+    let mut npm_mock_server = setup();
+    let contents = hashmap! {
+        PathBuf::from("packages/p1") => String::from(r#"{
+            "name": "p1",
+            "version": "1.0.0",
+            "dependencies": { "lib": "~1.0.0" },
+            "devDependencies": { "test-lib": "~1.0.0" }
+        }"#),
+    };
+
+    for name in &["lib", "test-lib"] {
+        let shasum = npm_mock_server.with_tarball_data(
+            name,
+            hashmap! { "file.js".to_string() => "const x = 1;".to_string() },
+        );
+        let metadata = with_npm_package_metadata(
+            "1.0.4",
+            None,
+            None,
+            shasum,
+            format!("{}/tarball/{}", npm_mock_server.url(), name),
+        );
+
+        npm_mock_server.with_metadata(name, &metadata);
+    }
+
+    given_mono_repo_with(contents, |path| async move {
+        let install = |prod| {
+            run(
+                path.to_path_buf(),
+                CliOptions {
+                    cache_group: String::from("tests"),
+                    registry: Some(npm_mock_server.url()),
+                    network_concurrency: None,
+                    requests_per_second: None,
+                    command: Command::Install(Install { prod, dev: false }),
+                    debug: false,
+                },
+            )
+        };
+        let node_modules_path = path.join("packages").join("p1").join("node_modules");
+
+        assert_eq!(install(true).await, Ok(()));
+        assert!(node_modules_path.join("lib").join("file.js").is_file());
+        assert!(!node_modules_path.join("test-lib").exists());
+
+        assert_eq!(install(false).await, Ok(()));
+        assert!(node_modules_path.join("test-lib").join("file.js").is_file());
+
+        assert_eq!(install(true).await, Ok(()));
+        assert!(!node_modules_path.join("test-lib").exists());
+    })
+    .await;
+}